@@ -6,4 +6,126 @@ fn main() {
   );
 
   println!("cargo:rustc-check-cfg=cfg(gnu_time_bits64)");
+
+  headers::generate();
+}
+
+/// Las cabeceras de C se generan de las declaraciones de Rust en `OUT_DIR`, las de `include/` son
+/// las que se distribuyen y solo se reemplazan con `AGAL_ACTUALIZAR_CABECERAS=1`, un script de
+/// compilacion no debe cambiar el codigo fuente
+mod headers {
+  use cbindgen::{Builder, Config, DocumentationStyle, EnumConfig, ExportConfig, Language};
+  use cbindgen::{LineEndingStyle, RenameRule, Style};
+
+  const AUTOGEN_WARNING: &str =
+    "/* Generado con cbindgen por build.rs a partir de src/, no se edita a mano */";
+  const UPDATE: &str = "AGAL_ACTUALIZAR_CABECERAS";
+
+  fn config(
+    header: &str,
+    guard: &str,
+    includes: &[&str],
+    opaque: &str,
+    rename: &[(&str, &str)],
+  ) -> Config {
+    Config {
+      language: Language::C,
+      header: Some(header.to_string()),
+      autogen_warning: Some(AUTOGEN_WARNING.to_string()),
+      include_guard: Some(guard.to_string()),
+      cpp_compat: true,
+      no_includes: true,
+      sys_includes: ["stdbool.h", "stddef.h", "stdint.h"]
+        .map(String::from)
+        .to_vec(),
+      includes: includes.iter().map(|include| include.to_string()).collect(),
+      // Los tipos que se usan pero no se declaran en el archivo que se lee
+      after_includes: Some(opaque.to_string()),
+      usize_is_size_t: true,
+      style: Style::Type,
+      documentation_style: DocumentationStyle::Doxy,
+      line_endings: LineEndingStyle::LF,
+      enumeration: EnumConfig {
+        rename_variants: RenameRule::ScreamingSnakeCase,
+        prefix_with_name: true,
+        ..Default::default()
+      },
+      export: ExportConfig {
+        rename: rename
+          .iter()
+          .map(|(name, rename)| (name.to_string(), rename.to_string()))
+          .collect(),
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  fn write(source: &str, header: &str, config: Config) {
+    println!("cargo:rerun-if-changed={source}");
+    let crate_dir = env!("CARGO_MANIFEST_DIR");
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    match Builder::new()
+      .with_src(format!("{crate_dir}/{source}"))
+      .with_config(config)
+      .generate()
+    {
+      Ok(bindings) => {
+        bindings.write_to_file(format!("{out_dir}/{header}"));
+        if std::env::var_os(UPDATE).is_some() {
+          bindings.write_to_file(format!("{crate_dir}/include/{header}"));
+        }
+      }
+      Err(error) => println!("cargo:warning=No se pudo generar {header}: {error}"),
+    }
+  }
+
+  pub fn generate() {
+    println!("cargo:rerun-if-env-changed={UPDATE}");
+    let host = config(
+      "/*
+ * Interfaz de C del interprete de Agal, corresponde a src/ffi.rs.
+ *
+ * Se enlaza con la biblioteca que genera `cargo build --release`:
+ *   target/release/libagalang_core.so (o .dylib, o agalang_core.dll)
+ *
+ * Los valores (`AgalValue *`) que retornan estas funciones pertenecen a quien los recibe y se
+ * liberan con `agal_value_free`, los textos con `agal_string_free`.
+ */",
+      "AGAL_H",
+      &[],
+      "\ntypedef struct AgalValue AgalValue;",
+      &[
+        ("Vm", "AgalVM"),
+        ("Value", "AgalValue"),
+        ("Type", "AgalType"),
+        ("Callback", "AgalCallback"),
+      ],
+    );
+    write("src/ffi.rs", "agal.h", host);
+
+    let extension = config(
+      "/*
+ * Interfaz de las extensiones nativas de Agal, corresponde a src/ffi/extension.rs.
+ *
+ * La extension es una biblioteca compartida que define `agal_registrar` y se carga con
+ * `importa ':nativo/ruta/libx.so' como x;`. No se enlaza con el interprete, todo lo que usa
+ * llega en `AgalApi`. Hay un ejemplo en tests/c/extension.c.
+ */",
+      "AGAL_EXTENSION_H",
+      &["agal.h"],
+      "",
+      &[
+        ("Type", "AgalType"),
+        ("Handle", "AgalHandle"),
+        ("Api", "AgalApi"),
+        ("ExtensionFn", "AgalExtensionFn"),
+        ("ExtensionFunction", "AgalExtensionFunction"),
+        ("Module", "AgalModule"),
+        ("RegisterFn", "AgalRegisterFn"),
+        ("ABI_VERSION", "AGAL_ABI_VERSION"),
+      ],
+    );
+    write("src/ffi/extension.rs", "agal_extension.h", extension);
+  }
 }
@@ -0,0 +1,170 @@
+/*
+ * Interfaz de C del interprete de Agal, corresponde a src/ffi.rs.
+ *
+ * Se enlaza con la biblioteca que genera `cargo build --release`:
+ *   target/release/libagalang_core.so (o .dylib, o agalang_core.dll)
+ *
+ * Los valores (`AgalValue *`) que retornan estas funciones pertenecen a quien los recibe y se
+ * liberan con `agal_value_free`, los textos con `agal_string_free`.
+ */
+
+#ifndef AGAL_H
+#define AGAL_H
+
+/* Generado con cbindgen por build.rs a partir de src/, no se edita a mano */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct AgalValue AgalValue;
+
+/**
+ * Lo que retorna `agal_value_type` y `type_of` de las extensiones
+ */
+enum AgalType
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  AGAL_TYPE_NULL = 0,
+  AGAL_TYPE_BOOLEAN = 1,
+  AGAL_TYPE_NUMBER = 2,
+  AGAL_TYPE_STRING = 3,
+  AGAL_TYPE_LIST = 4,
+  AGAL_TYPE_OTHER = 5,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum AgalType AgalType;
+#else
+typedef uint32_t AgalType;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct AgalVM AgalVM;
+
+/**
+ * Funcion del programa anfitrion. Recibe los datos con los que se registro y los argumentos,
+ * que solo son validos durante la llamada. Retorna un valor nuevo, uno de los argumentos o nulo
+ * para `nada`. Para lanzar un error se llama a `agal_throw` antes de retornar
+ */
+typedef AgalValue *(*AgalCallback)(void *data, AgalValue *const *args, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Crea un interprete con las opciones por defecto
+ */
+AgalVM *agal_vm_new(void);
+
+void agal_vm_free(AgalVM *vm);
+
+/**
+ * El mensaje del ultimo error, o nulo si la ultima operacion termino bien. Es valido hasta la
+ * siguiente operacion con el interprete
+ */
+const char *agal_vm_error(const AgalVM *vm);
+
+/**
+ * Compila y ejecuta el codigo hasta que termine, `path` se usa para los errores y las
+ * importaciones relativas
+ */
+bool agal_vm_eval(AgalVM *vm, const char *source, const char *path);
+
+/**
+ * Ejecuta el contenido de un archivo `.agab` hasta que termine
+ */
+bool agal_vm_eval_bytecode(AgalVM *vm, const uint8_t *bytes, size_t len);
+
+/**
+ * Agrega `func` al modulo que se importa como `importa ':modulo' como M;`, con `data` como
+ * primer argumento en cada llamada. Se registra antes de `agal_vm_eval`
+ */
+bool agal_vm_register(AgalVM *vm,
+                      const char *module,
+                      const char *name,
+                      AgalCallback func,
+                      void *data);
+
+/**
+ * Llama a una funcion que exporta el programa, retorna nulo si falla
+ */
+AgalValue *agal_vm_call(AgalVM *vm, const char *name, const AgalValue *const *args, size_t len);
+
+/**
+ * Lo que exporta el programa con ese nombre, o nulo si no existe
+ */
+AgalValue *agal_vm_get_export(AgalVM *vm, const char *name);
+
+AgalValue *agal_vm_get_global(AgalVM *vm, const char *name);
+
+/**
+ * Declara la variable global o cambia su valor, el valor se copia
+ */
+bool agal_vm_set_global(AgalVM *vm, const char *name, const AgalValue *value);
+
+/**
+ * Desde una funcion registrada, hace que la llamada lance el mensaje
+ */
+void agal_throw(const char *message);
+
+AgalValue *agal_value_null(void);
+
+AgalValue *agal_value_bool(bool value);
+
+/**
+ * Los numeros se pasan como texto para no perder precision, como `"3.5"` o `"-12"`. Retorna
+ * nulo si el texto no es un numero
+ */
+AgalValue *agal_value_number(const char *text);
+
+AgalValue *agal_value_string(const char *text);
+
+AgalValue *agal_value_list(void);
+
+/**
+ * Agrega una copia del valor al final de la lista
+ */
+bool agal_list_push(AgalValue *list, const AgalValue *value);
+
+size_t agal_list_len(const AgalValue *list);
+
+/**
+ * Una copia del elemento, o nulo si esta fuera de la lista
+ */
+AgalValue *agal_list_get(const AgalValue *list, size_t index);
+
+AgalType agal_value_type(const AgalValue *value);
+
+/**
+ * Retorna falso si el valor no es un buleano
+ */
+bool agal_value_as_bool(const AgalValue *value, bool *out);
+
+/**
+ * El numero como texto, o nulo si el valor no es un numero. Se libera con `agal_string_free`
+ */
+char *agal_value_as_number(const AgalValue *value);
+
+/**
+ * El texto, o nulo si el valor no es una cadena. Se libera con `agal_string_free`
+ */
+char *agal_value_as_string(const AgalValue *value);
+
+/**
+ * Una copia del valor, las listas y objetos se comparten con el original
+ */
+AgalValue *agal_value_clone(const AgalValue *value);
+
+void agal_value_free(AgalValue *value);
+
+void agal_string_free(char *text);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AGAL_H */
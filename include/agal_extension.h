@@ -0,0 +1,92 @@
+/*
+ * Interfaz de las extensiones nativas de Agal, corresponde a src/ffi/extension.rs.
+ *
+ * La extension es una biblioteca compartida que define `agal_registrar` y se carga con
+ * `importa ':nativo/ruta/libx.so' como x;`. No se enlaza con el interprete, todo lo que usa
+ * llega en `AgalApi`. Hay un ejemplo en tests/c/extension.c.
+ */
+
+#ifndef AGAL_EXTENSION_H
+#define AGAL_EXTENSION_H
+
+/* Generado con cbindgen por build.rs a partir de src/, no se edita a mano */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include "agal.h"
+
+
+/**
+ * Cambia cuando cambia la forma de `Api`, `Module` o `ExtensionFunction`
+ */
+#define AGAL_ABI_VERSION 1
+
+/**
+ * Un valor del interprete. Las extensiones solo lo usan por medio de `Api` y solo es valido
+ * hasta que termina la llamada que lo recibio o lo creo
+ */
+typedef struct AgalHandle AgalHandle;
+
+/**
+ * Funciones del interprete para crear y leer valores. Las funciones `as_*` retornan falso si el
+ * valor no es de ese tipo, `error` hace que la llamada lance el mensaje
+ */
+typedef struct {
+  uint32_t version;
+  AgalHandle *(*null)(void);
+  AgalHandle *(*boolean)(bool value);
+  AgalHandle *(*number)(double value);
+  AgalHandle *(*string)(const uint8_t *bytes, size_t len);
+  AgalHandle *(*list)(void);
+  bool (*list_push)(AgalHandle *list, AgalHandle *value);
+  size_t (*list_len)(AgalHandle *list);
+  AgalHandle *(*list_get)(AgalHandle *list, size_t index);
+  AgalType (*type_of)(AgalHandle *value);
+  bool (*as_boolean)(AgalHandle *value, bool *out);
+  bool (*as_number)(AgalHandle *value, double *out);
+  /**
+   * Los bytes no terminan en nulo y son validos mientras lo sea el valor
+   */
+  bool (*as_string)(AgalHandle *value, const uint8_t **bytes, size_t *len);
+  AgalHandle *(*error)(const uint8_t *bytes, size_t len);
+} AgalApi;
+
+/**
+ * Funcion de una extension, recibe los argumentos y retorna un valor creado con `api` o uno de
+ * los argumentos. Un puntero nulo es `nada`
+ */
+typedef AgalHandle *(*AgalExtensionFn)(const AgalApi *api, AgalHandle *const *args, size_t len);
+
+typedef struct {
+  /**
+   * Cadena terminada en nulo con el nombre que se exporta
+   */
+  const char *name;
+  AgalExtensionFn func;
+} AgalExtensionFunction;
+
+/**
+ * Lo que retorna `agal_registrar`, debe vivir mientras la biblioteca este cargada
+ */
+typedef struct {
+  uint32_t version;
+  const AgalExtensionFunction *functions;
+  size_t len;
+} AgalModule;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * La funcion que exporta la extension, el interprete la busca por su nombre al cargarla.
+ * Retorna nulo si no puede usar la version que recibe
+ */
+extern const AgalModule *agal_registrar(uint32_t version);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AGAL_EXTENSION_H */
@@ -2,34 +2,36 @@ use std::path::{Path, PathBuf};
 
 use crate::util::{OnError, OnSome};
 
+/// Etiquetas de las estructuras en el binario.
+/// Los valores son fijos para que el binario no dependa del orden de las variantes
 #[derive(PartialEq, Eq)]
 pub(crate) enum StructTag {
-  EndOfBlock,
-  Byte,
-  Compile,
-  USize,
-
-  String,
-  Number,
-  Bool,
-  Function,
-  Char,
-  Null,
-  Never,
-  Map,
-  Array,
-  Class,
-  Lazy,
-
-  ChunkGroup,
-  Chunk,
-  Values,
-  Code,
-  Lines,
-  Location,
-  Position,
-
-  None,
+  EndOfBlock = 0,
+  Byte = 1,
+  Compile = 2,
+  USize = 3,
+
+  String = 4,
+  Number = 5,
+  Bool = 6,
+  Function = 7,
+  Char = 8,
+  Null = 9,
+  Never = 10,
+  Map = 11,
+  Array = 12,
+  Class = 13,
+  Lazy = 14,
+
+  ChunkGroup = 15,
+  Chunk = 16,
+  Values = 17,
+  Code = 18,
+  Lines = 19,
+  Location = 20,
+  Position = 21,
+
+  None = 0xFF,
 }
 impl From<u8> for StructTag {
   fn from(value: u8) -> Self {
@@ -49,6 +51,7 @@ impl From<u8> for StructTag {
       x if x == StructTag::Map as u8 => StructTag::Map,
       x if x == StructTag::Array as u8 => StructTag::Array,
       x if x == StructTag::Class as u8 => StructTag::Class,
+      x if x == StructTag::Lazy as u8 => StructTag::Lazy,
 
       x if x == StructTag::ChunkGroup as u8 => StructTag::ChunkGroup,
       x if x == StructTag::Chunk as u8 => StructTag::Chunk,
@@ -62,7 +65,7 @@ impl From<u8> for StructTag {
     }
   }
 }
-pub(crate) trait Encode {
+pub trait Encode {
   fn encode(&self) -> Result<Vec<u8>, String>;
 }
 
@@ -240,3 +243,129 @@ impl Decode for Box<Path> {
     Ok(s.into_boxed_path())
   }
 }
+
+/// Identifica a los archivos de bytecode de Agal
+const MAGIC: [u8; 4] = *b"AGAB";
+/// Cambia cada vez que cambian los codigos de operacion o las etiquetas del binario
+pub(crate) const FORMAT_VERSION: u16 = 3;
+const INTERPRETER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = u32::MAX;
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      let mask = (crc & 1).wrapping_neg();
+      crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+    }
+  }
+  !crc
+}
+
+/// Cabecera de los archivos de bytecode, va antes del compilador
+pub(crate) struct Header {
+  format: u16,
+  interpreter: String,
+  checksum: u32,
+}
+impl Header {
+  pub fn new(payload: &[u8]) -> Self {
+    Self {
+      format: FORMAT_VERSION,
+      interpreter: INTERPRETER_VERSION.to_string(),
+      checksum: crc32(payload),
+    }
+  }
+  /// Comprueba que el contenido se pueda leer con esta version del interprete
+  pub fn check(&self, payload: &[u8]) -> Result<(), String> {
+    if self.format != FORMAT_VERSION {
+      return Err(format!(
+        "El binario usa la version {} del formato (interprete {}) pero este interprete usa la version {FORMAT_VERSION} ({INTERPRETER_VERSION}), vuelve a compilar el archivo",
+        self.format, self.interpreter
+      ));
+    }
+    if self.checksum != crc32(payload) {
+      return Err("Binario corrupto: la suma de verificacion no coincide".to_string());
+    }
+    Ok(())
+  }
+}
+impl Encode for Header {
+  fn encode(&self) -> Result<Vec<u8>, String> {
+    let mut encode = MAGIC.to_vec();
+    encode.extend(self.format.to_le_bytes());
+    encode.extend(self.interpreter.encode()?);
+    encode.extend(self.checksum.to_le_bytes());
+    Ok(encode)
+  }
+}
+impl Decode for Header {
+  fn decode(vec: &mut std::collections::VecDeque<u8>) -> Result<Self, String> {
+    fn take<const N: usize>(vec: &mut std::collections::VecDeque<u8>) -> Result<[u8; N], String> {
+      let mut bytes = [0u8; N];
+      for byte in bytes.iter_mut() {
+        *byte = vec
+          .pop_front()
+          .on_error(|_| "Binario corrupto: cabecera incompleta".to_string())?;
+      }
+      Ok(bytes)
+    }
+    if take::<4>(vec)? != MAGIC {
+      return Err(
+        "El archivo no es un binario de Agal o se compilo sin cabecera, vuelve a compilarlo"
+          .to_string(),
+      );
+    }
+    Ok(Self {
+      format: u16::from_le_bytes(take(vec)?),
+      interpreter: String::decode(vec)?,
+      checksum: u32::from_le_bytes(take(vec)?),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{compile_bytecode, compile_source};
+
+  fn bytecode() -> Vec<u8> {
+    let code = "def lista = [1, 'dos'];\nfn suma(a, b) { ret a + b; }\nsuma(lista[0], 2);";
+    compile_source(code, Path::new("prueba.aga"))
+      .unwrap()
+      .encode()
+      .unwrap()
+  }
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let bytes = bytecode();
+    assert_eq!(bytes[..4], MAGIC);
+    assert_eq!(bytes[4..6], FORMAT_VERSION.to_le_bytes());
+    let compiler = compile_bytecode(bytes.clone()).unwrap();
+    assert_eq!(compiler.encode().unwrap(), bytes);
+  }
+
+  #[test]
+  fn test_rejects_mismatches() {
+    let error = |change: fn(&mut Vec<u8>)| {
+      let mut bytes = bytecode();
+      change(&mut bytes);
+      compile_bytecode(bytes).err().unwrap()
+    };
+    assert!(error(|bytes| bytes[0] = b'X').contains("no es un binario de Agal"));
+    assert!(error(|bytes| bytes.truncate(5)).contains("cabecera incompleta"));
+    assert!(
+      error(|bytes| bytes[4] = bytes[4].wrapping_add(1)).contains(&format!(
+        "pero este interprete usa la version {FORMAT_VERSION}"
+      ))
+    );
+    assert!(error(|bytes| *bytes.last_mut().unwrap() ^= 1).contains("suma de verificacion"));
+  }
+}
@@ -1,66 +1,100 @@
-use crate::{compiler::ValueArray, Decode, Encode, MultiRefHash, StructTag};
+use crate::{compiler::ValueArray, util::Position, Decode, Encode, MultiRefHash, StructTag};
 
+/// Los valores son fijos porque forman parte del formato del binario
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
   // Const
-  Constant,
+  Constant = 0,
   // Math
-  Add,
-  Subtract,
-  Multiply,
-  Divide,
-  Negate,
-  Modulo,
-  Exponential,
-  Nullish,
+  Add = 1,
+  Subtract = 2,
+  Multiply = 3,
+  Divide = 4,
+  Negate = 5,
+  Modulo = 6,
+  Exponential = 7,
+  Nullish = 8,
   // Expr
-  Not,
-  Approximate,
-  At,
-  AsRef,
-  ToBoolean,
-  ToString,
-  Call,
-  ArgDecl,
-  GetMember,
-  SetMember,
+  Not = 9,
+  Approximate = 10,
+  At = 11,
+  AsRef = 12,
+  ToBoolean = 13,
+  ToString = 14,
+  Call = 15,
+  ArgDecl = 16,
+  GetMember = 17,
+  SetMember = 18,
   // Binary
-  And,
-  Or,
-  GreaterThan,
-  LessThan,
-  Equals,
+  And = 19,
+  Or = 20,
+  GreaterThan = 21,
+  LessThan = 22,
+  Equals = 23,
   // Statement
-  ConsoleOut,
-  VarDecl,
-  ConstDecl,
-  DelVar,
-  GetVar,
-  SetVar,
-  Loop,
-  Import,
-  Export,
-  ExtendClass,
-  Throw,
-  Try,
+  ConsoleOut = 24,
+  VarDecl = 25,
+  ConstDecl = 26,
+  DelVar = 27,
+  GetVar = 28,
+  SetVar = 29,
+  DeclLocal = 30,
+  GetLocal = 31,
+  SetLocal = 32,
+  GetUpvalue = 33,
+  SetUpvalue = 34,
+  Loop = 35,
+  Import = 36,
+  Export = 37,
+  ExtendClass = 38,
+  Throw = 39,
+  Try = 40,
   // Control
-  Pop,
-  Await,
-  UnPromise, // obtiene el valor de una promesa
-  Promised,  // mueve el frame a los asincronos
-  NewLocals,
-  RemoveLocals,
-  JumpIfFalse,
-  Jump,
-  Return,
-  Break,
-  Continue,
-  Copy,        // Para duplicar el ultimo valor en el stack (obtener el padre de un objeto)
-  SetScope,    // Agrega el scope actual a el ultimo valor de la pila (para funciones)
-  InClass,     // Determina que el scope actual es una clase (metodos de clase)
-  GetInstance, // Para agregar las propiedades de inctancia al declarar la clase
+  Pop = 41,
+  Await = 42,
+  UnPromise = 43, // obtiene el valor de una promesa
+  Promised = 44,  // mueve el frame a los asincronos
+  NewLocals = 45,
+  RemoveLocals = 46,
+  JumpIfFalse = 47,
+  Jump = 48,
+  Return = 49,
+  Break = 50,
+  Continue = 51,
+  Copy = 52, // Para duplicar el ultimo valor en el stack (obtener el padre de un objeto)
+  SetScope = 53, // Agrega el scope actual a el ultimo valor de la pila (para funciones)
+  InClass = 54, // Determina que el scope actual es una clase (metodos de clase)
+  GetInstance = 55, // Para agregar las propiedades de inctancia al declarar la clase
+  TailCall = 56, // Llamada en posicion de cola, reutiliza el marco actual
   // Invalid
-  Null,
+  Null = 0xFF,
+}
+impl OpCode {
+  /// Cantidad de bytes que lee la instruccion despues de su codigo
+  pub fn operand_len(&self) -> usize {
+    match self {
+      Self::Jump | Self::JumpIfFalse | Self::Loop | Self::Import => 2,
+      Self::Constant
+      | Self::GetVar
+      | Self::SetVar
+      | Self::VarDecl
+      | Self::ConstDecl
+      | Self::DelVar
+      | Self::Export
+      | Self::ArgDecl
+      | Self::DeclLocal
+      | Self::GetLocal
+      | Self::SetLocal
+      | Self::GetUpvalue
+      | Self::SetUpvalue
+      | Self::RemoveLocals
+      | Self::Call
+      | Self::TailCall
+      | Self::GetMember
+      | Self::SetMember => 1,
+      _ => 0,
+    }
+  }
 }
 impl From<&u8> for OpCode {
   fn from(value: &u8) -> Self {
@@ -75,6 +109,7 @@ impl From<u8> for OpCode {
       x if x == Self::SetMember as u8 => Self::SetMember,
       x if x == Self::Constant as u8 => Self::Constant,
       x if x == Self::Call as u8 => Self::Call,
+      x if x == Self::TailCall as u8 => Self::TailCall,
       x if x == Self::Add as u8 => Self::Add,
       x if x == Self::ArgDecl as u8 => Self::ArgDecl,
       x if x == Self::Subtract as u8 => Self::Subtract,
@@ -92,6 +127,11 @@ impl From<u8> for OpCode {
       x if x == Self::ConsoleOut as u8 => Self::ConsoleOut,
       x if x == Self::GetVar as u8 => Self::GetVar,
       x if x == Self::SetVar as u8 => Self::SetVar,
+      x if x == Self::DeclLocal as u8 => Self::DeclLocal,
+      x if x == Self::GetLocal as u8 => Self::GetLocal,
+      x if x == Self::SetLocal as u8 => Self::SetLocal,
+      x if x == Self::GetUpvalue as u8 => Self::GetUpvalue,
+      x if x == Self::SetUpvalue as u8 => Self::SetUpvalue,
       x if x == Self::VarDecl as u8 => Self::VarDecl,
       x if x == Self::ConstDecl as u8 => Self::ConstDecl,
       x if x == Self::Pop as u8 => Self::Pop,
@@ -128,7 +168,8 @@ impl From<u8> for OpCode {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Chunk {
   pub code: Vec<u8>,
-  pub lines: Vec<usize>,
+  /// Linea y columna del codigo fuente de cada byte
+  pub positions: Vec<Position>,
   pub constants: super::ValueArray,
 }
 
@@ -136,7 +177,7 @@ impl Chunk {
   pub fn new() -> Self {
     Self {
       code: Vec::new(),
-      lines: Vec::new(),
+      positions: Vec::new(),
       constants: super::ValueArray::new(),
     }
   }
@@ -146,35 +187,35 @@ impl Chunk {
   fn overwrite(&mut self, index: usize, byte: u8) {
     self.code[index] = byte;
   }
-  pub fn write(&mut self, byte: u8, line: usize) {
+  pub fn write(&mut self, byte: u8, position: Position) {
     self.code.push(byte);
-    self.lines.push(line);
+    self.positions.push(position);
   }
-  pub fn write_buffer(&mut self, bytes: Vec<u8>, line: usize) {
+  pub fn write_buffer(&mut self, bytes: Vec<u8>, position: Position) {
     for byte in bytes {
-      self.write(byte, line);
+      self.write(byte, position);
     }
   }
-  fn add_constant(&mut self, value: super::Value) -> u8 {
+  pub(super) fn add_constant(&mut self, value: super::Value) -> u8 {
     if self.constants.has_value(&value) {
       return self.constants.get_index(&value).unwrap_or(0);
     }
     self.constants.write(value);
     self.constants.len() - 1
   }
-  pub fn add_loop(&mut self, loop_start: usize) -> Result<(), String> {
-    self.write(OpCode::Loop as u8, self.code.len());
+  pub fn add_loop(&mut self, loop_start: usize, position: Position) -> Result<(), String> {
+    self.write(OpCode::Loop as u8, position);
 
     let offset = self.code.len() - loop_start + 2;
     if offset > u16::MAX.into() {
       Err("Longitud muy alta".to_string())?
     }
-    self.write(((offset >> 8) & 0xff) as u8, self.code.len());
-    self.write((offset & 0xff) as u8, self.code.len());
+    self.write(((offset >> 8) & 0xff) as u8, position);
+    self.write((offset & 0xff) as u8, position);
     Ok(())
   }
-  pub fn jump(&mut self, code: OpCode) -> usize {
-    self.write_buffer(vec![code as u8, 0xFF, 0xFF], self.code.len());
+  pub fn jump(&mut self, code: OpCode, position: Position) -> usize {
+    self.write_buffer(vec![code as u8, 0xFF, 0xFF], position);
     self.code.len() - 2
   }
   pub fn patch_jump(&mut self, offset: usize) -> Result<(), String> {
@@ -216,7 +257,6 @@ impl Chunk {
         | OpCode::GetVar
         | OpCode::ConstDecl
         | OpCode::VarDecl
-        | OpCode::ArgDecl
         | OpCode::Export => {
           let index = self.read(offset);
           offset += 1;
@@ -236,6 +276,21 @@ impl Chunk {
             "-------------------------".to_string(),
           )
         }
+        OpCode::ArgDecl
+        | OpCode::DeclLocal
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::RemoveLocals => {
+          let index = self.read(offset);
+          offset += 1;
+          (
+            "----".to_string(),
+            format!("{index:02x}"),
+            "-------------------------".to_string(),
+          )
+        }
         OpCode::Call | OpCode::SetMember | OpCode::GetMember => {
           offset += 1;
           (
@@ -286,8 +341,8 @@ impl Encode for Chunk {
     };
     {
       encode.push(StructTag::Lines as u8);
-      for line in &self.lines {
-        encode.extend(line.encode()?);
+      for position in &self.positions {
+        encode.extend(position.encode()?);
       }
       encode.push(StructTag::EndOfBlock as u8);
     };
@@ -325,6 +380,9 @@ impl Decode for Chunk {
           vec.pop_front(); // EOB
           break;
         }
+        if constants.len() == u8::MAX {
+          return Err("Binario corrupto: demasiadas constantes en un fragmento".to_string());
+        }
         constants.write(super::Value::decode(vec)?);
       }
       constants
@@ -359,7 +417,7 @@ impl Decode for Chunk {
       }
       code
     };
-    let lines = {
+    let positions = {
       vec
         .pop_front()
         .on_some_option(|byte| {
@@ -370,30 +428,41 @@ impl Decode for Chunk {
           }
         })
         .on_error(|_| "Se esperaban lineas de un fragmento".to_string())?;
-      let mut lines = vec![];
+      let mut positions = vec![];
       loop {
         let byte = vec.front().on_error(|_| "Binario corrupto".to_string())?;
         if *byte == StructTag::EndOfBlock as u8 {
           vec.pop_front(); // EOB
           break;
         }
-        lines.push(usize::decode(vec)?);
+        positions.push(Position::decode(vec)?);
       }
-      lines
+      positions
     };
     Ok(Self {
       code,
-      lines,
+      positions,
       constants,
     })
   }
 }
 
+/// Nombre de una ranura local entre las instrucciones `start` y `end`, para el depurador.
+/// No se guarda en el bytecode
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalName {
+  pub slot: u8,
+  pub name: String,
+  start: usize,
+  end: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ChunkGroup {
   chunks: Vec<Chunk>,
   aggregate_len: Vec<usize>,
   current: usize,
+  locals: Vec<LocalName>,
 }
 impl ChunkGroup {
   pub fn new_ref() -> MultiRefHash<Self> {
@@ -401,6 +470,7 @@ impl ChunkGroup {
       chunks: vec![Chunk::new()],
       aggregate_len: vec![0],
       current: 0,
+      locals: vec![],
     }
     .into()
   }
@@ -426,7 +496,10 @@ impl ChunkGroup {
   pub fn len(&self) -> usize {
     self.aggregate_len[self.current]
   }
-  pub fn get_line(&self, index: usize) -> usize {
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+  pub fn get_position(&self, index: usize) -> Position {
     let resolved_index = self.resolve_index(index);
     let base = if resolved_index == 0 {
       0
@@ -434,11 +507,45 @@ impl ChunkGroup {
       self.aggregate_len[resolved_index - 1]
     };
     let local_index = index - base;
-    *self
+    self
       .chunks
       .get(resolved_index)
-      .and_then(|chunk| chunk.lines.get(local_index))
-      .unwrap_or(&0)
+      .and_then(|chunk| chunk.positions.get(local_index))
+      .copied()
+      .unwrap_or_default()
+  }
+
+  pub fn get_line(&self, index: usize) -> usize {
+    self.get_position(index).line
+  }
+  /// La ranura tiene ese nombre desde la siguiente instruccion
+  pub fn name_local(&mut self, slot: u8, name: &str) {
+    self.locals.push(LocalName {
+      slot,
+      name: name.to_string(),
+      start: self.len(),
+      end: usize::MAX,
+    });
+  }
+  /// Al cerrar un bloque sus ranuras, desde `alive`, dejan de tener nombre
+  pub fn end_locals(&mut self, alive: u8) {
+    let end = self.len();
+    for local in &mut self.locals {
+      if local.slot >= alive && local.end == usize::MAX {
+        local.end = end;
+      }
+    }
+  }
+  /// Las ranuras con nombre en la instruccion `index`, si una ranura se reutilizo gana la ultima
+  pub fn locals_at(&self, index: usize) -> Vec<&LocalName> {
+    let mut locals: Vec<&LocalName> = vec![];
+    for local in &self.locals {
+      if local.start <= index && index < local.end {
+        locals.retain(|other| other.slot != local.slot);
+        locals.push(local);
+      }
+    }
+    locals
   }
 
   fn prev_aggregate_len(&self) -> usize {
@@ -452,7 +559,7 @@ impl ChunkGroup {
   pub fn read_constant(&self, index: u8) -> &super::Value {
     self.current_chunk().constants.get(index)
   }
-  pub fn read_var(&mut self, name: String, line: usize) -> u8 {
+  pub fn read_var(&mut self, name: String, position: Position) -> u8 {
     if self.current_chunk_mut().constants.len() == u8::MAX {
       self.current += 1;
       self.chunks.push(Chunk::new());
@@ -461,20 +568,19 @@ impl ChunkGroup {
     let index = self
       .current_chunk_mut()
       .add_constant(super::Value::String(name));
-    self.write_buffer(vec![OpCode::GetVar as u8, index], line);
+    self.write_buffer(vec![OpCode::GetVar as u8, index], position);
     index
   }
-  pub fn make_arg(&mut self, name: String, line: usize) -> u8 {
-    if self.current_chunk_mut().constants.len() == u8::MAX {
-      self.current += 1;
-      self.chunks.push(Chunk::new());
-      self.aggregate_len.push(self.prev_aggregate_len());
+  /// Convierte la llamada que empieza en el byte indicado en una llamada en posicion de cola
+  pub fn make_tail_call(&mut self, index: usize) {
+    let index = index - self.prev_aggregate_len();
+    let chunk = self.current_chunk_mut();
+    if chunk.read(index) == OpCode::Call as u8 {
+      chunk.overwrite(index, OpCode::TailCall as u8);
     }
-    let index = self
-      .current_chunk_mut()
-      .add_constant(super::Value::String(name));
-    self.write_buffer(vec![OpCode::ArgDecl as u8, index], line);
-    index
+  }
+  pub fn make_arg(&mut self, slot: u8, position: Position) {
+    self.write_buffer(vec![OpCode::ArgDecl as u8, slot], position);
   }
   pub fn add_value(&mut self, value: super::Value) -> u8 {
     if self.current_chunk_mut().constants.has_value(&value) {
@@ -492,13 +598,13 @@ impl ChunkGroup {
       self.current_chunk_mut().add_constant(value)
     }
   }
-  pub fn write_constant(&mut self, value: super::Value, line: usize) -> u8 {
+  pub fn write_constant(&mut self, value: super::Value, position: Position) -> u8 {
     let index = self.add_value(value);
-    self.write_buffer(vec![OpCode::Constant as u8, index], line);
+    self.write_buffer(vec![OpCode::Constant as u8, index], position);
     index
   }
-  pub fn write_buffer(&mut self, bytes: Vec<u8>, line: usize) {
-    self.current_chunk_mut().write_buffer(bytes, line);
+  pub fn write_buffer(&mut self, bytes: Vec<u8>, position: Position) {
+    self.current_chunk_mut().write_buffer(bytes, position);
     self.update_aggregate_len();
   }
 
@@ -512,12 +618,12 @@ impl ChunkGroup {
     let local_index = index - base;
     self.chunks[resolved_index].read(local_index)
   }
-  pub fn write(&mut self, byte: u8, line: usize) {
-    self.current_chunk_mut().write(byte, line);
+  pub fn write(&mut self, byte: u8, position: Position) {
+    self.current_chunk_mut().write(byte, position);
     self.update_aggregate_len();
   }
-  pub fn jump(&mut self, code: OpCode) -> usize {
-    let v = self.current_chunk_mut().jump(code);
+  pub fn jump(&mut self, code: OpCode, position: Position) -> usize {
+    let v = self.current_chunk_mut().jump(code, position);
     self.update_aggregate_len();
     v
   }
@@ -526,11 +632,46 @@ impl ChunkGroup {
     self.update_aggregate_len();
     v
   }
-  pub fn add_loop(&mut self, offset: usize) -> Result<(), String> {
-    let v = self.current_chunk_mut().add_loop(offset);
+  pub fn add_loop(&mut self, offset: usize, position: Position) -> Result<(), String> {
+    let v = self.current_chunk_mut().add_loop(offset, position);
     self.update_aggregate_len();
     v
   }
+  pub(super) fn chunks(&self) -> &[Chunk] {
+    &self.chunks
+  }
+  /// Optimiza cada fragmento y las funciones que contienen, los nombres de las ranuras se mueven
+  /// con sus instrucciones
+  pub fn optimize(&mut self) {
+    for chunk in &self.chunks {
+      super::optimizer::optimize_functions(chunk);
+    }
+    // Los saltos se guardan como distancias, si alguno sale de su fragmento mover el codigo de
+    // los demas lo romperia
+    if !self.chunks.iter().all(super::optimizer::can_optimize) {
+      return;
+    }
+    let mut offsets = vec![];
+    let mut len = 0;
+    self.aggregate_len.clear();
+    for chunk in &mut self.chunks {
+      let chunk_offsets = super::optimizer::optimize(chunk);
+      offsets.extend(
+        chunk_offsets[..chunk_offsets.len() - 1]
+          .iter()
+          .map(|offset| len + offset),
+      );
+      len += chunk.code.len();
+      self.aggregate_len.push(len);
+    }
+    offsets.push(len);
+    for local in &mut self.locals {
+      local.start = offsets[local.start];
+      if let Some(&end) = offsets.get(local.end) {
+        local.end = end;
+      }
+    }
+  }
   pub fn _print(&mut self) {
     for (i, chunk) in self.chunks.iter().enumerate() {
       chunk._print(format!("chunk {i}"));
@@ -543,9 +684,10 @@ impl Default for ChunkGroup {
       chunks: vec![Chunk::new()],
       aggregate_len: vec![0],
       current: 0,
+      locals: vec![],
     };
-    group.write_constant(super::Value::Never, 0);
-    group.write(OpCode::Return as u8, 0);
+    group.write_constant(super::Value::Never, Position::default());
+    group.write(OpCode::Return as u8, Position::default());
     group
   }
 }
@@ -578,6 +720,7 @@ impl Decode for ChunkGroup {
       chunks: vec![],
       aggregate_len: vec![0],
       current: 0,
+      locals: vec![],
     };
     loop {
       let byte = vec.front().on_error(|_| "Binario corrupto".to_string())?;
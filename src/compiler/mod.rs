@@ -1,13 +1,19 @@
 pub mod binary;
 mod chunk;
+mod optimizer;
+mod resolver;
 mod value;
+mod verifier;
 use std::path::PathBuf;
 
 pub use chunk::{ChunkGroup, OpCode};
+pub use resolver::Upvalue;
+use resolver::{Resolution, Resolver};
 pub use value::*;
 
 use crate::agal_parser::{Node, NodeFunction};
 use crate::compiler::traits::AsNumber as _;
+use crate::util::{OnError as _, Position};
 use crate::{Decode, StructTag};
 
 const OBJECT_MEMBER: u8 = 0b0;
@@ -17,11 +23,35 @@ const CLASS_DECLARATION: u8 = 0b100;
 pub struct Compiler {
   pub function: Function,
   pub path: PathBuf,
+  resolver: Resolver,
 }
 impl Compiler {
-  fn parse_function(function: &NodeFunction) -> Result<Function, String> {
-    let mut compiler = Self {
-      function: Function::Value {
+  /// Aplica las optimizaciones de bytecode al programa y a todas sus funciones
+  pub fn optimize(&mut self) {
+    self.function.chunk().write().optimize();
+  }
+  /// Revisa el bytecode cargado de un archivo antes de ejecutarlo
+  pub fn verify(&self) -> Result<(), String> {
+    verifier::verify(&self.function)
+  }
+  fn sub_compiler(&mut self, function: Function, path: PathBuf) -> Self {
+    let mut resolver = std::mem::take(&mut self.resolver);
+    resolver.begin_function();
+    Self {
+      function,
+      path,
+      resolver,
+    }
+  }
+  fn end_sub_compiler(&mut self, mut compiler: Self) -> Function {
+    let upvalues = compiler.resolver.end_function();
+    self.resolver = compiler.resolver;
+    compiler.function.set_upvalues(upvalues);
+    compiler.function
+  }
+  fn parse_function(&mut self, function: &NodeFunction) -> Result<Function, String> {
+    let mut compiler = self.sub_compiler(
+      Function::Value {
         arity: function.params.len(),
         chunk: ChunkGroup::new_ref(),
         name: function.name.name.clone(),
@@ -30,9 +60,11 @@ impl Compiler {
         scope: None.into(),
         has_rest: false,
         in_class: None.into(),
+        upvalues: vec![],
+        captures: vec![],
       },
-      path: function.location.file_name.to_path_buf(),
-    };
+      function.location.file_name.to_path_buf(),
+    );
     let mut has_rest = false;
     let mut rest_param = None;
     for param in &function.params {
@@ -49,57 +81,137 @@ impl Compiler {
       } else {
         param.name
       };
-      let _global = compiler
+      let slot = compiler.declare_local(&name, true)?;
+      compiler
         .function
         .chunk()
         .write()
-        .make_arg(name, param.location.start.line);
+        .make_arg(slot, param.location.start);
     }
     compiler.function.set_rest(has_rest);
     if function.is_async {
-      compiler.write(OpCode::Promised as u8, function.location.start.line);
+      compiler.write(OpCode::Promised as u8, function.location.start);
     }
     if !function.body.is_empty() {
       compiler.node_to_bytes(&function.body.clone().into_node())?;
+      let last = function.body.body.get(function.body.body.len() - 1);
+      if let Some(Node::Call(_)) = last {
+        // El bloque termina con la llamada seguida de RemoveLocals
+        let call = compiler.len() - 4;
+        compiler.tail_call(call);
+      }
     } else {
-      compiler.set_constant(Value::Never, function.location.end.line);
+      compiler.set_constant(Value::Never, function.location.end);
     }
-    compiler.write(OpCode::Return as u8, function.location.end.line);
-    Ok(compiler.function)
+    compiler.write(OpCode::Return as u8, function.location.end);
+    Ok(self.end_sub_compiler(compiler))
   }
-  fn set_constant(&mut self, value: Value, line: usize) -> u8 {
-    self.function.chunk().write().write_constant(value, line)
+  fn set_constant(&mut self, value: Value, position: Position) -> u8 {
+    self
+      .function
+      .chunk()
+      .write()
+      .write_constant(value, position)
   }
   fn set_value(&mut self, value: Value) -> u8 {
     self.function.chunk().write().add_value(value)
   }
-  fn write(&mut self, byte: u8, line: usize) {
-    self.function.chunk().write().write(byte, line);
+  fn write(&mut self, byte: u8, position: Position) {
+    self.function.chunk().write().write(byte, position);
   }
-  fn write_buffer(&mut self, buffer: Vec<u8>, line: usize) {
-    self.function.chunk().write().write_buffer(buffer, line);
+  fn write_buffer(&mut self, buffer: Vec<u8>, position: Position) {
+    self.function.chunk().write().write_buffer(buffer, position);
   }
   fn len(&mut self) -> usize {
     self.function.chunk().read().len()
   }
-  fn read_var(&mut self, name: String, line: usize) {
-    self.function.chunk().write().read_var(name, line);
+  /// Las llamadas en posicion de cola solo se optimizan en funciones sincronas,
+  /// los bloques de intenta/captura y los modulos necesitan su propio marco
+  fn tail_call(&mut self, call: usize) {
+    if let Function::Value {
+      is_async: false, ..
+    } = self.function
+    {
+      self.function.chunk().write().make_tail_call(call);
+    }
+  }
+  fn read_var(&mut self, name: String, position: Position) -> Result<(), String> {
+    match self.resolver.resolve(&name)? {
+      Resolution::Local { slot, .. } => {
+        self.write_buffer(vec![OpCode::GetLocal as u8, slot], position);
+      }
+      Resolution::Upvalue { index, .. } => {
+        self.write_buffer(vec![OpCode::GetUpvalue as u8, index], position);
+      }
+      Resolution::Global => {
+        self.function.chunk().write().read_var(name, position);
+      }
+    };
+    Ok(())
+  }
+  fn assign_var(&mut self, name: &str, position: Position) -> Result<(), String> {
+    match self.resolver.resolve(name)? {
+      Resolution::Local { is_const, .. } | Resolution::Upvalue { is_const, .. } if is_const => {
+        return Err(format!("No se puede re-asignar la constante '{name}'"))
+      }
+      Resolution::Local { slot, .. } => {
+        self.write_buffer(vec![OpCode::SetLocal as u8, slot], position);
+      }
+      Resolution::Upvalue { index, .. } => {
+        self.write_buffer(vec![OpCode::SetUpvalue as u8, index], position);
+      }
+      Resolution::Global => {
+        let name = self.set_value(Value::String(name.to_string()));
+        self.write_buffer(vec![OpCode::SetVar as u8, name], position);
+      }
+    };
+    Ok(())
+  }
+  fn declare_var(&mut self, name: &str, is_const: bool, position: Position) -> Result<(), String> {
+    let slot = match self.resolver.define(name) {
+      Some(slot) => Some(slot),
+      None => self.resolver.declare(name, is_const)?,
+    };
+    match slot {
+      Some(slot) => {
+        self.function.chunk().write().name_local(slot, name);
+        self.write_buffer(vec![OpCode::DeclLocal as u8, slot], position)
+      }
+      None => {
+        let op = if is_const {
+          OpCode::ConstDecl
+        } else {
+          OpCode::VarDecl
+        };
+        let name = self.set_value(Value::String(name.to_string()));
+        self.write_buffer(vec![op as u8, name], position);
+      }
+    };
+    Ok(())
+  }
+  fn declare_local(&mut self, name: &str, is_const: bool) -> Result<u8, String> {
+    let slot = self
+      .resolver
+      .declare(name, is_const)?
+      .on_error(|_| format!("Se esperaba que '{name}' fuera una variable local"))?;
+    self.function.chunk().write().name_local(slot, name);
+    Ok(slot)
   }
-  fn jump(&mut self, code: OpCode) -> usize {
-    self.function.chunk().write().jump(code)
+  fn jump(&mut self, code: OpCode, position: Position) -> usize {
+    self.function.chunk().write().jump(code, position)
   }
   fn patch_jump(&mut self, offset: usize) -> Result<(), String> {
     self.function.chunk().write().patch_jump(offset)
   }
-  fn add_loop(&mut self, offset: usize) -> Result<(), String> {
-    self.function.chunk().write().add_loop(offset)
+  fn add_loop(&mut self, offset: usize, position: Position) -> Result<(), String> {
+    self.function.chunk().write().add_loop(offset, position)
   }
   fn node_value_to_bytes(&mut self, node: &Node) -> Result<(), String> {
     match node {
       Node::Function(node_function) => {
-        let function = Value::Object(Self::parse_function(node_function)?.into());
-        self.set_constant(function.clone(), node_function.location.start.line);
-        self.write(OpCode::SetScope as u8, node_function.location.start.line);
+        let function = Value::Object(self.parse_function(node_function)?.into());
+        self.set_constant(function.clone(), node_function.location.start);
+        self.write(OpCode::SetScope as u8, node_function.location.start);
         Ok(())
       }
       node => self.node_to_bytes(node),
@@ -114,10 +226,10 @@ impl Compiler {
           node_number.value.as_radix(node_number.base)
         }
         .unwrap();
-        self.set_constant(Value::Number(number), node_number.location.start.line);
+        self.set_constant(Value::Number(number), node_number.location.start);
       }
       Node::Byte(node_byte) => {
-        self.set_constant(Value::Byte(node_byte.value), node_byte.location.start.line);
+        self.set_constant(Value::Byte(node_byte.value), node_byte.location.start);
       }
       Node::Binary(node_binary) => {
         self.node_to_bytes(&node_binary.left)?;
@@ -134,6 +246,7 @@ impl Compiler {
           crate::agal_parser::NodeOperator::Equal => vec![OpCode::Equals as u8],
           crate::agal_parser::NodeOperator::LessThan => vec![OpCode::LessThan as u8],
           crate::agal_parser::NodeOperator::Modulo => vec![OpCode::Modulo as u8],
+          crate::agal_parser::NodeOperator::Exponential => vec![OpCode::Exponential as u8],
           crate::agal_parser::NodeOperator::And => vec![OpCode::And as u8],
           crate::agal_parser::NodeOperator::Or => vec![OpCode::Or as u8],
           crate::agal_parser::NodeOperator::Nullish => vec![OpCode::Nullish as u8],
@@ -143,26 +256,49 @@ impl Compiler {
             ))
           }
         };
-        self.write_buffer(operator, node_binary.location.start.line);
+        self.write_buffer(operator, node_binary.location.start);
       }
       Node::Program(node_program) => {
         if !node_program.body.is_empty() {
           self.node_to_bytes(&node_program.body.clone().into_node())?;
-          self.write(OpCode::Pop as u8, node_program.location.start.line);
+          self.write(OpCode::Pop as u8, node_program.location.start);
         }
-        self.set_constant(Value::Never, node_program.location.start.line);
-        self.write(OpCode::Return as u8, node_program.location.end.line);
+        self.set_constant(Value::Never, node_program.location.start);
+        self.write(OpCode::Return as u8, node_program.location.end);
       }
       Node::Block(node_block, _is_async) => {
-        self.write(OpCode::NewLocals as u8, node_block.location.start.line);
+        self.resolver.begin_scope();
+        // Las funciones se declaran antes para que puedan llamarse entre ellas
+        // y capturar las variables del bloque aunque se declaren despues
+        if !self.resolver.is_global_scope() {
+          for node in node_block.body.iter() {
+            match node {
+              Node::Function(node_function) => {
+                self.declare_local(&node_function.name.name, true)?;
+              }
+              Node::VarDecl(node_var_decl) => {
+                self
+                  .resolver
+                  .declare_pending(&node_var_decl.name.name, node_var_decl.is_const)?;
+              }
+              _ => {}
+            }
+          }
+        }
+        self.write(OpCode::NewLocals as u8, node_block.location.start);
         let code_len = node_block.body.len();
         for (index, node) in node_block.body.clone().enumerate() {
           self.node_to_bytes(node)?;
           if index < (code_len - 1) {
-            self.write(OpCode::Pop as u8, node.get_location().end.line);
+            self.write(OpCode::Pop as u8, node.get_location().end);
           }
         }
-        self.write(OpCode::RemoveLocals as u8, node_block.location.end.line);
+        let alive = self.resolver.end_scope();
+        self.function.chunk().write().end_locals(alive);
+        self.write_buffer(
+          vec![OpCode::RemoveLocals as u8, alive],
+          node_block.location.end,
+        );
       }
       Node::UnaryFront(node_unary) => {
         self.node_to_bytes(&node_unary.operand)?;
@@ -179,40 +315,34 @@ impl Compiler {
             ))
           }
         } as u8;
-        self.write(operator, node_unary.location.start.line);
+        self.write(operator, node_unary.location.start);
       }
       Node::Identifier(node_identifier) => {
-        self.read_var(
-          node_identifier.name.clone(),
-          node_identifier.location.start.line,
-        );
+        self.read_var(node_identifier.name.clone(), node_identifier.location.start)?;
       }
       Node::Console(node_console) => {
         if let crate::agal_parser::NodeConsole::Output { value, location } = node_console {
           self.node_to_bytes(value)?;
-          self.write(OpCode::ConsoleOut as u8, location.start.line);
+          self.write(OpCode::ConsoleOut as u8, location.start);
         }
       }
       Node::String(node_string) => {
         for (i, data) in node_string.value.clone().enumerate() {
           match data {
             crate::agal_parser::StringData::Str(val) => {
-              self.set_constant(
-                Value::String(val.to_string()),
-                node_string.location.start.line,
-              );
+              self.set_constant(Value::String(val.to_string()), node_string.location.start);
             }
             crate::agal_parser::StringData::Id(value) => {
-              self.read_var(value.name.to_string(), value.location.start.line);
+              self.read_var(value.name.to_string(), value.location.start)?;
             }
           }
           if i != 0 {
-            self.write(OpCode::Add as u8, node_string.location.start.line);
+            self.write(OpCode::Add as u8, node_string.location.start);
           }
         }
       }
       Node::VarDecl(node_var_decl) => {
-        let op = if node_var_decl.is_const {
+        if node_var_decl.is_const {
           match &node_var_decl.value {
             Some(value) => {
               self.node_value_to_bytes(value)?;
@@ -224,30 +354,27 @@ impl Compiler {
               ))
             }
           }
-          OpCode::ConstDecl as u8
         } else {
           match &node_var_decl.value {
             Some(value) => {
               self.node_value_to_bytes(value)?;
             }
             None => {
-              self.set_constant(Value::Never, node_var_decl.location.start.line);
+              self.set_constant(Value::Never, node_var_decl.location.start);
             }
           };
-          OpCode::VarDecl as u8
         };
-        let name = self.set_value(Value::String(node_var_decl.name.name.clone()));
-        self.write_buffer(vec![op, name], node_var_decl.location.start.line);
+        self.declare_var(
+          &node_var_decl.name.name,
+          node_var_decl.is_const,
+          node_var_decl.location.start,
+        )?;
       }
       Node::Assignment(node_assignament) => {
         match node_assignament.identifier.as_ref() {
           Node::Identifier(id) => {
             self.node_value_to_bytes(&node_assignament.value)?;
-            let name = self.set_value(Value::String(id.name.clone()));
-            self.write_buffer(
-              vec![OpCode::SetVar as u8, name],
-              node_assignament.location.start.line,
-            );
+            self.assign_var(&id.name, node_assignament.location.start)?;
           }
           Node::Member(m) => {
             self.node_to_bytes(&m.object)?;
@@ -258,7 +385,7 @@ impl Compiler {
                 Node::Identifier(id) => id.name.as_str(),
                 _ => return Err("Se esperaba un identificador como propiedad".to_string()),
               };
-              self.set_constant(Value::String(name.to_string()), m.location.start.line);
+              self.set_constant(Value::String(name.to_string()), m.location.start);
             };
             self.node_value_to_bytes(&node_assignament.value)?;
             self.write_buffer(
@@ -270,7 +397,7 @@ impl Compiler {
                   OBJECT_MEMBER
                 },
               ],
-              m.location.start.line,
+              m.location.start,
             );
           }
           _ => return Err("Se esperaba una assignacion valida".to_string()),
@@ -278,72 +405,91 @@ impl Compiler {
       }
       Node::If(node_if) => {
         self.node_to_bytes(&node_if.condition)?;
-        let jump_if = self.jump(OpCode::JumpIfFalse);
+        let jump_if = self.jump(OpCode::JumpIfFalse, node_if.location.start);
         self.node_to_bytes(&node_if.body.clone().into_node())?;
 
-        let jump_else = self.jump(OpCode::Jump);
+        let jump_else = self.jump(OpCode::Jump, node_if.location.start);
         self.patch_jump(jump_if)?;
 
         if let Some(e) = &node_if.else_body {
           self.node_to_bytes(&e.clone().into_node())?;
         } else {
-          self.set_constant(Value::Never, node_if.location.start.line);
+          self.set_constant(Value::Never, node_if.location.start);
         }
         self.patch_jump(jump_else)?;
       }
       Node::While(node_while) => {
         let loop_start = self.len();
         self.node_to_bytes(&node_while.condition)?;
-        let jump_while = self.jump(OpCode::JumpIfFalse);
+        let jump_while = self.jump(OpCode::JumpIfFalse, node_while.location.start);
         self.node_to_bytes(&node_while.body.clone().into_node())?;
         if !node_while.body.is_empty() {
-          self.write(OpCode::Pop as u8, 0);
+          self.write(OpCode::Pop as u8, Position::default());
         }
-        self.add_loop(loop_start)?;
+        self.add_loop(loop_start, node_while.location.end)?;
         self.patch_jump(jump_while)?;
-        self.set_constant(Value::Never, node_while.location.start.line);
+        self.set_constant(Value::Never, node_while.location.start);
       }
       Node::DoWhile(node_do_while) => {
-        let jump_do = self.jump(OpCode::Jump);
+        let jump_do = self.jump(OpCode::Jump, node_do_while.location.start);
         let loop_start = self.len();
         self.node_to_bytes(&node_do_while.condition)?;
-        let jump_do_while = self.jump(OpCode::JumpIfFalse);
+        let jump_do_while = self.jump(OpCode::JumpIfFalse, node_do_while.location.start);
         self.patch_jump(jump_do)?;
         self.node_to_bytes(&node_do_while.body.clone().into_node())?;
         if !node_do_while.body.is_empty() {
-          self.write(OpCode::Pop as u8, 0);
+          self.write(OpCode::Pop as u8, Position::default());
         }
-        self.add_loop(loop_start)?;
+        self.add_loop(loop_start, node_do_while.location.end)?;
         self.patch_jump(jump_do_while)?;
-        self.set_constant(Value::Never, node_do_while.location.start.line);
+        self.set_constant(Value::Never, node_do_while.location.start);
       }
       Node::For(node_for) => {
-        self.write(OpCode::NewLocals as u8, node_for.location.start.line);
+        self.resolver.begin_scope();
+        self.write(OpCode::NewLocals as u8, node_for.location.start);
         self.node_to_bytes(&node_for.init)?;
-        self.write(OpCode::Pop as u8, 0);
+        self.write(OpCode::Pop as u8, Position::default());
         let loop_start = self.len();
         self.node_to_bytes(&node_for.condition)?;
-        let jump_for = self.jump(OpCode::JumpIfFalse);
+        let jump_for = self.jump(OpCode::JumpIfFalse, node_for.location.start);
         self.node_to_bytes(&node_for.body.clone().into_node())?;
         if !node_for.body.is_empty() {
-          self.write(OpCode::Pop as u8, 0);
+          self.write(OpCode::Pop as u8, Position::default());
         }
         self.node_to_bytes(&node_for.update)?;
-        self.write(OpCode::Pop as u8, 0);
-        self.add_loop(loop_start)?;
+        self.write(OpCode::Pop as u8, Position::default());
+        self.add_loop(loop_start, node_for.location.end)?;
         self.patch_jump(jump_for)?;
-        self.write(OpCode::RemoveLocals as u8, node_for.location.start.line);
-        self.set_constant(Value::Never, node_for.location.end.line);
+        let alive = self.resolver.end_scope();
+        self.function.chunk().write().end_locals(alive);
+        self.write_buffer(
+          vec![OpCode::RemoveLocals as u8, alive],
+          node_for.location.start,
+        );
+        self.set_constant(Value::Never, node_for.location.end);
       }
       Node::Function(node_function) => {
-        let function = Value::Object(Self::parse_function(node_function)?.into());
-        self.set_constant(function.clone(), node_function.location.start.line);
+        let name = &node_function.name.name;
+        // Se declara antes de compilar el cuerpo para que la funcion pueda llamarse a si misma
+        let slot = if self.resolver.is_global_scope() {
+          None
+        } else {
+          match self.resolver.declared_in_scope(name) {
+            Some(slot) => Some(slot),
+            None => Some(self.declare_local(name, true)?),
+          }
+        };
+        let function = Value::Object(self.parse_function(node_function)?.into());
+        self.set_constant(function.clone(), node_function.location.start);
 
-        let name = self.set_value(Value::String(node_function.name.name.clone()));
-        self.write_buffer(
-          vec![OpCode::SetScope as u8, OpCode::ConstDecl as u8, name],
-          node_function.location.start.line,
-        );
+        let decl = match slot {
+          Some(slot) => vec![OpCode::SetScope as u8, OpCode::DeclLocal as u8, slot],
+          None => {
+            let name = self.set_value(Value::String(name.clone()));
+            vec![OpCode::SetScope as u8, OpCode::ConstDecl as u8, name]
+          }
+        };
+        self.write_buffer(decl, node_function.location.start);
       }
       Node::Call(node_call) => {
         for arg in &node_call.arguments {
@@ -352,7 +498,7 @@ impl Compiler {
         match node_call.callee.as_ref() {
           Node::Member(m) => {
             self.node_to_bytes(&m.object)?;
-            self.write(OpCode::Copy as u8, m.object.get_location().end.line);
+            self.write(OpCode::Copy as u8, m.object.get_location().end);
             if m.computed {
               self.node_to_bytes(&m.member)?;
             } else {
@@ -360,69 +506,70 @@ impl Compiler {
                 Node::Identifier(id) => id.name.as_str(),
                 _ => return Err("Se esperaba un identificador como propiedad".to_string()),
               };
-              self.set_constant(Value::String(name.to_string()), m.location.start.line);
+              self.set_constant(Value::String(name.to_string()), m.location.start);
             };
             let is_instance = if m.instance {
               INSTANCE_MEMBER
             } else {
               OBJECT_MEMBER
             };
-            self.write_buffer(
-              vec![OpCode::GetMember as u8, is_instance],
-              m.location.start.line,
-            );
+            self.write_buffer(vec![OpCode::GetMember as u8, is_instance], m.location.start);
           }
           Node::Identifier(i) => {
-            self.read_var(i.name.clone(), i.location.start.line);
-            self.write(OpCode::Copy as u8, node.get_location().start.line)
+            self.read_var(i.name.clone(), i.location.start)?;
+            self.write(OpCode::Copy as u8, node.get_location().start)
           }
           node => {
             self.node_value_to_bytes(&node_call.callee)?;
-            self.write(OpCode::Copy as u8, node.get_location().start.line)
+            self.write(OpCode::Copy as u8, node.get_location().start)
           }
         };
 
         self.write_buffer(
           vec![OpCode::Call as u8, node_call.arguments.len() as u8],
-          node_call.location.start.line,
+          node_call.location.start,
         );
       }
       Node::Return(node_return) => {
         match &node_return.value {
           Some(value) => {
             self.node_to_bytes(value)?;
+            if let Node::Call(_) = value.as_ref() {
+              let call = self.len() - 2;
+              self.tail_call(call);
+            }
           }
           None => {
-            self.set_constant(Value::Never, node_return.location.start.line);
+            self.set_constant(Value::Never, node_return.location.start);
           }
         };
-        self.write(OpCode::Return as u8, node_return.location.start.line);
+        self.write(OpCode::Return as u8, node_return.location.start);
       }
       Node::Object(node_object) => {
         let value = Value::Object(std::collections::HashMap::new().into());
         for p in node_object.properties.clone() {
-          self.set_constant(value.clone(), node_object.location.start.line);
+          self.set_constant(value.clone(), node_object.location.start);
           match p {
             crate::agal_parser::NodeProperty::Dynamic(key, value) => {
               self.node_to_bytes(&key)?;
               self.node_value_to_bytes(&value)?;
               self.write_buffer(
                 vec![OpCode::SetMember as u8, OBJECT_MEMBER, OpCode::Pop as u8],
-                node_object.location.start.line,
+                node_object.location.start,
               );
             }
             crate::agal_parser::NodeProperty::Property(key, value) => {
-              self.set_constant(Value::String(key.name), node_object.location.start.line);
+              self.set_constant(Value::String(key.name), node_object.location.start);
               self.node_value_to_bytes(&value)?;
               self.write_buffer(
                 vec![OpCode::SetMember as u8, OBJECT_MEMBER, OpCode::Pop as u8],
-                node_object.location.start.line,
+                node_object.location.start,
               );
             }
             _ => {}
           };
         }
-        self.set_constant(value, node_object.location.start.line);
+        self.set_constant(value, node_object.location.start);
       }
       Node::Member(node_member) => {
         self.node_to_bytes(&node_member.object)?;
@@ -433,10 +580,7 @@ impl Compiler {
             Node::Identifier(id) => id.name.as_str(),
             _ => return Err("Se esperaba un identificador como propiedad".to_string()),
           };
-          self.set_constant(
-            Value::String(name.to_string()),
-            node_member.location.start.line,
-          );
+          self.set_constant(Value::String(name.to_string()), node_member.location.start);
         };
         let is_instance = if node_member.instance {
           INSTANCE_MEMBER
@@ -445,36 +589,36 @@ impl Compiler {
         };
         self.write_buffer(
           vec![OpCode::GetMember as u8, is_instance],
-          node_member.location.start.line,
+          node_member.location.start,
         );
       }
       Node::Array(node_array) => {
         let vec: Vec<Value> = vec![];
         let value = Value::Object(vec.into());
         for (index, p) in node_array.elements.clone().into_iter().enumerate() {
-          self.set_constant(value.clone(), node_array.location.start.line);
+          self.set_constant(value.clone(), node_array.location.start);
           if let crate::agal_parser::NodeProperty::Indexable(value) = p {
-            self.set_constant(Value::Number(index.into()), node_array.location.start.line);
+            self.set_constant(Value::Number(index.into()), node_array.location.start);
             self.node_value_to_bytes(&value)?;
             self.write_buffer(
               vec![OpCode::SetMember as u8, OBJECT_MEMBER, OpCode::Pop as u8],
-              node_array.location.start.line,
+              node_array.location.start,
             );
           }
         }
-        self.set_constant(value, node_array.location.start.line);
+        self.set_constant(value, node_array.location.start);
       }
       Node::LoopEdit(node_loop_editor) => {
         let byte = match node_loop_editor.action {
           crate::agal_parser::NodeLoopEditType::Break => OpCode::Break,
           crate::agal_parser::NodeLoopEditType::Continue => OpCode::Continue,
         } as u8;
-        self.write(byte, node_loop_editor.location.start.line);
+        self.write(byte, node_loop_editor.location.start);
       }
       Node::Import(node_import) => {
         self.set_constant(
           Value::String(node_import.path.clone()),
-          node_import.location.start.line,
+          node_import.location.start,
         );
         let lazy_bit = if node_import.is_lazy { 0b10 } else { 0b00 };
         let alias_bit = if node_import.name.is_some() {
@@ -490,7 +634,7 @@ impl Compiler {
         };
         self.write_buffer(
           vec![OpCode::Import as u8, meta_byte, name_byte],
-          node_import.location.start.line,
+          node_import.location.start,
         );
       }
       Node::Name(_) => {
@@ -502,21 +646,18 @@ impl Compiler {
       Node::Export(node_export) => {
         let name: &str = match node_export.value.as_ref() {
           Node::Name(n) => {
-            self.read_var(n.name.clone(), n.location.start.line);
+            self.read_var(n.name.clone(), n.location.start)?;
             &n.name
           }
           Node::Function(f) => {
-            self.set_constant(
-              Value::Object(Self::parse_function(f)?.into()),
-              f.location.start.line,
-            );
-
-            let name = self.set_value(Value::String(f.name.name.clone()));
-            self.write_buffer(vec![OpCode::ConstDecl as u8, name], f.location.start.line);
+            let function = Value::Object(self.parse_function(f)?.into());
+            self.set_constant(function, f.location.start);
+            self.write(OpCode::SetScope as u8, f.location.start);
+            self.declare_var(&f.name.name, true, f.location.start)?;
             &f.name.name
           }
           Node::VarDecl(v) => {
-            let op = if v.is_const {
+            if v.is_const {
               match &v.value {
                 Some(value) => {
                   self.node_to_bytes(value)?;
@@ -528,20 +669,17 @@ impl Compiler {
                   ))
                 }
               }
-              OpCode::ConstDecl as u8
             } else {
               match &v.value {
                 Some(value) => {
                   self.node_to_bytes(value)?;
                 }
                 None => {
-                  self.set_constant(Value::Never, v.location.start.line);
+                  self.set_constant(Value::Never, v.location.start);
                 }
               };
-              OpCode::VarDecl as u8
             };
-            let name = self.set_value(Value::String(v.name.name.clone()));
-            self.write_buffer(vec![op, name], v.location.start.line);
+            self.declare_var(&v.name.name, v.is_const, v.location.start)?;
             &v.name.name
           }
 
@@ -555,21 +693,25 @@ impl Compiler {
         let name_byte = self.set_value(Value::String(name.to_string()));
         self.write_buffer(
           vec![OpCode::Export as u8, name_byte],
-          node_export.location.start.line,
+          node_export.location.start,
         );
       }
       Node::VarDel(node_identifier) => {
-        self.read_var(
-          node_identifier.name.clone(),
-          node_identifier.location.start.line,
+        let name = &node_identifier.name;
+        if self.resolver.resolve(name)? != Resolution::Global {
+          return Err(format!("No se puede eliminar la variable local '{name}'"));
+        }
+        let name = self.set_value(Value::String(name.clone()));
+        self.write_buffer(
+          vec![OpCode::DelVar as u8, name],
+          node_identifier.location.start,
         );
-        self.write(OpCode::DelVar as u8, node_identifier.location.start.line);
       }
       Node::Await(node_expression) => {
         self.node_value_to_bytes(&node_expression.expression)?;
         self.write_buffer(
           vec![OpCode::Await as u8, OpCode::UnPromise as u8],
-          node_expression.location.start.line,
+          node_expression.location.start,
         );
       }
       Node::Class(node_class) => {
@@ -581,20 +723,20 @@ impl Compiler {
 
           self.set_constant(
             Value::Object(class.clone()),
-            prop.value.get_location().start.line,
+            prop.value.get_location().start,
           );
           if !is_static {
-            self.write(OpCode::GetInstance as u8, node_class.location.start.line);
+            self.write(OpCode::GetInstance as u8, node_class.location.start);
           };
 
           self.set_constant(
             Value::String(prop.name.name),
-            prop.value.get_location().start.line,
+            prop.value.get_location().start,
           );
           self.node_value_to_bytes(&prop.value)?;
           self.set_constant(
             Value::Object(class.clone()),
-            prop.value.get_location().start.line,
+            prop.value.get_location().start,
           );
           self.write_buffer(
             vec![
@@ -603,102 +745,132 @@ impl Compiler {
               INSTANCE_MEMBER | CLASS_DECLARATION | is_public,
               OpCode::Pop as u8,
             ],
-            prop.value.get_location().start.line,
+            prop.value.get_location().start,
           );
         }
-        self.set_constant(Value::Object(class), node_class.location.start.line);
+        self.set_constant(Value::Object(class), node_class.location.start);
         if let Some(node_identifier) = &node_class.extend_of {
-          self.read_var(
-            node_identifier.name.clone(),
-            node_identifier.location.start.line,
-          );
-          self.write(OpCode::ExtendClass as u8, node_identifier.location.end.line);
+          self.read_var(node_identifier.name.clone(), node_identifier.location.start)?;
+          self.write(OpCode::ExtendClass as u8, node_identifier.location.end);
         }
-        let name = self.set_value(Value::String(node_class.name.name.clone()));
-        self.write_buffer(
-          vec![OpCode::ConstDecl as u8, name],
-          node_class.location.start.line,
-        );
+        self.declare_var(&node_class.name.name, true, node_class.location.start)?;
       }
       Node::Throw(node_value) => {
         self.node_value_to_bytes(node)?;
-        self.write(OpCode::Throw as u8, node_value.location.start.line);
+        self.write(OpCode::Throw as u8, node_value.location.start);
       }
       Node::Try(node_try) => {
-        let mut try_block = Self {
-          function: Function::Script {
+        let mut try_block = self.sub_compiler(
+          Function::Script {
             chunk: ChunkGroup::new_ref(),
             path: node_try.location.file_name.to_path_buf(),
             scope: Default::default(),
+            upvalues: vec![],
+            captures: vec![],
           },
-          path: node_try.location.file_name.to_path_buf(),
-        };
+          node_try.location.file_name.to_path_buf(),
+        );
         if !node_try.body.is_empty() {
           try_block.node_to_bytes(&node_try.body.clone().into_node())?;
         } else {
-          try_block.set_constant(Value::Never, node_try.location.end.line);
+          try_block.set_constant(Value::Never, node_try.location.end);
         }
-        try_block.write(OpCode::Return as u8, node_try.location.end.line);
-        self.set_constant(
-          Value::Object(try_block.function.into()),
-          node_try.location.start.line,
-        );
-        let mut catch_block = Self {
-          function: Function::Script {
+        try_block.write(OpCode::Return as u8, node_try.location.end);
+        let try_block = self.end_sub_compiler(try_block);
+        self.set_constant(Value::Object(try_block.into()), node_try.location.start);
+        self.write(OpCode::SetScope as u8, node_try.location.start);
+        let mut catch_block = self.sub_compiler(
+          Function::Script {
             chunk: ChunkGroup::new_ref(),
             path: node_try.location.file_name.to_path_buf(),
             scope: Default::default(),
+            upvalues: vec![],
+            captures: vec![],
           },
-          path: node_try.location.file_name.to_path_buf(),
-        };
+          node_try.location.file_name.to_path_buf(),
+        );
         match &node_try.catch {
           Some((error, block)) => {
+            let slot = catch_block.declare_local(error, true)?;
             catch_block
               .function
               .chunk()
               .write()
-              .make_arg(error.clone(), block.location.start.line);
+              .make_arg(slot, block.location.start);
             if !block.is_empty() {
               catch_block.node_to_bytes(&block.clone().into_node())?;
             } else {
-              catch_block.set_constant(Value::Never, block.location.end.line);
+              catch_block.set_constant(Value::Never, block.location.end);
             }
           }
           None => {
-            catch_block.set_constant(Value::Never, node_try.location.end.line);
+            catch_block.set_constant(Value::Never, node_try.location.end);
           }
         };
-        catch_block.write(OpCode::Return as u8, node_try.location.end.line);
-        let catch_block = catch_block.function;
-        self.set_constant(
-          Value::Object(catch_block.into()),
-          node_try.location.start.line,
+        catch_block.write(OpCode::Return as u8, node_try.location.end);
+        let catch_block = self.end_sub_compiler(catch_block);
+        self.set_constant(Value::Object(catch_block.into()), node_try.location.start);
+        self.write_buffer(
+          vec![OpCode::SetScope as u8, OpCode::Try as u8],
+          node_try.location.start,
         );
-        self.write(OpCode::Try as u8, node_try.location.start.line);
-        self.set_constant(Value::Never, node_try.location.end.line);
+        self.set_constant(Value::Never, node_try.location.end);
       }
       Node::Lazy(node_expression) => {
-        let mut lazy_block = Self {
-          function: Function::Script {
+        let mut lazy_block = self.sub_compiler(
+          Function::Script {
             chunk: ChunkGroup::new_ref(),
             path: node.get_file(),
             scope: None.into(),
+            upvalues: vec![],
+            captures: vec![],
           },
-          path: node_expression.location.file_name.to_path_buf(),
-        };
+          node_expression.location.file_name.to_path_buf(),
+        );
         lazy_block.node_to_bytes(&node_expression.expression)?;
-        lazy_block.write(OpCode::Return as u8, node_expression.location.end.line);
+        lazy_block.write(OpCode::Return as u8, node_expression.location.end);
+        let lazy_block = self.end_sub_compiler(lazy_block);
         self.set_constant(
-          Value::Lazy(lazy_block.function.into()),
-          node_expression.location.start.line,
+          Value::Lazy(lazy_block.into()),
+          node_expression.location.start,
         );
-        self.write(OpCode::SetScope as u8, node_expression.location.end.line);
+        self.write(OpCode::SetScope as u8, node_expression.location.end);
       }
       Node::None => return Err("Se encontro un error de nodos".to_string()),
     };
     Ok(())
   }
 }
+impl Compiler {
+  /// Compila un programa que retorna el valor de su ultima expresion en lugar de `nada`, como
+  /// las expresiones que se evaluan en el depurador
+  pub fn expression(node: &Node) -> Result<Self, String> {
+    let Node::Program(program) = node else {
+      return Err("Se esperaba un programa".to_string());
+    };
+    let path = node.get_file();
+    let mut compiler = Self {
+      function: Function::Script {
+        chunk: ChunkGroup::new_ref(),
+        path: path.clone(),
+        scope: None.into(),
+        upvalues: vec![],
+        captures: vec![],
+      },
+      path,
+      resolver: Default::default(),
+    };
+    compiler.resolver.begin_module();
+    if program.body.is_empty() {
+      compiler.set_constant(Value::Never, program.location.start);
+    } else {
+      compiler.node_to_bytes(&program.body.clone().into_node())?;
+    }
+    compiler.write(OpCode::Return as u8, program.location.end);
+    compiler.resolver.end_function();
+    Ok(compiler)
+  }
+}
 impl TryFrom<&Node> for Compiler {
   type Error = String;
 
@@ -709,26 +881,37 @@ impl TryFrom<&Node> for Compiler {
       chunk,
       path: path.clone(),
       scope: None.into(),
+      upvalues: vec![],
+      captures: vec![],
+    };
+    let mut compiler = Self {
+      function,
+      path,
+      resolver: Default::default(),
     };
-    let mut compiler = Self { function, path };
+    compiler.resolver.begin_module();
     compiler.node_to_bytes(value)?;
+    compiler.resolver.end_function();
     Ok(compiler)
   }
 }
 impl crate::Encode for Compiler {
   fn encode(&self) -> Result<Vec<u8>, String> {
-    let mut encode = vec![];
+    let mut payload = vec![];
 
-    encode.push(crate::StructTag::Compile as u8);
-    encode.extend(self.path.encode()?);
-    encode.extend(self.function.encode()?);
+    payload.push(crate::StructTag::Compile as u8);
+    payload.extend(self.path.encode()?);
+    payload.extend(self.function.encode()?);
 
+    let mut encode = binary::Header::new(&payload).encode()?;
+    encode.extend(payload);
     Ok(encode)
   }
 }
 impl Decode for Compiler {
   fn decode(vec: &mut std::collections::VecDeque<u8>) -> Result<Self, String> {
     use crate::util::{OnError as _, OnSome as _};
+    binary::Header::decode(vec)?.check(vec.make_contiguous())?;
     vec
       .pop_front()
       .on_some_option(|byte| {
@@ -742,6 +925,7 @@ impl Decode for Compiler {
     Ok(Self {
       path: PathBuf::decode(vec)?,
       function: Function::decode(vec)?,
+      resolver: Default::default(),
     })
   }
 }
@@ -0,0 +1,585 @@
+use std::collections::HashSet;
+
+use super::chunk::Chunk;
+use super::{Number, Object, OpCode, Value};
+use crate::util::Position;
+
+#[derive(Clone, Debug)]
+struct Instruction {
+  op: OpCode,
+  operands: Vec<u8>,
+  position: Position,
+  // Indice de la instruccion destino para los saltos
+  target: Option<usize>,
+  // Byte donde empezaba antes de optimizar
+  offset: usize,
+}
+impl Instruction {
+  fn operand(&self) -> u8 {
+    self.operands[0]
+  }
+}
+
+/// Optimiza las funciones que el fragmento contiene como constantes
+pub fn optimize_functions(chunk: &Chunk) {
+  for (_, value) in chunk.constants.enumerate() {
+    if let Some(function) = constant_function(value) {
+      function.read().chunk().write().optimize();
+    }
+  }
+}
+/// Si todas las instrucciones y los destinos de los saltos estan dentro del fragmento
+pub fn can_optimize(chunk: &Chunk) -> bool {
+  decode(chunk).is_some()
+}
+/// Optimiza el codigo del fragmento y retorna donde quedo cada byte del codigo original, y su
+/// final. Lo que se quito queda en la siguiente instruccion que se conserva
+pub fn optimize(chunk: &mut Chunk) -> Vec<usize> {
+  let mut code = match decode(chunk) {
+    Some(code) => code,
+    None => return (0..=chunk.code.len()).collect(),
+  };
+  // El orden importa: los bloques deben estar completos para colapsar sus ambitos
+  remove_empty_scopes(&mut code);
+  fold_constants(&mut code, chunk);
+  remove_dead_stores(&mut code, chunk);
+  remove_unreachable(&mut code);
+  encode(&code, chunk)
+}
+
+fn constant_function(value: &Value) -> Option<crate::MultiRefHash<super::Function>> {
+  match value {
+    Value::Object(Object::Function(function)) => Some(function.clone()),
+    Value::Lazy(lazy) => Some(lazy.get_once()),
+    _ => None,
+  }
+}
+
+fn decode(chunk: &Chunk) -> Option<Vec<Instruction>> {
+  let mut code = vec![];
+  let mut positions = vec![];
+  let mut jumps = vec![];
+  let mut offset = 0;
+  while offset < chunk.code.len() {
+    let op = OpCode::from(chunk.code[offset]);
+    if op == OpCode::Null {
+      return None;
+    }
+    let end = offset + 1 + op.operand_len();
+    let operands = chunk.code.get(offset + 1..end)?.to_vec();
+    if matches!(op, OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop) {
+      let distance = ((operands[0] as usize) << 8) | operands[1] as usize;
+      let target = match op {
+        OpCode::Loop => end.checked_sub(distance)?,
+        _ => end + distance,
+      };
+      jumps.push((code.len(), target));
+    }
+    positions.push(offset);
+    code.push(Instruction {
+      op,
+      operands,
+      position: chunk.positions[offset],
+      target: None,
+      offset,
+    });
+    offset = end;
+  }
+  positions.push(offset);
+  for (index, target) in jumps {
+    code[index].target = Some(positions.binary_search(&target).ok()?);
+  }
+  Some(code)
+}
+
+fn encode(code: &[Instruction], chunk: &mut Chunk) -> Vec<usize> {
+  let mut positions = Vec::with_capacity(code.len() + 1);
+  let mut offset = 0;
+  for instruction in code {
+    positions.push(offset);
+    offset += 1 + instruction.operands.len();
+  }
+  positions.push(offset);
+  let mut offsets = vec![0; chunk.code.len() + 1];
+  let mut next = code.len();
+  for (old, new) in offsets.iter_mut().enumerate().rev() {
+    while next > 0 && code[next - 1].offset >= old {
+      next -= 1;
+    }
+    *new = positions[next];
+  }
+  chunk.code.clear();
+  chunk.positions.clear();
+  for (index, instruction) in code.iter().enumerate() {
+    let mut bytes = vec![instruction.op as u8];
+    match instruction.target {
+      Some(target) => {
+        let end = positions[index + 1];
+        let distance = match instruction.op {
+          OpCode::Loop => end - positions[target],
+          _ => positions[target] - end,
+        };
+        bytes.extend([((distance >> 8) & 0xff) as u8, (distance & 0xff) as u8]);
+      }
+      None => bytes.extend(&instruction.operands),
+    }
+    chunk.write_buffer(bytes, instruction.position);
+  }
+  offsets
+}
+
+fn jump_targets(code: &[Instruction]) -> HashSet<usize> {
+  code
+    .iter()
+    .filter_map(|instruction| instruction.target)
+    .collect()
+}
+
+/// Elimina las instrucciones marcadas y reubica los saltos a la siguiente instruccion que se conserva
+fn retain(code: &mut Vec<Instruction>, removed: &[bool]) {
+  let mut new_index = Vec::with_capacity(code.len() + 1);
+  let mut count = 0;
+  for is_removed in removed {
+    new_index.push(count);
+    if !is_removed {
+      count += 1;
+    }
+  }
+  new_index.push(count);
+  let old = std::mem::take(code);
+  for (mut instruction, is_removed) in old.into_iter().zip(removed) {
+    if *is_removed {
+      continue;
+    }
+    instruction.target = instruction.target.map(|target| new_index[target]);
+    code.push(instruction);
+  }
+}
+
+fn remove_empty_scopes(code: &mut Vec<Instruction>) {
+  let mut removed = vec![false; code.len()];
+  // (inicio del bloque, si declara variables)
+  let mut scopes: Vec<(usize, bool)> = vec![];
+  for (index, instruction) in code.iter().enumerate() {
+    match instruction.op {
+      OpCode::NewLocals => scopes.push((index, false)),
+      OpCode::RemoveLocals => {
+        let (start, declares) = match scopes.pop() {
+          Some(scope) => scope,
+          None => return,
+        };
+        if !declares {
+          removed[start] = true;
+          removed[index] = true;
+        }
+      }
+      OpCode::VarDecl | OpCode::ConstDecl | OpCode::DeclLocal | OpCode::Import => {
+        if let Some(scope) = scopes.last_mut() {
+          scope.1 = true;
+        }
+      }
+      _ => {}
+    }
+  }
+  retain(code, &removed);
+}
+
+fn constant_number(instruction: &Instruction, chunk: &Chunk) -> Option<Number> {
+  if instruction.op != OpCode::Constant {
+    return None;
+  }
+  match chunk.constants.get(instruction.operand()) {
+    Value::Number(number) => Some(number.clone()),
+    _ => None,
+  }
+}
+
+/// Las operaciones que pueden fallar o tardar mucho se dejan para la ejecucion, aunque el codigo
+/// nunca se ejecute
+fn fold(op: OpCode, a: &Number, b: &Number) -> Option<Number> {
+  // Las potencias quedan como `1024E-0`, que no se puede guardar tal cual, se calculan al ejecutar
+  Some(match op {
+    OpCode::Add => a + b,
+    OpCode::Subtract => a - b,
+    OpCode::Multiply => a * b,
+    OpCode::Divide | OpCode::Modulo if b.is_zero() => return None,
+    OpCode::Divide => a / b,
+    OpCode::Modulo => a % b,
+    _ => return None,
+  })
+}
+
+fn add_constant(chunk: &mut Chunk, value: Value) -> Option<u8> {
+  if !chunk.constants.has_value(&value) && chunk.constants.len() == u8::MAX {
+    return None;
+  }
+  Some(chunk.add_constant(value))
+}
+
+fn fold_constants(code: &mut Vec<Instruction>, chunk: &mut Chunk) {
+  let mut index = 0;
+  while index < code.len() {
+    let targets = jump_targets(code);
+    let a = constant_number(&code[index], chunk);
+    let unary = code
+      .get(index + 1)
+      .filter(|_| !targets.contains(&(index + 1)));
+    let folded = match (a, unary.map(|instruction| instruction.op)) {
+      (Some(a), Some(OpCode::Negate)) => Some((-a, 2)),
+      (Some(a), Some(OpCode::Approximate)) => Some((a.trunc(), 2)),
+      (Some(a), Some(OpCode::Constant)) => {
+        let b = constant_number(&code[index + 1], chunk);
+        let binary = code
+          .get(index + 2)
+          .filter(|_| !targets.contains(&(index + 2)));
+        match (b, binary) {
+          (Some(b), Some(binary)) => fold(binary.op, &a, &b).map(|value| (value, 3)),
+          _ => None,
+        }
+      }
+      _ => None,
+    };
+    // Los numeros se guardan como texto, solo se pliegan los que se leen igual para que el
+    // programa muestre lo mismo que sin optimizar
+    let folded = folded.filter(|(value, _)| {
+      use super::traits::AsNumber as _;
+      let text = value.to_string();
+      let parsed = text.as_number::<Number>().ok();
+      parsed.is_some_and(|parsed| parsed == *value && parsed.to_string() == text)
+    });
+    let constant =
+      folded.and_then(|(value, len)| Some((add_constant(chunk, Value::Number(value))?, len)));
+    match constant {
+      Some((constant, len)) => {
+        code[index].operands = vec![constant];
+        let mut removed = vec![false; code.len()];
+        removed[index + 1..index + len].fill(true);
+        retain(code, &removed);
+        // La constante resultante puede formar parte de otra operacion
+        index = index.saturating_sub(2);
+      }
+      None => index += 1,
+    }
+  }
+}
+
+fn remove_dead_stores(code: &mut Vec<Instruction>, chunk: &Chunk) {
+  let mut used_slots: HashSet<u8> = code
+    .iter()
+    .filter(|instruction| instruction.op == OpCode::GetLocal)
+    .map(Instruction::operand)
+    .collect();
+  for (_, value) in chunk.constants.enumerate() {
+    if let Some(function) = constant_function(value) {
+      used_slots.extend(
+        function
+          .read()
+          .get_upvalues()
+          .iter()
+          .filter(|upvalue| upvalue.is_local)
+          .map(|upvalue| upvalue.index),
+      );
+    }
+  }
+  // Ambas dejan el valor en la pila, asi que se pueden quitar sin mas
+  let removed: Vec<bool> = code
+    .iter()
+    .map(|instruction| {
+      matches!(instruction.op, OpCode::DeclLocal | OpCode::SetLocal)
+        && !used_slots.contains(&instruction.operand())
+    })
+    .collect();
+  retain(code, &removed);
+
+  // Valores que se apilan solo para descartarse
+  let targets = jump_targets(code);
+  let mut removed = vec![false; code.len()];
+  for index in 1..code.len() {
+    if code[index].op != OpCode::Pop || targets.contains(&index) || removed[index - 1] {
+      continue;
+    }
+    let previous = &code[index - 1];
+    let is_pure = match previous.op {
+      OpCode::Constant => !matches!(chunk.constants.get(previous.operand()), Value::Lazy(_)),
+      OpCode::Copy => true,
+      _ => false,
+    };
+    if is_pure {
+      removed[index - 1] = true;
+      removed[index] = true;
+    }
+  }
+  retain(code, &removed);
+}
+
+fn remove_unreachable(code: &mut Vec<Instruction>) {
+  let targets = jump_targets(code);
+  let mut removed = vec![false; code.len()];
+  let mut reachable = true;
+  for (index, instruction) in code.iter().enumerate() {
+    if targets.contains(&index) {
+      reachable = true;
+    }
+    removed[index] = !reachable;
+    if matches!(
+      instruction.op,
+      OpCode::Return | OpCode::Throw | OpCode::Jump | OpCode::Loop
+    ) {
+      reachable = false;
+    }
+  }
+  retain(code, &removed);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::ChunkGroup;
+
+  fn number(text: &str) -> Number {
+    text.parse().ok().unwrap()
+  }
+  /// `a op b` como lo genera el compilador
+  fn binary(a: &str, b: &str, op: OpCode) -> Chunk {
+    let mut chunk = Chunk::new();
+    let position = Position::default();
+    let a = chunk.add_constant(Value::Number(number(a)));
+    chunk.write_buffer(vec![OpCode::Constant as u8, a], position);
+    let b = chunk.add_constant(Value::Number(number(b)));
+    chunk.write_buffer(vec![OpCode::Constant as u8, b], position);
+    chunk.write(op as u8, position);
+    chunk
+  }
+  /// Fragmento con las instrucciones y sus operandos tal cual, con la constante 1 en el indice 0
+  fn assemble(code: &[(OpCode, &[u8])]) -> Chunk {
+    let mut chunk = Chunk::new();
+    chunk.add_constant(Value::Number(number("1")));
+    for (op, operands) in code {
+      let mut bytes = vec![*op as u8];
+      bytes.extend(*operands);
+      chunk.write_buffer(bytes, Position::default());
+    }
+    chunk
+  }
+  fn ops(chunk: &Chunk) -> Vec<OpCode> {
+    decode(chunk)
+      .unwrap()
+      .into_iter()
+      .map(|instruction| instruction.op)
+      .collect()
+  }
+  fn folded(chunk: &Chunk) -> Option<Number> {
+    match chunk.code[..] {
+      [op, constant] if op == OpCode::Constant as u8 => match chunk.constants.get(constant) {
+        Value::Number(number) => Some(number.clone()),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn test_fold_arithmetic() {
+    let cases = [
+      ("2", "3", OpCode::Add, "5"),
+      ("2", "3", OpCode::Subtract, "-1"),
+      ("2", "3", OpCode::Multiply, "6"),
+      ("6", "3", OpCode::Divide, "2"),
+      ("7", "3", OpCode::Modulo, "1"),
+    ];
+    for (a, b, op, result) in cases {
+      let mut chunk = binary(a, b, op);
+      optimize(&mut chunk);
+      assert_eq!(folded(&chunk), Some(number(result)), "{a} {op:?} {b}");
+    }
+  }
+
+  #[test]
+  fn test_fold_keeps_division_by_zero() {
+    for op in [OpCode::Divide, OpCode::Modulo] {
+      let mut chunk = binary("1", "0", op);
+      let code = chunk.code.clone();
+      optimize(&mut chunk);
+      assert_eq!(chunk.code, code, "{op:?}");
+    }
+  }
+
+  #[test]
+  fn test_fold_keeps_powers() {
+    for (base, exponent) in [("2", "10"), ("1.5", "2"), ("2", "0.5"), ("2", "-1")] {
+      let mut chunk = binary(base, exponent, OpCode::Exponential);
+      let code = chunk.code.clone();
+      optimize(&mut chunk);
+      assert_eq!(chunk.code, code, "{base} ^ {exponent}");
+    }
+  }
+
+  #[test]
+  fn test_fold_unary_and_chains() {
+    let mut chunk = binary("2", "3", OpCode::Add);
+    let four = chunk.add_constant(Value::Number(number("4")));
+    chunk.write_buffer(vec![OpCode::Constant as u8, four], Position::default());
+    chunk.write(OpCode::Multiply as u8, Position::default());
+    chunk.write(OpCode::Negate as u8, Position::default());
+    optimize(&mut chunk);
+    assert_eq!(folded(&chunk), Some(number("-20")));
+
+    let mut chunk = Chunk::new();
+    let constant = chunk.add_constant(Value::Number(number("2.5")));
+    chunk.write_buffer(vec![OpCode::Constant as u8, constant], Position::default());
+    chunk.write(OpCode::Approximate as u8, Position::default());
+    optimize(&mut chunk);
+    assert_eq!(folded(&chunk), Some(number("2")));
+  }
+
+  #[test]
+  fn test_fold_keeps_jump_targets() {
+    // El salto llega a la suma, asi que la suma no puede desaparecer
+    let mut chunk = assemble(&[
+      (OpCode::Constant, &[0]),
+      (OpCode::JumpIfFalse, &[0, 4]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Add, &[]),
+    ]);
+    optimize(&mut chunk);
+    assert_eq!(
+      ops(&chunk),
+      [
+        OpCode::Constant,
+        OpCode::JumpIfFalse,
+        OpCode::Constant,
+        OpCode::Constant,
+        OpCode::Add,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_remove_empty_scopes() {
+    let mut chunk = assemble(&[
+      (OpCode::NewLocals, &[]),
+      (OpCode::NewLocals, &[]),
+      (OpCode::RemoveLocals, &[0]),
+      (OpCode::Constant, &[0]),
+      (OpCode::DeclLocal, &[0]),
+      (OpCode::GetLocal, &[0]),
+      (OpCode::Return, &[]),
+      (OpCode::RemoveLocals, &[0]),
+    ]);
+    optimize(&mut chunk);
+    assert_eq!(
+      ops(&chunk),
+      [
+        OpCode::NewLocals,
+        OpCode::Constant,
+        OpCode::DeclLocal,
+        OpCode::GetLocal,
+        OpCode::Return,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_remove_dead_stores() {
+    let mut chunk = assemble(&[
+      (OpCode::Constant, &[0]),
+      (OpCode::DeclLocal, &[0]),
+      (OpCode::Pop, &[]),
+      (OpCode::Constant, &[0]),
+      (OpCode::DeclLocal, &[1]),
+      (OpCode::Pop, &[]),
+      (OpCode::GetLocal, &[1]),
+      (OpCode::Return, &[]),
+    ]);
+    optimize(&mut chunk);
+    // La ranura 0 nunca se lee, su valor se apilaba solo para descartarse
+    assert_eq!(
+      ops(&chunk),
+      [
+        OpCode::Constant,
+        OpCode::DeclLocal,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::Return,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_remove_unreachable() {
+    let mut chunk = assemble(&[
+      (OpCode::Jump, &[0, 2]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+    ]);
+    optimize(&mut chunk);
+    assert_eq!(
+      ops(&chunk),
+      [OpCode::Jump, OpCode::Constant, OpCode::Return]
+    );
+    // El salto se reubica a la instruccion que sigue
+    assert_eq!(chunk.code[..3], [OpCode::Jump as u8, 0, 0]);
+  }
+
+  #[test]
+  fn test_offsets_of_removed_instructions() {
+    let mut chunk = binary("2", "3", OpCode::Add);
+    chunk.write(OpCode::Return as u8, Position::default());
+    // `Constant 2, Constant 3, Add, Return` queda como `Constant 5, Return`
+    assert_eq!(optimize(&mut chunk), [0, 2, 2, 2, 2, 2, 3]);
+  }
+
+  #[test]
+  fn test_group_keeps_local_names() {
+    let group = ChunkGroup::new_ref();
+    let mut group = group.write();
+    let position = Position::default();
+    group.write_constant(Value::Number(number("2")), position);
+    group.write_constant(Value::Number(number("3")), position);
+    group.write(OpCode::Add as u8, position);
+    group.name_local(0, "x");
+    group.write_buffer(vec![OpCode::DeclLocal as u8, 0], position);
+    group.write_buffer(vec![OpCode::GetLocal as u8, 0], position);
+    group.write(OpCode::Return as u8, position);
+    group.end_locals(0);
+    group.optimize();
+    // `Constant 5, DeclLocal 0, GetLocal 0, Return`
+    assert_eq!(group.len(), 7);
+    let names = |index| {
+      group
+        .locals_at(index)
+        .iter()
+        .map(|local| local.name.clone())
+        .collect::<Vec<_>>()
+    };
+    assert!(names(0).is_empty());
+    assert_eq!(names(2), ["x"]);
+    assert_eq!(names(6), ["x"]);
+    assert!(names(7).is_empty());
+  }
+
+  #[test]
+  fn test_group_with_several_chunks() {
+    let group = ChunkGroup::new_ref();
+    let mut group = group.write();
+    let position = Position::default();
+    for index in 0..300 {
+      group.write_constant(Value::Number(Number::from(index)), position);
+      group.write(OpCode::Pop as u8, position);
+    }
+    group.write_constant(Value::Number(number("2")), position);
+    group.write_constant(Value::Number(number("3")), position);
+    group.write(OpCode::Add as u8, position);
+    group.write(OpCode::Return as u8, position);
+    assert_eq!(group.chunks().len(), 2);
+    group.optimize();
+    // Los valores que se descartan se quitan de ambos fragmentos y la suma se pliega
+    assert!(group.chunks()[0].code.is_empty());
+    assert_eq!(ops(&group.chunks()[1]), [OpCode::Constant, OpCode::Return]);
+    assert_eq!(group.len(), 3);
+  }
+}
@@ -0,0 +1,427 @@
+use super::{FALSE_NAME, NEVER_NAME, NULL_NAME, TRUE_NAME};
+use crate::{Decode, Encode};
+
+const THIS_NAME: &str = "esto";
+const KEYWORDS: [&str; 5] = [FALSE_NAME, NULL_NAME, TRUE_NAME, NEVER_NAME, THIS_NAME];
+
+const MAX_SLOTS: usize = u8::MAX as usize + 1;
+
+/// Describe de donde obtiene una funcion cada valor capturado al crear su clausura
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Upvalue {
+  /// Si captura una ranura local de la funcion que la contiene o reutiliza una de sus capturas
+  pub is_local: bool,
+  pub index: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+  Local { slot: u8, is_const: bool },
+  Upvalue { index: u8, is_const: bool },
+  Global,
+}
+
+#[derive(Debug)]
+struct Local {
+  name: String,
+  depth: usize,
+  is_const: bool,
+  // Solo las funciones internas pueden ver una variable antes de su declaracion
+  ready: bool,
+}
+
+#[derive(Debug)]
+struct FunctionScope {
+  locals: Vec<Local>,
+  upvalues: Vec<Upvalue>,
+  depth: usize,
+  // Las declaraciones con una profundidad menor o igual son globales (nivel superior de un modulo)
+  global_depth: usize,
+}
+impl FunctionScope {
+  fn resolve_local(&self, name: &str, with_pending: bool) -> Option<(u8, bool)> {
+    self
+      .locals
+      .iter()
+      .enumerate()
+      .rev()
+      .find(|(_, local)| local.name == name && (with_pending || local.ready))
+      .map(|(slot, local)| (slot as u8, local.is_const))
+  }
+  fn add_upvalue(&mut self, upvalue: Upvalue) -> Result<u8, String> {
+    if let Some(index) = self.upvalues.iter().position(|u| *u == upvalue) {
+      return Ok(index as u8);
+    }
+    if self.upvalues.len() == MAX_SLOTS {
+      return Err("Demasiadas variables capturadas en una funcion".to_string());
+    }
+    self.upvalues.push(upvalue);
+    Ok((self.upvalues.len() - 1) as u8)
+  }
+}
+
+/// Asigna en tiempo de compilacion la ubicacion de cada identificador:
+/// una ranura local de la funcion, una captura de una funcion externa o un nombre global
+#[derive(Debug, Default)]
+pub struct Resolver {
+  functions: Vec<FunctionScope>,
+}
+impl Resolver {
+  pub fn begin_module(&mut self) {
+    self.functions.push(FunctionScope {
+      locals: vec![],
+      upvalues: vec![],
+      depth: 0,
+      global_depth: 1,
+    });
+  }
+  pub fn begin_function(&mut self) {
+    self.functions.push(FunctionScope {
+      locals: vec![],
+      upvalues: vec![],
+      depth: 1,
+      global_depth: 0,
+    });
+  }
+  pub fn end_function(&mut self) -> Vec<Upvalue> {
+    self
+      .functions
+      .pop()
+      .map(|function| function.upvalues)
+      .unwrap_or_default()
+  }
+  fn current(&mut self) -> &mut FunctionScope {
+    self.functions.last_mut().unwrap()
+  }
+  pub fn begin_scope(&mut self) {
+    self.current().depth += 1;
+  }
+  /// Cierra el bloque actual y retorna la cantidad de ranuras que siguen vivas
+  pub fn end_scope(&mut self) -> u8 {
+    let function = self.current();
+    while function
+      .locals
+      .last()
+      .is_some_and(|local| local.depth >= function.depth)
+    {
+      function.locals.pop();
+    }
+    function.depth -= 1;
+    function.locals.len() as u8
+  }
+  pub fn is_global_scope(&mut self) -> bool {
+    let function = self.current();
+    function.depth <= function.global_depth
+  }
+  /// Retorna la ranura asignada o `None` si la declaracion es global
+  pub fn declare(&mut self, name: &str, is_const: bool) -> Result<Option<u8>, String> {
+    self.push_local(name, is_const, true)
+  }
+  /// Reserva la ranura de una variable del bloque antes de llegar a su declaracion
+  pub fn declare_pending(&mut self, name: &str, is_const: bool) -> Result<Option<u8>, String> {
+    self.push_local(name, is_const, false)
+  }
+  /// Marca como declarada una variable reservada del bloque actual
+  pub fn define(&mut self, name: &str) -> Option<u8> {
+    let function = self.current();
+    let depth = function.depth;
+    let slot = function
+      .locals
+      .iter()
+      .rposition(|local| local.depth == depth && local.name == name && !local.ready)?;
+    function.locals[slot].ready = true;
+    Some(slot as u8)
+  }
+  fn push_local(&mut self, name: &str, is_const: bool, ready: bool) -> Result<Option<u8>, String> {
+    if KEYWORDS.contains(&name) {
+      return Err(format!("No se pudo declarar la variable '{name}'"));
+    }
+    if self.is_global_scope() {
+      return Ok(None);
+    }
+    let function = self.current();
+    let depth = function.depth;
+    if function
+      .locals
+      .iter()
+      .any(|local| local.depth == depth && local.name == name)
+    {
+      return Err(format!(
+        "La variable '{name}' ya fue declarada en este bloque"
+      ));
+    }
+    if function.locals.len() == MAX_SLOTS {
+      return Err("Demasiadas variables locales en una funcion".to_string());
+    }
+    function.locals.push(Local {
+      name: name.to_string(),
+      depth,
+      is_const,
+      ready,
+    });
+    Ok(Some((function.locals.len() - 1) as u8))
+  }
+  /// Retorna la ranura de una variable declarada en el bloque actual
+  pub fn declared_in_scope(&mut self, name: &str) -> Option<u8> {
+    let function = self.current();
+    let depth = function.depth;
+    function
+      .locals
+      .iter()
+      .rposition(|local| local.depth == depth && local.name == name)
+      .map(|slot| slot as u8)
+  }
+  pub fn resolve(&mut self, name: &str) -> Result<Resolution, String> {
+    if KEYWORDS.contains(&name) || self.functions.is_empty() {
+      return Ok(Resolution::Global);
+    }
+    let level = self.functions.len() - 1;
+    if let Some((slot, is_const)) = self.functions[level].resolve_local(name, false) {
+      return Ok(Resolution::Local { slot, is_const });
+    }
+    Ok(match self.resolve_upvalue(level, name)? {
+      Some((index, is_const)) => Resolution::Upvalue { index, is_const },
+      None => Resolution::Global,
+    })
+  }
+  fn resolve_upvalue(&mut self, level: usize, name: &str) -> Result<Option<(u8, bool)>, String> {
+    if level == 0 {
+      return Ok(None);
+    }
+    let enclosing = level - 1;
+    if let Some((slot, is_const)) = self.functions[enclosing].resolve_local(name, true) {
+      let index = self.functions[level].add_upvalue(Upvalue {
+        is_local: true,
+        index: slot,
+      })?;
+      return Ok(Some((index, is_const)));
+    }
+    Ok(match self.resolve_upvalue(enclosing, name)? {
+      Some((upvalue, is_const)) => {
+        let index = self.functions[level].add_upvalue(Upvalue {
+          is_local: false,
+          index: upvalue,
+        })?;
+        Some((index, is_const))
+      }
+      None => None,
+    })
+  }
+}
+
+impl Encode for Upvalue {
+  fn encode(&self) -> Result<Vec<u8>, String> {
+    let mut encode = self.is_local.encode()?;
+    encode.extend((self.index as usize).encode()?);
+    Ok(encode)
+  }
+}
+impl Decode for Upvalue {
+  fn decode(vec: &mut std::collections::VecDeque<u8>) -> Result<Self, String> {
+    let is_local = bool::decode(vec)?;
+    let index = usize::decode(vec)?;
+    if index >= MAX_SLOTS {
+      return Err("Binario corrupto".to_string());
+    }
+    Ok(Self {
+      is_local,
+      index: index as u8,
+    })
+  }
+}
+impl Encode for Vec<Upvalue> {
+  fn encode(&self) -> Result<Vec<u8>, String> {
+    let mut encode = self.len().encode()?;
+    for upvalue in self {
+      encode.extend(upvalue.encode()?);
+    }
+    Ok(encode)
+  }
+}
+impl Decode for Vec<Upvalue> {
+  fn decode(vec: &mut std::collections::VecDeque<u8>) -> Result<Self, String> {
+    let len = usize::decode(vec)?;
+    if len > MAX_SLOTS {
+      return Err("Binario corrupto".to_string());
+    }
+    let mut upvalues = Vec::with_capacity(len);
+    for _ in 0..len {
+      upvalues.push(Upvalue::decode(vec)?);
+    }
+    Ok(upvalues)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn local(slot: u8) -> Resolution {
+    Resolution::Local {
+      slot,
+      is_const: false,
+    }
+  }
+
+  #[test]
+  fn test_module_scope_is_global() {
+    let mut resolver = Resolver::default();
+    resolver.begin_module();
+    // El cuerpo del modulo
+    resolver.begin_scope();
+    assert!(resolver.is_global_scope());
+    assert_eq!(resolver.declare("a", false), Ok(None));
+    assert_eq!(resolver.resolve("a"), Ok(Resolution::Global));
+    // Un bloque dentro del modulo ya tiene variables locales
+    resolver.begin_scope();
+    assert!(!resolver.is_global_scope());
+    assert_eq!(resolver.declare("b", false), Ok(Some(0)));
+    assert_eq!(resolver.resolve("b"), Ok(local(0)));
+    assert_eq!(resolver.end_scope(), 0);
+    assert_eq!(resolver.resolve("b"), Ok(Resolution::Global));
+  }
+
+  #[test]
+  fn test_function_slots_and_shadowing() {
+    let mut resolver = Resolver::default();
+    resolver.begin_module();
+    resolver.begin_function();
+    assert_eq!(resolver.declare("a", false), Ok(Some(0)));
+    assert_eq!(resolver.declare("b", true), Ok(Some(1)));
+    resolver.begin_scope();
+    assert_eq!(resolver.declare("a", true), Ok(Some(2)));
+    assert_eq!(
+      resolver.resolve("a"),
+      Ok(Resolution::Local {
+        slot: 2,
+        is_const: true
+      })
+    );
+    assert_eq!(resolver.end_scope(), 2);
+    assert_eq!(resolver.resolve("a"), Ok(local(0)));
+    assert_eq!(resolver.resolve("c"), Ok(Resolution::Global));
+    assert_eq!(resolver.end_function(), vec![]);
+  }
+
+  #[test]
+  fn test_declaration_errors() {
+    let mut resolver = Resolver::default();
+    resolver.begin_function();
+    resolver.declare("a", false).unwrap();
+    assert!(resolver.declare("a", false).is_err());
+    assert!(resolver.declare(THIS_NAME, false).is_err());
+    assert!(resolver.declare(NULL_NAME, false).is_err());
+    assert_eq!(resolver.resolve(THIS_NAME), Ok(Resolution::Global));
+  }
+
+  #[test]
+  fn test_slot_limit() {
+    let mut resolver = Resolver::default();
+    resolver.begin_function();
+    for index in 0..MAX_SLOTS {
+      resolver.declare(&format!("v{index}"), false).unwrap();
+    }
+    assert!(resolver.declare("otra", false).is_err());
+  }
+
+  #[test]
+  fn test_pending_declarations() {
+    let mut resolver = Resolver::default();
+    resolver.begin_function();
+    assert_eq!(resolver.declare_pending("f", true), Ok(Some(0)));
+    // Antes de su declaracion solo la ven las funciones internas
+    assert_eq!(resolver.resolve("f"), Ok(Resolution::Global));
+    resolver.begin_function();
+    assert_eq!(
+      resolver.resolve("f"),
+      Ok(Resolution::Upvalue {
+        index: 0,
+        is_const: true
+      })
+    );
+    resolver.end_function();
+    assert_eq!(resolver.declared_in_scope("f"), Some(0));
+    assert_eq!(resolver.define("f"), Some(0));
+    assert_eq!(
+      resolver.resolve("f"),
+      Ok(Resolution::Local {
+        slot: 0,
+        is_const: true
+      })
+    );
+    assert_eq!(resolver.define("f"), None);
+  }
+
+  #[test]
+  fn test_upvalues() {
+    let mut resolver = Resolver::default();
+    resolver.begin_module();
+    resolver.begin_function();
+    resolver.declare("a", false).unwrap();
+    resolver.declare("b", false).unwrap();
+    resolver.begin_function();
+    resolver.begin_function();
+    // La funcion mas interna reutiliza la captura de la intermedia
+    let upvalue = |index| Resolution::Upvalue {
+      index,
+      is_const: false,
+    };
+    assert_eq!(resolver.resolve("b"), Ok(upvalue(0)));
+    assert_eq!(resolver.resolve("a"), Ok(upvalue(1)));
+    assert_eq!(resolver.resolve("b"), Ok(upvalue(0)));
+    assert_eq!(
+      resolver.end_function(),
+      vec![
+        Upvalue {
+          is_local: false,
+          index: 0
+        },
+        Upvalue {
+          is_local: false,
+          index: 1
+        },
+      ]
+    );
+    assert_eq!(
+      resolver.end_function(),
+      vec![
+        Upvalue {
+          is_local: true,
+          index: 1
+        },
+        Upvalue {
+          is_local: true,
+          index: 0
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_upvalue_round_trip() {
+    let upvalues = vec![
+      Upvalue {
+        is_local: true,
+        index: 3,
+      },
+      Upvalue {
+        is_local: false,
+        index: 255,
+      },
+    ];
+    let mut bytes = upvalues.encode().unwrap().into();
+    assert_eq!(Vec::<Upvalue>::decode(&mut bytes), Ok(upvalues));
+
+    let mut bytes = vec![Upvalue {
+      is_local: true,
+      index: 0,
+    }]
+    .encode()
+    .unwrap();
+    // El indice ocupa los ultimos bytes, uno fuera de rango no se acepta
+    let index_len = 0usize.encode().unwrap().len();
+    let len = bytes.len();
+    bytes.splice(len - index_len.., (MAX_SLOTS).encode().unwrap());
+    assert!(Vec::<Upvalue>::decode(&mut bytes.into()).is_err());
+  }
+}
@@ -31,6 +31,18 @@ impl Instance {
   pub fn get_type(&self) -> &str {
     &self.name
   }
+  /// Las propiedades propias, sin las de la instancia que extiende ni las internas
+  pub fn properties(&self) -> Vec<(String, Value)> {
+    let mut properties: Vec<(String, Value)> = self
+      .poperties
+      .read()
+      .iter()
+      .filter(|(key, _)| key.as_str() != SUPER && key.as_str() != CONSTRUCTOR)
+      .map(|(key, value)| (key.clone(), value.clone()))
+      .collect();
+    properties.sort_by(|(a, _), (b, _)| a.cmp(b));
+    properties
+  }
   pub fn get_instance_property(
     &self,
     key: &str,
@@ -98,6 +110,12 @@ impl Instance {
       }
     }
   }
+  pub fn get_parent(&self) -> Option<Instance> {
+    self.extend.cloned()
+  }
+  pub fn values(&self) -> Vec<Value> {
+    self.poperties.read().values().cloned().collect()
+  }
   pub fn ovwerwrite_instance_property(&self, key: &str, value: Value) {
     self
       .poperties
@@ -155,6 +173,9 @@ impl Class {
   pub fn get_instance_property(&self, key: &str) -> Option<Value> {
     self.poperties.read().get(key).cloned()
   }
+  pub fn values(&self) -> Vec<Value> {
+    self.poperties.read().values().cloned().collect()
+  }
   pub fn make_instance(&self) -> Value {
     self
       .extend
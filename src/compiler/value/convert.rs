@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use super::{Number, Object, RealNumber, Value};
+
+/// Convierte un valor de Rust en un valor del interprete
+pub trait IntoValue {
+  fn into_value(self) -> Value;
+}
+/// Convierte un valor del interprete en un valor de Rust, falla si el tipo no coincide
+pub trait FromValue: Sized {
+  fn from_value(value: Value) -> Result<Self, String>;
+}
+
+fn type_error(expected: &str, value: &Value) -> String {
+  format!(
+    "Se esperaba un '{expected}' pero se recibio un {}",
+    value.get_type()
+  )
+}
+/// Las referencias y los valores vagos ya calculados se convierten segun su contenido
+fn unwrap(value: Value) -> Value {
+  match value {
+    Value::Ref(reference) => unwrap(reference.borrow().clone()),
+    Value::Lazy(lazy) => unwrap(lazy.get().clone().unwrap_or_default()),
+    value => value,
+  }
+}
+
+impl IntoValue for Value {
+  fn into_value(self) -> Value {
+    self
+  }
+}
+impl FromValue for Value {
+  fn from_value(value: Value) -> Result<Self, String> {
+    Ok(value)
+  }
+}
+
+impl IntoValue for () {
+  fn into_value(self) -> Value {
+    Value::Never
+  }
+}
+impl FromValue for () {
+  fn from_value(_: Value) -> Result<Self, String> {
+    Ok(())
+  }
+}
+
+impl IntoValue for bool {
+  fn into_value(self) -> Value {
+    self.into()
+  }
+}
+impl FromValue for bool {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::True => Ok(true),
+      Value::False => Ok(false),
+      value => Err(type_error(super::BOOLEAN_TYPE, &value)),
+    }
+  }
+}
+
+impl IntoValue for char {
+  fn into_value(self) -> Value {
+    self.into()
+  }
+}
+impl FromValue for char {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::Char(char) => Ok(char),
+      value => Err(type_error(super::CHAR_TYPE, &value)),
+    }
+  }
+}
+
+impl IntoValue for String {
+  fn into_value(self) -> Value {
+    Value::String(self)
+  }
+}
+impl IntoValue for &str {
+  fn into_value(self) -> Value {
+    self.into()
+  }
+}
+impl FromValue for String {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::String(string) => Ok(string),
+      value => Err(type_error(super::STRING_TYPE, &value)),
+    }
+  }
+}
+
+impl IntoValue for Number {
+  fn into_value(self) -> Value {
+    self.into()
+  }
+}
+impl FromValue for Number {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::Number(number) => Ok(number),
+      value => Err(type_error(super::NUMBER_TYPE, &value)),
+    }
+  }
+}
+
+impl IntoValue for f64 {
+  fn into_value(self) -> Value {
+    let number = if self.is_nan() {
+      Number::NaN
+    } else if self.is_infinite() {
+      if self.is_sign_negative() {
+        Number::NegativeInfinity
+      } else {
+        Number::Infinity
+      }
+    } else {
+      // Desde el texto para obtener el decimal mas corto, no la expansion binaria completa
+      self.to_string().parse().unwrap_or(Number::NaN)
+    };
+    Value::Number(number)
+  }
+}
+impl FromValue for f64 {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match Number::from_value(value)? {
+      Number::NaN => Ok(f64::NAN),
+      Number::Infinity => Ok(f64::INFINITY),
+      Number::NegativeInfinity => Ok(f64::NEG_INFINITY),
+      Number::Real(real) => real
+        .to_string()
+        .parse()
+        .map_err(|_| format!("No se puede convertir el numero '{real}' a decimal")),
+      // Los numeros complejos no tienen equivalente
+      number => Err(format!(
+        "No se puede convertir el numero complejo '{number}' a decimal"
+      )),
+    }
+  }
+}
+
+macro_rules! impl_integer {
+  ($($type:ty),*) => {
+    $(
+      impl IntoValue for $type {
+        fn into_value(self) -> Value {
+          #[allow(unused_comparisons)]
+          let is_negative = self < 0;
+          Value::Number(Number::Real(RealNumber::Int(
+            is_negative,
+            (self as i128).unsigned_abs().into(),
+          )))
+        }
+      }
+      impl FromValue for $type {
+        fn from_value(value: Value) -> Result<Self, String> {
+          match Number::from_value(value)? {
+            Number::Real(real) if real.is_int() => real.trunc().to_string().parse().map_err(|_| {
+              format!(
+                "El numero '{real}' no cabe en un {}",
+                stringify!($type)
+              )
+            }),
+            number => Err(format!("Se esperaba un numero entero pero se recibio '{number}'")),
+          }
+        }
+      }
+    )*
+  };
+}
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+  fn into_value(self) -> Value {
+    let values: Vec<Value> = self.into_iter().map(IntoValue::into_value).collect();
+    Value::Object(values.into())
+  }
+}
+impl<T: FromValue> FromValue for Vec<T> {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::Object(Object::Array(list)) => {
+        let values = list.read().clone();
+        values.into_iter().map(T::from_value).collect()
+      }
+      value => Err(type_error(super::LIST_TYPE, &value)),
+    }
+  }
+}
+
+/// `None` es `nulo`, y tanto `nulo` como `nada` son `None`
+impl<T: IntoValue> IntoValue for Option<T> {
+  fn into_value(self) -> Value {
+    match self {
+      Some(value) => value.into_value(),
+      None => Value::Null,
+    }
+  }
+}
+impl<T: FromValue> FromValue for Option<T> {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::Null | Value::Never => Ok(None),
+      value => T::from_value(value).map(Some),
+    }
+  }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+  fn into_value(self) -> Value {
+    let properties: HashMap<String, Value> = self
+      .into_iter()
+      .map(|(key, value)| (key, value.into_value()))
+      .collect();
+    Value::Object(properties.into())
+  }
+}
+/// Solo las propiedades del objeto, no las de su instancia
+impl<T: FromValue> FromValue for HashMap<String, T> {
+  fn from_value(value: Value) -> Result<Self, String> {
+    match unwrap(value) {
+      Value::Object(Object::Map(properties, _)) => {
+        let properties = properties.read().clone();
+        properties
+          .into_iter()
+          .map(|(key, value)| Ok((key, T::from_value(value)?)))
+          .collect()
+      }
+      value => Err(type_error(super::MAP_TYPE, &value)),
+    }
+  }
+}
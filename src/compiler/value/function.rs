@@ -1,10 +1,10 @@
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::{Class, Value};
-use crate::compiler::Promise;
+use crate::compiler::{Promise, Upvalue};
 use crate::interpreter::VarsManager;
-use crate::util::{Color, Location, MutClone};
+use crate::util::{Color, Location, MutClone, Position};
 use crate::{compiler::ChunkGroup, Decode, MultiRefHash, StructTag};
 
 pub const FUNCTION_TYPE: &str = "funcion";
@@ -16,6 +16,12 @@ pub enum NativeValue {
   TcpStream(std::net::TcpStream),
   Promise(Promise),
   ValuePromise(MultiRefHash<NativeValue>, Promise),
+  /// Identificador de un temporizador de `:tmp`
+  Timer(usize),
+  /// Funcion de una extension cargada con `:nativo/`
+  Extension(crate::ffi::extension::ExtensionFn),
+  /// Funcion registrada por un programa en C con `agal_vm_register`
+  Callback(crate::ffi::HostCallback),
 }
 impl NativeValue {
   pub fn mut_tcp_stream(&mut self) -> Option<&mut std::net::TcpStream> {
@@ -30,6 +36,12 @@ impl NativeValue {
       _ => None,
     }
   }
+  pub fn get_timer(&self) -> Option<usize> {
+    match self {
+      Self::Timer(id) => Some(*id),
+      _ => None,
+    }
+  }
   pub fn get_promise(&mut self) -> Option<&mut Promise> {
     match self {
       Self::Promise(promise) => Some(promise),
@@ -58,7 +70,7 @@ impl From<(MultiRefHash<NativeValue>, Promise)> for MultiRefHash<NativeValue> {
     MultiRefHash::new(NativeValue::ValuePromise(values.0, values.1))
   }
 }
-type NativeFn = fn(
+pub type NativeFn = fn(
   Value,
   Vec<Value>,
   &mut crate::interpreter::Thread,
@@ -76,11 +88,15 @@ pub enum Function {
     location: crate::util::Location,
     scope: MultiRefHash<Option<MultiRefHash<VarsManager>>>,
     has_rest: bool,
+    upvalues: Vec<Upvalue>,
+    captures: Vec<MultiRefHash<Value>>,
   },
   Script {
     chunk: MultiRefHash<ChunkGroup>,
     path: PathBuf,
     scope: MultiRefHash<Option<MultiRefHash<VarsManager>>>,
+    upvalues: Vec<Upvalue>,
+    captures: Vec<MultiRefHash<Value>>,
   },
   Native {
     name: String,
@@ -119,6 +135,8 @@ impl PartialEq for Function {
           is_async: asa,
           location: la,
           scope: sa,
+          upvalues: ua,
+          captures: cpa,
         },
         Function::Value {
           arity: ab,
@@ -129,6 +147,8 @@ impl PartialEq for Function {
           is_async: asb,
           location: lb,
           scope: sb,
+          upvalues: ub,
+          captures: cpb,
         },
       ) => {
         aa == ab
@@ -139,19 +159,25 @@ impl PartialEq for Function {
           && asa == asb
           && la == lb
           && sa == sb
+          && ua == ub
+          && cpa == cpb
       }
       (
         Function::Script {
           path: pa,
           chunk: ca,
           scope: sa,
+          upvalues: ua,
+          captures: cpa,
         },
         Function::Script {
           path: pb,
           chunk: cb,
           scope: sb,
+          upvalues: ub,
+          captures: cpb,
         },
-      ) => pa == pb && ca == cb && sa == sb,
+      ) => pa == pb && ca == cb && sa == sb && ua == ub && cpa == cpb,
       _ => false,
     }
   }
@@ -195,6 +221,105 @@ impl Function {
       Self::Native { .. } => None,
     }
   }
+  pub fn set_upvalues(&mut self, list: Vec<Upvalue>) {
+    match self {
+      Self::Value { upvalues, .. } | Self::Script { upvalues, .. } => *upvalues = list,
+      Self::Native { .. } => {}
+    }
+  }
+  pub fn get_upvalues(&self) -> &[Upvalue] {
+    match self {
+      Self::Value { upvalues, .. } | Self::Script { upvalues, .. } => upvalues,
+      Self::Native { .. } => &[],
+    }
+  }
+  pub fn get_captures(&self) -> &[MultiRefHash<Value>] {
+    match self {
+      Self::Value { captures, .. } | Self::Script { captures, .. } => captures,
+      Self::Native { .. } => &[],
+    }
+  }
+  /// Suelta el entorno, las capturas y la clase de una clausura que ya no se puede usar,
+  /// asi se rompen los ciclos que la mantenian viva
+  pub fn release(&mut self) {
+    match self {
+      Self::Value {
+        scope,
+        captures,
+        in_class,
+        ..
+      } => {
+        *scope.write() = None;
+        *in_class.write() = None;
+        captures.clear();
+      }
+      Self::Script {
+        scope, captures, ..
+      } => {
+        *scope.write() = None;
+        captures.clear();
+      }
+      Self::Native { .. } => {}
+    }
+  }
+  pub fn get_capture(&self, index: u8) -> Option<MultiRefHash<Value>> {
+    match self {
+      Self::Value { captures, .. } | Self::Script { captures, .. } => {
+        captures.get(index as usize).cloned()
+      }
+      Self::Native { .. } => None,
+    }
+  }
+  /// Crea una nueva clausura que comparte el codigo de la funcion,
+  /// pero con su propio entorno y sus propias capturas
+  pub fn close_over(
+    &self,
+    vars: MultiRefHash<VarsManager>,
+    cells: Vec<MultiRefHash<Value>>,
+  ) -> Self {
+    let mut closure = match self {
+      Self::Value {
+        arity,
+        chunk,
+        name,
+        is_async,
+        in_class,
+        location,
+        has_rest,
+        upvalues,
+        ..
+      } => Self::Value {
+        arity: *arity,
+        chunk: chunk.clone(),
+        name: name.clone(),
+        is_async: *is_async,
+        in_class: in_class.cloned().into(),
+        location: location.clone(),
+        scope: None.into(),
+        has_rest: *has_rest,
+        upvalues: upvalues.clone(),
+        captures: vec![],
+      },
+      Self::Script {
+        chunk,
+        path,
+        upvalues,
+        ..
+      } => Self::Script {
+        chunk: chunk.clone(),
+        path: path.clone(),
+        scope: None.into(),
+        upvalues: upvalues.clone(),
+        captures: vec![],
+      },
+      Self::Native { .. } => return self.clone(),
+    };
+    if let Self::Value { captures, .. } | Self::Script { captures, .. } = &mut closure {
+      *captures = cells;
+    }
+    closure.set_scope(vars);
+    closure
+  }
   pub fn chunk(&self) -> MultiRefHash<ChunkGroup> {
     match self {
       Self::Value { chunk, .. } => chunk.clone(),
@@ -203,7 +328,20 @@ impl Function {
     }
   }
   pub fn location(&self) -> String {
+    self.location_at(None)
+  }
+  /// Como `location` pero con la posicion de la instruccion que se esta ejecutando en lugar de
+  /// la posicion donde se declaro la funcion
+  pub fn location_at(&self, position: Option<Position>) -> String {
     use crate::util::SetColor as _;
+    let at = |file: String, position: Position| {
+      format!(
+        "{}:{}:{}",
+        file.set_color(Color::Cyan),
+        (position.line + 1).to_string().set_color(Color::Yellow),
+        (position.column + 1).to_string().set_color(Color::Yellow)
+      )
+    };
     match self {
       Self::Value {
         name,
@@ -211,31 +349,25 @@ impl Function {
         location,
         ..
       } => format!(
-        "en {} <{}:{}:{}>",
+        "en {} <{}>",
         if *is_async {
           format!("asinc {name}")
         } else {
           name.to_string()
         },
-        location
-          .file_name
-          .to_string_lossy()
-          .to_string()
-          .set_color(Color::Cyan),
-        (location.start.line + 1)
-          .to_string()
-          .set_color(Color::Yellow),
-        (location.start.column + 1)
-          .to_string()
-          .set_color(Color::Yellow)
+        at(
+          location.file_name.to_string_lossy().to_string(),
+          position.unwrap_or(location.start)
+        )
       ),
-      Self::Script { path, .. } => {
-        format!(
+      Self::Script { path, .. } => match position {
+        Some(position) => format!("en <{}>", at(path.to_string_lossy().to_string(), position)),
+        None => format!(
           "en <{}:{}>",
           path.to_string_lossy().to_string().set_color(Color::Cyan),
           "script".to_string().set_color(Color::Gray)
-        )
-      }
+        ),
+      },
       Self::Native { path, name, .. } => {
         if path.is_empty() {
           return format!(
@@ -251,6 +383,14 @@ impl Function {
       }
     }
   }
+  /// Archivo con el codigo fuente de la funcion, las funciones nativas no tienen
+  pub fn source_path(&self) -> Option<&Path> {
+    match self {
+      Self::Value { location, .. } => Some(&location.file_name),
+      Self::Script { path, .. } => Some(path),
+      Self::Native { .. } => None,
+    }
+  }
 }
 impl Display for Function {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -275,6 +415,8 @@ impl std::hash::Hash for Function {
         location,
         scope,
         has_rest,
+        upvalues,
+        captures,
       } => {
         arity.hash(state);
         chunk.hash(state);
@@ -284,11 +426,21 @@ impl std::hash::Hash for Function {
         location.hash(state);
         scope.hash(state);
         has_rest.hash(state);
+        upvalues.hash(state);
+        captures.hash(state);
       }
-      Function::Script { chunk, path, scope } => {
+      Function::Script {
+        chunk,
+        path,
+        scope,
+        upvalues,
+        captures,
+      } => {
         chunk.hash(state);
         path.hash(state);
         scope.hash(state);
+        upvalues.hash(state);
+        captures.hash(state);
       }
       Function::Native {
         name,
@@ -322,6 +474,7 @@ impl crate::Encode for Function {
         is_async,
         location,
         has_rest,
+        upvalues,
         .. /* El resto se calcula de forma dinamica */
       } => {
         encode.push(0);
@@ -331,11 +484,13 @@ impl crate::Encode for Function {
         encode.extend(is_async.encode()?);
         encode.extend(location.encode()?);
         encode.extend(has_rest.encode()?);
+        encode.extend(upvalues.encode()?);
       }
-      Function::Script { chunk, path, .. } => {
+      Function::Script { chunk, path, upvalues, .. } => {
         encode.push(1);
         encode.extend(path.encode()?);
         encode.extend(chunk.read().encode()?);
+        encode.extend(upvalues.encode()?);
       }
       // Tecnicamente no se deberia llegar a este punto
       Function::Native { .. } => return Err("No se puede compilar una funcion nativa".to_string()),
@@ -370,11 +525,15 @@ impl Decode for Function {
         is_async: bool::decode(vec)?,
         location: Location::decode(vec)?,
         has_rest: bool::decode(vec)?,
+        upvalues: Vec::decode(vec)?,
+        captures: vec![],
       }),
       1 => Ok(Self::Script {
         scope: Default::default(),
         path: PathBuf::decode(vec)?,
         chunk: ChunkGroup::decode(vec)?.into(),
+        upvalues: Vec::decode(vec)?,
+        captures: vec![],
       }),
       _ => Err("Se esperaba una funcion".to_string()),
     }
@@ -2,11 +2,13 @@ use std::fmt::Display;
 use std::path::PathBuf;
 
 mod class;
+mod convert;
 mod function;
 mod number;
 mod object;
 mod promise;
 pub use class::{Class, Instance};
+pub use convert::{FromValue, IntoValue};
 pub use function::*;
 pub use number::*;
 pub use object::*;
@@ -37,6 +39,9 @@ impl RefValue {
   pub fn borrow(&'_ self) -> std::sync::RwLockReadGuard<'_, Value> {
     self.0.read()
   }
+  pub fn get_cell(&self) -> MultiRefHash<Value> {
+    self.0.clone()
+  }
 }
 impl From<Value> for RefValue {
   fn from(value: Value) -> Self {
@@ -124,10 +129,21 @@ impl Value {
       Self::Object(o) => o.get_type(),
     }
   }
-  pub fn set_scope(&self, vars: MultiRefHash<VarsManager>) {
+  pub fn get_upvalues(&self) -> Vec<super::Upvalue> {
+    match self {
+      Self::Object(Object::Function(f)) => f.read().get_upvalues().to_vec(),
+      Self::Lazy(LazyValue { once, .. }) => once.read().get_upvalues().to_vec(),
+      _ => vec![],
+    }
+  }
+  pub fn close_over(
+    &self,
+    vars: MultiRefHash<VarsManager>,
+    cells: Vec<MultiRefHash<Value>>,
+  ) -> Self {
     match self {
-      Self::Object(Object::Function(f)) => f.read().set_scope(vars),
-      Self::Lazy(LazyValue { once, .. }) => once.read().set_scope(vars),
+      Self::Object(Object::Function(f)) => Self::Object(f.read().close_over(vars, cells).into()),
+      Self::Lazy(LazyValue { once, .. }) => Self::Lazy(once.read().close_over(vars, cells).into()),
       _ => panic!(
         "Error: no se puede establecer una variable local en un valor de tipo {}",
         self.get_type()
@@ -277,6 +293,8 @@ impl Value {
         chunk: Default::default(),
         scope: None.into(),
         path: PathBuf::from("<nulo>".to_string()),
+        upvalues: vec![],
+        captures: vec![],
       }
       .into(),
     }
@@ -570,6 +588,10 @@ impl Decode for Value {
           Default::default(),
         )))
       }
+      StructTag::Array => {
+        vec.pop_front();
+        Ok(Self::Object(Object::Array(Default::default())))
+      }
       StructTag::Class => {
         vec.pop_front();
         Ok(Self::Object(Object::Class(Class::new(String::decode(
@@ -595,7 +617,7 @@ impl Decode for Value {
 }
 impl MutClone for Value {}
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct ValueArray {
   values: Vec<Value>,
 }
@@ -614,6 +636,9 @@ impl ValueArray {
   pub fn len(&self) -> u8 {
     self.values.len() as u8
   }
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
   pub fn get(&self, index: u8) -> &Value {
     self.values.get(index as usize).unwrap_or_else(|| {
       panic!(
@@ -16,6 +16,9 @@ impl Big256 {
   pub fn len(&self) -> usize {
     self.digits.len()
   }
+  pub fn is_empty(&self) -> bool {
+    self.digits.is_empty()
+  }
   pub fn new(digits: Vec<u8>) -> Self {
     let mut d = Self { digits };
     d.normalize();
@@ -58,6 +58,9 @@ impl BigUDecimal {
   pub fn len(&self) -> usize {
     self.mantissa.len()
   }
+  pub fn is_empty(&self) -> bool {
+    self.mantissa.is_empty()
+  }
   pub fn get_exponent(&self) -> u8 {
     self.exponent
   }
@@ -5,6 +5,8 @@ use crate::MultiRefHash;
 use super::Value;
 
 pub const PROMISE_TYPE: &str = "promesa";
+/// Nombre con el que empiezan los errores de las promesas canceladas
+pub const CANCEL_ERROR: &str = "Cancelado";
 
 #[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
 pub enum PromiseStatus {
@@ -20,23 +22,74 @@ pub enum PromiseData {
   Err(String),
 }
 
+/// Libera un recurso nativo (como un servidor o un socket) cuando se cancela su promesa
+pub struct CancelHook(Box<dyn FnOnce() + Send + Sync>);
+impl std::fmt::Debug for CancelHook {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "CancelHook")
+  }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
 pub struct Promise {
   value: MultiRefHash<Option<MultiRefHash<Value>>>,
   err: MultiRefHash<Option<String>>,
   status: MultiRefHash<PromiseStatus>,
+  canceled: MultiRefHash<bool>,
+  on_cancel: MultiRefHash<Vec<CancelHook>>,
+  /// Alguien espero la promesa o reacciono a ella, asi que sus errores no se pierden
+  observed: MultiRefHash<bool>,
 }
 impl Promise {
   pub fn new() -> Self {
     Self::default()
   }
   pub fn set_value(&self, value: Value) {
+    // Lo que haga la tarea despues de cancelarse ya no importa
+    if self.is_canceled() {
+      return;
+    }
     *self.status.write() = PromiseStatus::Done;
     *self.value.write() = Some(value.into());
+    crate::interpreter::wake_scheduler();
   }
   pub fn set_err(&self, err: String) {
+    if self.is_canceled() {
+      return;
+    }
     *self.status.write() = PromiseStatus::Done;
     *self.err.write() = Some(err);
+    crate::interpreter::wake_scheduler();
+  }
+  /// Rechaza la promesa pendiente y libera sus recursos, el planificador descarta el hilo que
+  /// la iba a cumplir. Retorna falso si la promesa ya se habia cumplido
+  pub fn cancel(&self) -> bool {
+    if !matches!(self.get_data(), PromiseData::Pending) {
+      return false;
+    }
+    self.set_err(format!("{CANCEL_ERROR}: La promesa fue cancelada"));
+    *self.canceled.write() = true;
+    self.observe();
+    let hooks = std::mem::take(&mut *self.on_cancel.write());
+    for CancelHook(hook) in hooks {
+      hook();
+    }
+    true
+  }
+  pub fn observe(&self) {
+    *self.observed.write() = true;
+  }
+  pub fn is_observed(&self) -> bool {
+    *self.observed.read()
+  }
+  pub fn is_canceled(&self) -> bool {
+    *self.canceled.read()
+  }
+  pub fn on_cancel(&self, hook: impl FnOnce() + Send + Sync + 'static) {
+    if self.is_canceled() {
+      return hook();
+    }
+    self.on_cancel.write().push(CancelHook(Box::new(hook)));
   }
   pub fn get_value_str(&self) -> String {
     if self.value.read().is_some() {
@@ -71,6 +124,9 @@ impl From<Value> for Promise {
       err: Default::default(),
       status: PromiseStatus::Done.into(),
       value: Some(value.into()).into(),
+      canceled: Default::default(),
+      on_cancel: Default::default(),
+      observed: Default::default(),
     }
   }
 }
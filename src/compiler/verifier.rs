@@ -0,0 +1,351 @@
+use super::chunk::Chunk;
+use super::{Function, Object, OpCode, Value};
+
+#[derive(Clone, Copy, Debug)]
+struct Instruction {
+  op: OpCode,
+  offset: usize,
+  len: usize,
+  operand: u8,
+  // Byte destino de los saltos
+  target: Option<usize>,
+}
+
+/// Comprueba que el bytecode de la funcion y de sus funciones internas se pueda ejecutar
+/// sin que la maquina virtual falle al leerlo
+pub fn verify(function: &Function) -> Result<(), String> {
+  let chunk = function.chunk();
+  let group = chunk.read();
+  let chunks = group.chunks();
+  verify_code(function, chunks).map_err(|error| {
+    format!(
+      "Bytecode invalido {}: {error}",
+      function.location().trim_start_matches("en ")
+    )
+  })?;
+  for chunk in chunks {
+    for (_, value) in chunk.constants.enumerate() {
+      match value {
+        Value::Object(Object::Function(function)) => verify(&function.read())?,
+        Value::Lazy(lazy) => verify(&lazy.get_once().read())?,
+        _ => {}
+      }
+    }
+  }
+  Ok(())
+}
+
+fn verify_code(function: &Function, chunks: &[Chunk]) -> Result<(), String> {
+  let instructions = decode(function, chunks)?;
+  check_stack(function, &instructions)
+}
+
+fn decode(function: &Function, chunks: &[Chunk]) -> Result<Vec<Instruction>, String> {
+  let upvalues = function.get_upvalues().len();
+  let mut instructions = vec![];
+  let mut base = 0;
+  for chunk in chunks {
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+      let byte = chunk.code[offset];
+      let op = OpCode::from(byte);
+      let position = base + offset;
+      if op == OpCode::Null {
+        return Err(format!("Byte invalido 0x{byte:02X} en el byte {position}"));
+      }
+      let len = 1 + op.operand_len();
+      let operands = chunk
+        .code
+        .get(offset + 1..offset + len)
+        .ok_or_else(|| format!("Faltan operandos para {op:?} en el byte {position}"))?;
+      let constant = match op {
+        OpCode::Constant
+        | OpCode::GetVar
+        | OpCode::SetVar
+        | OpCode::VarDecl
+        | OpCode::ConstDecl
+        | OpCode::DelVar
+        | OpCode::Export => Some(operands[0]),
+        // Solo se lee el nombre si se importa con un alias
+        OpCode::Import if operands[0] & 0b01 == 0b01 => Some(operands[1]),
+        _ => None,
+      };
+      if let Some(constant) = constant {
+        if constant >= chunk.constants.len() {
+          return Err(format!(
+            "Constante {constant} fuera de rango para {op:?} en el byte {position}"
+          ));
+        }
+      }
+      if matches!(op, OpCode::GetUpvalue | OpCode::SetUpvalue) && operands[0] as usize >= upvalues {
+        return Err(format!(
+          "Variable capturada {} fuera de rango en el byte {position}",
+          operands[0]
+        ));
+      }
+      let next = position + len;
+      let target = match op {
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+          let distance = ((operands[0] as usize) << 8) | operands[1] as usize;
+          let target = match op {
+            OpCode::Loop => next.checked_sub(distance),
+            _ => Some(next + distance),
+          };
+          Some(target.ok_or_else(|| format!("Salto fuera del codigo en el byte {position}"))?)
+        }
+        _ => None,
+      };
+      instructions.push(Instruction {
+        op,
+        offset: position,
+        len,
+        operand: operands.first().copied().unwrap_or_default(),
+        target,
+      });
+      offset += len;
+    }
+    base += chunk.code.len();
+  }
+  Ok(instructions)
+}
+
+/// Valores que la instruccion saca y mete en la pila
+fn stack_effect(op: OpCode, operand: u8) -> (usize, usize) {
+  match op {
+    OpCode::Constant
+    | OpCode::GetVar
+    | OpCode::GetLocal
+    | OpCode::GetUpvalue
+    | OpCode::DelVar
+    | OpCode::Break
+    | OpCode::Continue => (0, 1),
+    OpCode::Copy => (1, 2),
+    OpCode::VarDecl
+    | OpCode::ConstDecl
+    | OpCode::SetVar
+    | OpCode::DeclLocal
+    | OpCode::SetLocal
+    | OpCode::SetUpvalue
+    | OpCode::Negate
+    | OpCode::Not
+    | OpCode::Approximate
+    | OpCode::At
+    | OpCode::AsRef
+    | OpCode::ToBoolean
+    | OpCode::ToString
+    | OpCode::SetScope
+    | OpCode::UnPromise
+    | OpCode::GetInstance
+    | OpCode::Await
+    | OpCode::ConsoleOut
+    | OpCode::Import
+    | OpCode::Export => (1, 1),
+    OpCode::Add
+    | OpCode::Subtract
+    | OpCode::Multiply
+    | OpCode::Divide
+    | OpCode::Modulo
+    | OpCode::Exponential
+    | OpCode::Nullish
+    | OpCode::And
+    | OpCode::Or
+    | OpCode::GreaterThan
+    | OpCode::LessThan
+    | OpCode::Equals
+    | OpCode::GetMember
+    | OpCode::ExtendClass
+    | OpCode::InClass => (2, 1),
+    OpCode::SetMember => (3, 1),
+    OpCode::Pop | OpCode::JumpIfFalse | OpCode::ArgDecl | OpCode::Throw => (1, 0),
+    OpCode::Return => (1, 1),
+    OpCode::Try => (2, 0),
+    // Los argumentos, el objeto y la funcion se cambian por el resultado.
+    // Si la llamada en cola no reutiliza el marco se comporta como una llamada normal
+    OpCode::Call | OpCode::TailCall => (operand as usize + 2, 1),
+    OpCode::NewLocals
+    | OpCode::RemoveLocals
+    | OpCode::Jump
+    | OpCode::Loop
+    | OpCode::Promised
+    | OpCode::Null => (0, 0),
+  }
+}
+
+fn check_stack(function: &Function, instructions: &[Instruction]) -> Result<(), String> {
+  let find = |offset: usize| {
+    instructions
+      .binary_search_by_key(&offset, |instruction| instruction.offset)
+      .ok()
+  };
+  // Los argumentos ya estan en la pila al entrar a la funcion
+  let entry = match function {
+    Function::Value {
+      arity, has_rest, ..
+    } => {
+      if *has_rest && *arity == 0 {
+        return Err("Una funcion con parametro expandido debe tener al menos un parametro".into());
+      }
+      *arity
+    }
+    _ => instructions
+      .iter()
+      .filter(|instruction| instruction.op == OpCode::ArgDecl)
+      .count(),
+  };
+  let mut depths: Vec<Option<usize>> = vec![None; instructions.len()];
+  let mut pending = vec![(0, entry)];
+  while let Some((index, depth)) = pending.pop() {
+    let instruction = match instructions.get(index) {
+      Some(instruction) => instruction,
+      None => {
+        let end = instructions.last().map(|i| i.offset + i.len).unwrap_or(0);
+        return Err(format!("El codigo termina sin retornar en el byte {end}"));
+      }
+    };
+    match depths[index] {
+      Some(expected) if expected == depth => continue,
+      Some(expected) => {
+        return Err(format!(
+          "Profundidad de pila inconsistente en el byte {}: {expected} y {depth}",
+          instruction.offset
+        ))
+      }
+      None => depths[index] = Some(depth),
+    }
+    let (pops, pushes) = stack_effect(instruction.op, instruction.operand);
+    // Al retornar solo debe quedar el valor de retorno, el resto quedaria en la pila del llamador
+    if instruction.op == OpCode::Return && depth != 1 {
+      return Err(format!(
+        "Se esperaba solo el valor de retorno en la pila pero hay {depth} en el byte {}",
+        instruction.offset
+      ));
+    }
+    if pops > depth {
+      return Err(format!(
+        "{:?} necesita {pops} valores en la pila pero hay {depth} en el byte {}",
+        instruction.op, instruction.offset
+      ));
+    }
+    let depth = depth - pops + pushes;
+    if let Some(target) = instruction.target {
+      let target =
+        find(target).ok_or_else(|| format!("Salto invalido en el byte {}", instruction.offset))?;
+      pending.push((target, depth));
+    }
+    if !matches!(
+      instruction.op,
+      OpCode::Return | OpCode::Throw | OpCode::Jump | OpCode::Loop
+    ) {
+      pending.push((index + 1, depth));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::ChunkGroup;
+  use crate::util::Position;
+
+  /// Programa con las instrucciones tal cual, con la constante 1 en el indice 0
+  fn script(code: &[(OpCode, &[u8])]) -> Function {
+    let chunk = ChunkGroup::new_ref();
+    {
+      let mut group = chunk.write();
+      group.add_value(Value::Number(1.into()));
+      for (op, operands) in code {
+        let mut bytes = vec![*op as u8];
+        bytes.extend(*operands);
+        group.write_buffer(bytes, Position::default());
+      }
+    }
+    Function::Script {
+      chunk,
+      path: "prueba.agab".into(),
+      scope: None.into(),
+      upvalues: vec![],
+      captures: vec![],
+    }
+  }
+  fn error(code: &[(OpCode, &[u8])]) -> String {
+    verify(&script(code)).unwrap_err()
+  }
+
+  #[test]
+  fn test_valid_code() {
+    let code: &[(OpCode, &[u8])] = &[
+      (OpCode::Constant, &[0]),
+      (OpCode::JumpIfFalse, &[0, 3]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Pop, &[]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+    ];
+    assert_eq!(verify(&script(code)), Ok(()));
+  }
+
+  #[test]
+  fn test_rejects_malformed_instructions() {
+    let function = script(&[]);
+    function
+      .chunk()
+      .write()
+      .write_buffer(vec![OpCode::Null as u8], Position::default());
+    assert!(verify(&function)
+      .unwrap_err()
+      .contains("Byte invalido 0xFF"));
+    assert!(error(&[(OpCode::Constant, &[])]).contains("Faltan operandos"));
+    assert!(error(&[(OpCode::Constant, &[1]), (OpCode::Return, &[])])
+      .contains("Constante 1 fuera de rango"));
+    assert!(error(&[(OpCode::GetUpvalue, &[0]), (OpCode::Return, &[])])
+      .contains("Variable capturada 0 fuera de rango"));
+  }
+
+  #[test]
+  fn test_rejects_invalid_jumps() {
+    assert!(error(&[(OpCode::Loop, &[0, 9])]).contains("Salto fuera del codigo"));
+    // Un salto a la mitad de una instruccion
+    let code: &[(OpCode, &[u8])] = &[
+      (OpCode::Jump, &[0, 1]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+    ];
+    assert!(error(code).contains("Salto invalido en el byte 0"));
+  }
+
+  #[test]
+  fn test_rejects_stack_errors() {
+    assert!(error(&[(OpCode::Add, &[]), (OpCode::Return, &[])])
+      .contains("Add necesita 2 valores en la pila pero hay 0"));
+    let code: &[(OpCode, &[u8])] = &[
+      (OpCode::Constant, &[0]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+    ];
+    assert!(error(code).contains("Se esperaba solo el valor de retorno"));
+    assert!(error(&[(OpCode::Constant, &[0]), (OpCode::Pop, &[])])
+      .contains("El codigo termina sin retornar"));
+    // Un camino salta con la pila vacia y el otro llega con un valor
+    let code: &[(OpCode, &[u8])] = &[
+      (OpCode::Constant, &[0]),
+      (OpCode::JumpIfFalse, &[0, 2]),
+      (OpCode::Constant, &[0]),
+      (OpCode::Return, &[]),
+    ];
+    assert!(error(code).contains("Profundidad de pila inconsistente"));
+  }
+
+  #[test]
+  fn test_verifies_inner_functions() {
+    let inner = script(&[(OpCode::Pop, &[])]);
+    let outer = script(&[(OpCode::Constant, &[1]), (OpCode::Return, &[])]);
+    outer
+      .chunk()
+      .write()
+      .add_value(Value::Object(Object::Function(inner.into())));
+    assert!(verify(&outer)
+      .unwrap_err()
+      .contains("Pop necesita 1 valores"));
+  }
+}
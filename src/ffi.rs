@@ -0,0 +1,448 @@
+//! Interfaz de C para incluir el interprete en programas de otros lenguajes, `include/agal.h` se
+//! genera de este archivo al compilar
+//!
+//! # Safety
+//! Todas las funciones reciben punteros nulos o creados por estas mismas funciones y que no se
+//! han liberado, y textos terminados en nulo
+//!
+//! Un fallo interno del interprete no llega al programa anfitrion, la funcion retorna falso o
+//! nulo y el mensaje queda en `agal_vm_error`
+#![allow(clippy::missing_safety_doc)]
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::compiler::{NativeValue, Number, Object, Value};
+use crate::interpreter::{HostModule, Motor, Thread, VMOptions};
+use crate::MultiRefHash;
+
+pub mod extension;
+
+/// Funcion del programa anfitrion. Recibe los datos con los que se registro y los argumentos,
+/// que solo son validos durante la llamada. Retorna un valor nuevo, uno de los argumentos o nulo
+/// para `nada`. Para lanzar un error se llama a `agal_throw` antes de retornar
+pub type Callback =
+  unsafe extern "C" fn(data: *mut c_void, args: *const *mut Value, len: usize) -> *mut Value;
+
+/// La funcion registrada y sus datos, se guardan en la funcion nativa que la llama
+#[derive(Clone, Copy)]
+pub struct HostCallback {
+  func: Callback,
+  data: *mut c_void,
+}
+// Quien registra la funcion se encarga de que sus datos se puedan usar desde otros hilos
+unsafe impl Send for HostCallback {}
+unsafe impl Sync for HostCallback {}
+
+/// Lo que retorna `agal_value_type` y `type_of` de las extensiones
+#[repr(u32)]
+pub enum Type {
+  Null = 0,
+  Boolean = 1,
+  Number = 2,
+  String = 3,
+  List = 4,
+  Other = 5,
+}
+
+pub struct Vm {
+  motor: Motor,
+  modules: HashMap<String, HostModule>,
+  error: Option<CString>,
+}
+impl Vm {
+  /// Guarda el error para `agal_vm_error`, retorna si no hubo error
+  fn settle<T>(&mut self, result: Result<T, String>) -> Option<T> {
+    match result {
+      Ok(value) => {
+        self.error = None;
+        Some(value)
+      }
+      Err(error) => {
+        self.error = Some(to_c_string(error));
+        None
+      }
+    }
+  }
+}
+
+thread_local! {
+  /// El error que lanzo la funcion del anfitrion con `agal_throw`
+  static THROWN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Un panico no puede cruzar `extern "C"`, se atrapa y se convierte en un error
+fn catch<T>(body: impl FnOnce() -> T) -> Result<T, String> {
+  catch_unwind(AssertUnwindSafe(body)).map_err(|panic| {
+    let message = match panic.downcast_ref::<&str>() {
+      Some(message) => message.to_string(),
+      None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    format!("Error interno del interprete: {message}")
+  })
+}
+/// Como `catch` para las funciones que no tienen un interprete donde guardar el error
+fn guard<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+  catch(body).unwrap_or(fallback)
+}
+
+fn to_c_string(text: String) -> CString {
+  // Un texto con nulos se corta en el primero, C no puede leer lo que sigue
+  CString::new(text).unwrap_or_else(|error| {
+    let end = error.nul_position();
+    CString::new(&error.into_vec()[..end]).unwrap_or_default()
+  })
+}
+unsafe fn from_c_str(text: *const c_char) -> Option<String> {
+  if text.is_null() {
+    return None;
+  }
+  Some(CStr::from_ptr(text).to_string_lossy().into_owned())
+}
+fn into_handle(value: Value) -> *mut Value {
+  Box::into_raw(Box::new(value))
+}
+unsafe fn value_of<'a>(value: *const Value) -> Option<&'a Value> {
+  value.as_ref()
+}
+
+/// Funcion nativa de todas las funciones registradas, la del anfitrion esta en los datos
+fn call(
+  _: Value,
+  args: Vec<Value>,
+  _: &mut Thread,
+  data: MultiRefHash<NativeValue>,
+) -> Result<Value, String> {
+  let callback = match &*data.read() {
+    NativeValue::Callback(callback) => *callback,
+    _ => Err("Se esperaba una funcion del programa anfitrion".to_string())?,
+  };
+  let args: Vec<*mut Value> = args.into_iter().map(into_handle).collect();
+  let result = unsafe { (callback.func)(callback.data, args.as_ptr(), args.len()) };
+  let value = if result.is_null() {
+    Value::Never
+  } else if args.contains(&result) {
+    unsafe { (*result).clone() }
+  } else {
+    *unsafe { Box::from_raw(result) }
+  };
+  for arg in args {
+    drop(unsafe { Box::from_raw(arg) });
+  }
+  match THROWN.with(|thrown| thrown.borrow_mut().take()) {
+    Some(error) => Err(error),
+    None => Ok(value),
+  }
+}
+
+/// Crea un interprete con las opciones por defecto
+#[no_mangle]
+pub extern "C" fn agal_vm_new() -> *mut Vm {
+  guard(ptr::null_mut(), || {
+    Box::into_raw(Box::new(Vm {
+      motor: Motor::new(VMOptions::default()),
+      modules: HashMap::new(),
+      error: None,
+    }))
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_free(vm: *mut Vm) {
+  guard((), || {
+    if !vm.is_null() {
+      drop(Box::from_raw(vm));
+    }
+  })
+}
+/// El mensaje del ultimo error, o nulo si la ultima operacion termino bien. Es valido hasta la
+/// siguiente operacion con el interprete
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_error(vm: *const Vm) -> *const c_char {
+  guard(ptr::null(), || {
+    match vm.as_ref().and_then(|vm| vm.error.as_ref()) {
+      Some(error) => error.as_ptr(),
+      None => ptr::null(),
+    }
+  })
+}
+/// Compila y ejecuta el codigo hasta que termine, `path` se usa para los errores y las
+/// importaciones relativas
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_eval(
+  vm: *mut Vm,
+  source: *const c_char,
+  path: *const c_char,
+) -> bool {
+  let Some(vm) = vm.as_mut() else {
+    return false;
+  };
+  let source = from_c_str(source).unwrap_or_default();
+  let path = from_c_str(path).unwrap_or_else(|| "<anfitrion>.aga".to_string());
+  let result = catch(|| {
+    vm.motor
+      .load_source(&source, path)
+      .and_then(|_| vm.motor.run())
+  });
+  vm.settle(result.and_then(|result| result)).is_some()
+}
+/// Ejecuta el contenido de un archivo `.agab` hasta que termine
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_eval_bytecode(vm: *mut Vm, bytes: *const u8, len: usize) -> bool {
+  let Some(vm) = vm.as_mut() else {
+    return false;
+  };
+  let bytes = if bytes.is_null() {
+    vec![]
+  } else {
+    std::slice::from_raw_parts(bytes, len).to_vec()
+  };
+  let result = catch(|| vm.motor.load_bytecode(bytes).and_then(|_| vm.motor.run()));
+  vm.settle(result.and_then(|result| result)).is_some()
+}
+/// Agrega `func` al modulo que se importa como `importa ':modulo' como M;`, con `data` como
+/// primer argumento en cada llamada. Se registra antes de `agal_vm_eval`
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_register(
+  vm: *mut Vm,
+  module: *const c_char,
+  name: *const c_char,
+  func: Callback,
+  data: *mut c_void,
+) -> bool {
+  let Some(vm) = vm.as_mut() else {
+    return false;
+  };
+  let (Some(module), Some(name)) = (from_c_str(module), from_c_str(name)) else {
+    return vm
+      .settle::<()>(Err(
+        "Se esperaba el nombre del modulo y de la funcion".to_string(),
+      ))
+      .is_some();
+  };
+  let result = catch(|| {
+    let module = HostModule::new(&module);
+    let module = vm
+      .modules
+      .remove(module.get_name())
+      .unwrap_or(module)
+      .function_with_data(
+        &name,
+        call,
+        MultiRefHash::new(NativeValue::Callback(HostCallback { func, data })),
+      );
+    vm.motor.register_module(module.clone());
+    vm.modules.insert(module.get_name().to_string(), module);
+  });
+  vm.settle(result).is_some()
+}
+/// Llama a una funcion que exporta el programa, retorna nulo si falla
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_call(
+  vm: *mut Vm,
+  name: *const c_char,
+  args: *const *const Value,
+  len: usize,
+) -> *mut Value {
+  let Some(vm) = vm.as_mut() else {
+    return ptr::null_mut();
+  };
+  let name = from_c_str(name).unwrap_or_default();
+  let args = if args.is_null() {
+    vec![]
+  } else {
+    std::slice::from_raw_parts(args, len)
+      .iter()
+      .map(|arg| value_of(*arg).cloned().unwrap_or_default())
+      .collect()
+  };
+  let result = catch(|| vm.motor.call(&name, args)).and_then(|result| result);
+  vm.settle(result).map_or(ptr::null_mut(), into_handle)
+}
+/// Lo que exporta el programa con ese nombre, o nulo si no existe
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_get_export(vm: *mut Vm, name: *const c_char) -> *mut Value {
+  let Some(vm) = vm.as_mut() else {
+    return ptr::null_mut();
+  };
+  let name = from_c_str(name).unwrap_or_default();
+  let result = catch(|| vm.motor.get_export::<Value>(&name)).and_then(|result| result);
+  vm.settle(result).map_or(ptr::null_mut(), into_handle)
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_get_global(vm: *mut Vm, name: *const c_char) -> *mut Value {
+  let Some(vm) = vm.as_mut() else {
+    return ptr::null_mut();
+  };
+  let name = from_c_str(name).unwrap_or_default();
+  let result = catch(|| vm.motor.get_global::<Value>(&name)).and_then(|result| result);
+  vm.settle(result).map_or(ptr::null_mut(), into_handle)
+}
+/// Declara la variable global o cambia su valor, el valor se copia
+#[no_mangle]
+pub unsafe extern "C" fn agal_vm_set_global(
+  vm: *mut Vm,
+  name: *const c_char,
+  value: *const Value,
+) -> bool {
+  let Some(vm) = vm.as_mut() else {
+    return false;
+  };
+  let name = from_c_str(name).unwrap_or_default();
+  let value = value_of(value).cloned().unwrap_or_default();
+  let result = catch(|| vm.motor.set_global(&name, value)).and_then(|result| result);
+  vm.settle(result).is_some()
+}
+
+/// Desde una funcion registrada, hace que la llamada lance el mensaje
+#[no_mangle]
+pub unsafe extern "C" fn agal_throw(message: *const c_char) {
+  guard((), || {
+    let message = from_c_str(message).unwrap_or_default();
+    THROWN.with(|thrown| *thrown.borrow_mut() = Some(message));
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn agal_value_null() -> *mut Value {
+  guard(ptr::null_mut(), || into_handle(Value::Null))
+}
+#[no_mangle]
+pub extern "C" fn agal_value_bool(value: bool) -> *mut Value {
+  guard(ptr::null_mut(), || into_handle(value.into()))
+}
+/// Los numeros se pasan como texto para no perder precision, como `"3.5"` o `"-12"`. Retorna
+/// nulo si el texto no es un numero
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_number(text: *const c_char) -> *mut Value {
+  guard(ptr::null_mut(), || {
+    match from_c_str(text).map(|text| text.trim().parse::<Number>()) {
+      Some(Ok(number)) => into_handle(Value::Number(number)),
+      _ => ptr::null_mut(),
+    }
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_string(text: *const c_char) -> *mut Value {
+  guard(ptr::null_mut(), || {
+    into_handle(Value::String(from_c_str(text).unwrap_or_default()))
+  })
+}
+#[no_mangle]
+pub extern "C" fn agal_value_list() -> *mut Value {
+  guard(ptr::null_mut(), || {
+    into_handle(Value::Object(Vec::<Value>::new().into()))
+  })
+}
+/// Agrega una copia del valor al final de la lista
+#[no_mangle]
+pub unsafe extern "C" fn agal_list_push(list: *mut Value, value: *const Value) -> bool {
+  guard(false, || match (value_of(list), value_of(value)) {
+    (Some(Value::Object(Object::Array(list))), Some(value)) => {
+      list.write().push(value.clone());
+      true
+    }
+    _ => false,
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_list_len(list: *const Value) -> usize {
+  guard(0, || match value_of(list) {
+    Some(Value::Object(Object::Array(list))) => list.read().len(),
+    _ => 0,
+  })
+}
+/// Una copia del elemento, o nulo si esta fuera de la lista
+#[no_mangle]
+pub unsafe extern "C" fn agal_list_get(list: *const Value, index: usize) -> *mut Value {
+  guard(ptr::null_mut(), || match value_of(list) {
+    Some(Value::Object(Object::Array(list))) => list
+      .read()
+      .get(index)
+      .cloned()
+      .map_or(ptr::null_mut(), into_handle),
+    _ => ptr::null_mut(),
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_type(value: *const Value) -> Type {
+  guard(Type::Null, || match value_of(value) {
+    None | Some(Value::Null | Value::Never) => Type::Null,
+    Some(Value::True | Value::False) => Type::Boolean,
+    Some(Value::Number(_)) => Type::Number,
+    Some(Value::String(_)) => Type::String,
+    Some(Value::Object(Object::Array(_))) => Type::List,
+    Some(_) => Type::Other,
+  })
+}
+/// Retorna falso si el valor no es un buleano
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_as_bool(value: *const Value, out: *mut bool) -> bool {
+  guard(false, || {
+    match value_of(value) {
+      Some(Value::True) if !out.is_null() => *out = true,
+      Some(Value::False) if !out.is_null() => *out = false,
+      _ => return false,
+    }
+    true
+  })
+}
+/// El numero como texto, o nulo si el valor no es un numero. Se libera con `agal_string_free`
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_as_number(value: *const Value) -> *mut c_char {
+  guard(ptr::null_mut(), || match value_of(value) {
+    Some(Value::Number(number)) => to_c_string(number.to_string()).into_raw(),
+    _ => ptr::null_mut(),
+  })
+}
+/// El texto, o nulo si el valor no es una cadena. Se libera con `agal_string_free`
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_as_string(value: *const Value) -> *mut c_char {
+  guard(ptr::null_mut(), || match value_of(value) {
+    Some(Value::String(text)) => to_c_string(text.clone()).into_raw(),
+    _ => ptr::null_mut(),
+  })
+}
+/// Una copia del valor, las listas y objetos se comparten con el original
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_clone(value: *const Value) -> *mut Value {
+  guard(ptr::null_mut(), || {
+    value_of(value)
+      .cloned()
+      .map_or(ptr::null_mut(), into_handle)
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_value_free(value: *mut Value) {
+  guard((), || {
+    if !value.is_null() {
+      drop(Box::from_raw(value));
+    }
+  })
+}
+#[no_mangle]
+pub unsafe extern "C" fn agal_string_free(text: *mut c_char) {
+  guard((), || {
+    if !text.is_null() {
+      drop(CString::from_raw(text));
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  /// Las cabeceras de `include/` son las que se distribuyen, deben ser iguales a las generadas
+  #[test]
+  fn test_headers_are_up_to_date() {
+    for header in ["agal.h", "agal_extension.h"] {
+      let read = |dir: &str| std::fs::read_to_string(format!("{dir}/{header}")).unwrap();
+      let generated = read(env!("OUT_DIR"));
+      let committed = read(concat!(env!("CARGO_MANIFEST_DIR"), "/include"));
+      assert!(
+        generated == committed,
+        "include/{header} esta desactualizada, se actualiza con AGAL_ACTUALIZAR_CABECERAS=1 cargo build"
+      );
+    }
+  }
+}
@@ -0,0 +1,68 @@
+//! Interfaz de las extensiones nativas que se cargan con `importa ':nativo/ruta'`,
+//! `include/agal_extension.h` se genera de este archivo al compilar
+//!
+//! La biblioteca exporta `agal_registrar` con la forma de `RegisterFn`, que retorna las
+//! funciones de la extension. Las funciones reciben `Api` para crear y leer valores
+use std::ffi::c_char;
+
+pub use super::Type;
+
+/// Cambia cuando cambia la forma de `Api`, `Module` o `ExtensionFunction`
+pub const ABI_VERSION: u32 = 1;
+
+/// Un valor del interprete. Las extensiones solo lo usan por medio de `Api` y solo es valido
+/// hasta que termina la llamada que lo recibio o lo creo
+pub struct Handle {
+  _private: [u8; 0],
+}
+
+/// Funcion de una extension, recibe los argumentos y retorna un valor creado con `api` o uno de
+/// los argumentos. Un puntero nulo es `nada`
+pub type ExtensionFn =
+  unsafe extern "C" fn(api: *const Api, args: *const *mut Handle, len: usize) -> *mut Handle;
+
+#[repr(C)]
+pub struct ExtensionFunction {
+  /// Cadena terminada en nulo con el nombre que se exporta
+  pub name: *const c_char,
+  pub func: ExtensionFn,
+}
+
+/// Lo que retorna `agal_registrar`, debe vivir mientras la biblioteca este cargada
+#[repr(C)]
+pub struct Module {
+  pub version: u32,
+  pub functions: *const ExtensionFunction,
+  pub len: usize,
+}
+
+/// `agal_registrar` recibe la version de la interfaz del interprete
+pub type RegisterFn = unsafe extern "C" fn(version: u32) -> *const Module;
+
+/// Funciones del interprete para crear y leer valores. Las funciones `as_*` retornan falso si el
+/// valor no es de ese tipo, `error` hace que la llamada lance el mensaje
+#[repr(C)]
+pub struct Api {
+  pub version: u32,
+  pub null: extern "C" fn() -> *mut Handle,
+  pub boolean: extern "C" fn(value: bool) -> *mut Handle,
+  pub number: extern "C" fn(value: f64) -> *mut Handle,
+  pub string: unsafe extern "C" fn(bytes: *const u8, len: usize) -> *mut Handle,
+  pub list: extern "C" fn() -> *mut Handle,
+  pub list_push: extern "C" fn(list: *mut Handle, value: *mut Handle) -> bool,
+  pub list_len: extern "C" fn(list: *mut Handle) -> usize,
+  pub list_get: extern "C" fn(list: *mut Handle, index: usize) -> *mut Handle,
+  pub type_of: extern "C" fn(value: *mut Handle) -> Type,
+  pub as_boolean: unsafe extern "C" fn(value: *mut Handle, out: *mut bool) -> bool,
+  pub as_number: unsafe extern "C" fn(value: *mut Handle, out: *mut f64) -> bool,
+  /// Los bytes no terminan en nulo y son validos mientras lo sea el valor
+  pub as_string:
+    unsafe extern "C" fn(value: *mut Handle, bytes: *mut *const u8, len: *mut usize) -> bool,
+  pub error: unsafe extern "C" fn(bytes: *const u8, len: usize) -> *mut Handle,
+}
+
+extern "C" {
+  /// La funcion que exporta la extension, el interprete la busca por su nombre al cargarla.
+  /// Retorna nulo si no puede usar la version que recibe
+  pub fn agal_registrar(version: u32) -> *const Module;
+}
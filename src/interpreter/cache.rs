@@ -14,6 +14,9 @@ impl<K: Eq + std::hash::Hash, V: Clone> DataManager<K, V> {
   pub fn has(&self, key: &K) -> bool {
     self.data.read().contains_key(key)
   }
+  pub fn values(&self) -> Vec<V> {
+    self.data.read().values().cloned().collect()
+  }
 }
 
 pub type DataCache = DataManager<String, Value>;
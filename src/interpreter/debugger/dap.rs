@@ -0,0 +1,832 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use super::{
+  absolute, at_breakpoint, current_frame, debug_options, evaluate, local_variables, main_thread,
+  module_variables, thread_frames, Breakpoint, Location, Step,
+};
+use crate::compiler::{Object, Value};
+use crate::interpreter::stack::CallFrame;
+use crate::interpreter::vm::{AsyncThread, VM};
+use crate::interpreter::{wake_scheduler, InterpretResult, VMOptions};
+use crate::util::json::Json;
+use crate::{MultiRefHash, ToJSON as _};
+
+const MAIN_THREAD: i64 = 1;
+
+/// Envia los mensajes al editor, tambien desde el hilo que reenvia la salida del programa
+struct Client {
+  writer: Mutex<(i64, Box<dyn Write + Send>)>,
+}
+impl Client {
+  fn send(&self, kind: &str, mut properties: Vec<(String, Json)>) {
+    let mut writer = self.writer.lock().unwrap();
+    writer.0 += 1;
+    properties.insert(0, ("seq".to_string(), writer.0.into()));
+    properties.insert(1, ("type".to_string(), kind.into()));
+    let message = Json::Object(properties).to_json();
+    let _ = write!(
+      writer.1,
+      "Content-Length: {}\r\n\r\n{message}",
+      message.len()
+    );
+    let _ = writer.1.flush();
+  }
+  fn event(&self, event: &str, body: Json) {
+    self.send(
+      "event",
+      vec![
+        ("event".to_string(), event.into()),
+        ("body".to_string(), body),
+      ],
+    );
+  }
+  fn respond(&self, request: &Json, result: Result<Json, String>) {
+    let mut properties = vec![
+      ("request_seq".to_string(), request.get("seq").clone()),
+      ("command".to_string(), request.get("command").clone()),
+      ("success".to_string(), result.is_ok().into()),
+    ];
+    match result {
+      Ok(body) => properties.push(("body".to_string(), body)),
+      Err(message) => properties.push(("message".to_string(), message.into())),
+    }
+    self.send("response", properties);
+  }
+  fn output(&self, category: &str, text: String) {
+    self.event(
+      "output",
+      Json::object([("category", category.into()), ("output", text.into())]),
+    );
+  }
+}
+
+/// Lee los mensajes del editor en otro hilo para no detener el programa mientras no lleguen
+fn read_messages(input: impl Read + Send + 'static) -> Receiver<Json> {
+  let (sender, receiver) = mpsc::channel();
+  std::thread::spawn(move || {
+    let mut reader = BufReader::new(input);
+    loop {
+      let mut length = None;
+      loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+          return;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+          break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+          if name.eq_ignore_ascii_case("Content-Length") {
+            length = value.trim().parse::<usize>().ok();
+          }
+        }
+      }
+      let Some(length) = length else { continue };
+      let mut content = vec![0; length];
+      if reader.read_exact(&mut content).is_err() {
+        return;
+      }
+      let Ok(message) = Json::parse(&String::from_utf8_lossy(&content)) else {
+        continue;
+      };
+      if sender.send(message).is_err() {
+        return;
+      }
+      // El planificador puede estar esperando a un temporizador
+      wake_scheduler();
+    }
+  });
+  receiver
+}
+
+/// Lo que muestra una referencia de variables de los mensajes `variables`
+enum Reference {
+  Locals(CallFrame),
+  Module(CallFrame),
+  Value(Value),
+}
+
+struct Adapter {
+  client: Arc<Client>,
+  options: VMOptions,
+  path: Option<PathBuf>,
+  vm: Option<MultiRefHash<VM>>,
+  running: bool,
+  stop_on_entry: bool,
+  breakpoints: Vec<Breakpoint>,
+  /// El hilo que avanza y hasta donde, los demas solo se detienen en los puntos de interrupcion
+  step: Option<(i64, Step)>,
+  pause: bool,
+  thread_ids: HashMap<usize, i64>,
+  next_thread_id: i64,
+  previous: HashMap<i64, Location>,
+  /// Las llamadas y referencias solo son validas mientras el programa esta detenido
+  frames: Vec<(i64, CallFrame)>,
+  references: Vec<Reference>,
+  output: Option<Capture>,
+}
+impl Adapter {
+  fn vm(&self) -> Result<&MultiRefHash<VM>, String> {
+    self
+      .vm
+      .as_ref()
+      .ok_or_else(|| "No hay ningun programa en ejecucion".to_string())
+  }
+  /// El modulo principal es el hilo 1, los demas conservan su numero mientras sigan vivos
+  fn threads(&mut self) -> Vec<(i64, MultiRefHash<AsyncThread>)> {
+    let Some(vm) = &self.vm else {
+      return vec![];
+    };
+    let mut threads = vec![(MAIN_THREAD, main_thread(vm))];
+    let process_manager = vm.read().get_process_manager();
+    let alive = process_manager.read().get_threads();
+    let mut ids = HashMap::new();
+    for thread in alive {
+      let id = match self.thread_ids.get(&thread.addr()) {
+        Some(id) => *id,
+        None => {
+          self.next_thread_id += 1;
+          self.client.event(
+            "thread",
+            Json::object([
+              ("reason", "started".into()),
+              ("threadId", self.next_thread_id.into()),
+            ]),
+          );
+          self.next_thread_id
+        }
+      };
+      ids.insert(thread.addr(), id);
+      threads.push((id, thread));
+    }
+    let ended: HashSet<i64> = self
+      .thread_ids
+      .values()
+      .filter(|id| !ids.values().any(|alive| alive == *id))
+      .cloned()
+      .collect();
+    for id in ended {
+      self.previous.remove(&id);
+      self.client.event(
+        "thread",
+        Json::object([("reason", "exited".into()), ("threadId", id.into())]),
+      );
+    }
+    self.thread_ids = ids;
+    threads
+  }
+  fn thread(&mut self, id: i64) -> Result<MultiRefHash<AsyncThread>, String> {
+    self
+      .threads()
+      .into_iter()
+      .find(|(thread_id, _)| *thread_id == id)
+      .map(|(_, thread)| thread)
+      .ok_or_else(|| format!("No existe el hilo {id}"))
+  }
+  /// Revisa la posicion de cada hilo antes de la siguiente instruccion
+  fn check_pause(&mut self) -> Option<(&'static str, i64)> {
+    let mut stop = None;
+    for (id, thread) in self.threads() {
+      let Some(location) = Location::of(&thread_frames(&thread)) else {
+        continue;
+      };
+      let previous = self.previous.get(&id);
+      let reason = if self.pause {
+        Some("pause")
+      } else if at_breakpoint(&self.breakpoints, &location, previous) {
+        Some("breakpoint")
+      } else {
+        match &self.step {
+          Some((step_id, step)) if *step_id == id && step.should_pause(&location, previous) => {
+            Some("step")
+          }
+          _ => None,
+        }
+      };
+      self.previous.insert(id, location);
+      if stop.is_none() {
+        stop = reason.map(|reason| (reason, id));
+      }
+    }
+    stop
+  }
+  fn stop(&mut self, reason: &str, thread_id: i64) {
+    let _ = std::io::stdout().flush();
+    self.running = false;
+    self.pause = false;
+    self.step = None;
+    self.client.event(
+      "stopped",
+      Json::object([
+        ("reason", reason.into()),
+        ("threadId", thread_id.into()),
+        ("allThreadsStopped", true.into()),
+      ]),
+    );
+  }
+  fn resume(&mut self, step: Option<(i64, Step)>) {
+    self.frames.clear();
+    self.references.clear();
+    self.step = step;
+    self.running = true;
+  }
+  fn finish(&mut self, exit_code: i64) {
+    self.vm = None;
+    self.running = false;
+    // Lo que imprimio el programa debe llegar antes de avisar que termino
+    if let Some(output) = self.output.take() {
+      output.stop();
+    }
+    self
+      .client
+      .event("exited", Json::object([("exitCode", exit_code.into())]));
+    self.client.event("terminated", Json::object([]));
+  }
+  fn display(&self, value: &Value) -> String {
+    let Ok(vm) = self.vm() else {
+      return String::new();
+    };
+    let process_manager = vm.read().get_process_manager();
+    let thread = process_manager.read().get_root_thread();
+    let thread = thread.read();
+    match value {
+      Value::String(string) => format!("'{string}'"),
+      Value::Char(char) => format!("'{char}'"),
+      value => value.to_aga_string(&thread),
+    }
+  }
+  /// Los valores con elementos o propiedades se pueden expandir en el editor
+  fn reference(&mut self, value: &Value) -> i64 {
+    match value {
+      Value::Object(Object::Array(_) | Object::Map(..)) => {
+        self.references.push(Reference::Value(value.clone()));
+        self.references.len() as i64
+      }
+      Value::Ref(inner) => {
+        let inner = inner.borrow().clone();
+        self.reference(&inner)
+      }
+      _ => 0,
+    }
+  }
+  fn variable(&mut self, name: String, value: &Value) -> Json {
+    Json::object([
+      ("name", name.into()),
+      ("value", self.display(value).into()),
+      ("type", value.get_type().into()),
+      ("variablesReference", self.reference(value).into()),
+    ])
+  }
+  fn children(value: &Value) -> Vec<(String, Value)> {
+    match value {
+      Value::Object(Object::Array(list)) => list
+        .read()
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (index.to_string(), value.clone()))
+        .collect(),
+      Value::Object(Object::Map(properties, instance)) => {
+        let mut children: Vec<(String, Value)> = properties
+          .read()
+          .iter()
+          .map(|(key, value)| (key.clone(), value.clone()))
+          .collect();
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if let Some(instance) = &*instance.read() {
+          children.extend(instance.properties());
+        }
+        children
+      }
+      Value::Ref(inner) => Self::children(&inner.borrow()),
+      _ => vec![],
+    }
+  }
+  fn frame(&self, id: &Json) -> Result<CallFrame, String> {
+    id.as_i64()
+      .and_then(|id| self.frames.get((id - 1) as usize))
+      .map(|(_, frame)| frame.clone())
+      .ok_or_else(|| "La llamada ya no existe".to_string())
+  }
+  fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+    let path = match arguments.get("program").as_str() {
+      Some(program) => PathBuf::from(program),
+      None => self
+        .path
+        .clone()
+        .ok_or_else(|| "Falta el programa a depurar".to_string())?,
+    };
+    let (compiler, _) = crate::compile(&path)?;
+    self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+    self.vm = Some(VM::new(compiler, debug_options(self.options.clone())));
+    if self.output.is_none() {
+      self.output = Capture::start(self.client.clone());
+    }
+    Ok(Json::Null)
+  }
+  fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+    let path = arguments
+      .get("source")
+      .get("path")
+      .as_str()
+      .map(|path| absolute(path.as_ref()))
+      .ok_or_else(|| "Falta la ruta del archivo".to_string())?;
+    self
+      .breakpoints
+      .retain(|breakpoint| breakpoint.path != path);
+    let mut verified = vec![];
+    for breakpoint in arguments.get("breakpoints").as_array() {
+      let Some(line) = breakpoint.get("line").as_i64().filter(|line| *line > 0) else {
+        continue;
+      };
+      self.breakpoints.push(Breakpoint {
+        path: path.clone(),
+        line: line as usize,
+      });
+      verified.push(Json::object([
+        ("verified", true.into()),
+        ("line", line.into()),
+      ]));
+    }
+    Ok(Json::object([("breakpoints", verified.into())]))
+  }
+  fn stack_trace(&mut self, arguments: &Json) -> Result<Json, String> {
+    let thread_id = arguments.get("threadId").as_i64().unwrap_or(MAIN_THREAD);
+    let frames = thread_frames(&self.thread(thread_id)?);
+    let mut stack_frames = vec![];
+    // La llamada mas reciente aun no ejecuta su siguiente instruccion, las demas esperan a la
+    // llamada que hicieron
+    for (index, frame) in frames.into_iter().rev().enumerate() {
+      let position = if index == 0 {
+        frame.next_position()
+      } else {
+        frame.current_position()
+      };
+      let mut properties = vec![
+        ("name".to_string(), frame.to_string().into()),
+        ("line".to_string(), (position.line + 1).into()),
+        ("column".to_string(), (position.column + 1).into()),
+      ];
+      match frame.source_path() {
+        Some(path) => {
+          let name = path.file_name().unwrap_or_default().to_string_lossy();
+          properties.push((
+            "source".to_string(),
+            Json::object([
+              ("name", name.to_string().into()),
+              ("path", absolute(&path).display().to_string().into()),
+            ]),
+          ))
+        }
+        None => properties.push(("presentationHint".to_string(), "subtle".into())),
+      }
+      self.frames.push((thread_id, frame));
+      properties.insert(0, ("id".to_string(), self.frames.len().into()));
+      stack_frames.push(Json::Object(properties));
+    }
+    Ok(Json::object([
+      ("totalFrames", stack_frames.len().into()),
+      ("stackFrames", stack_frames.into()),
+    ]))
+  }
+  fn scopes(&mut self, arguments: &Json) -> Result<Json, String> {
+    let frame = self.frame(arguments.get("frameId"))?;
+    self.references.push(Reference::Locals(frame.clone()));
+    let locals = Json::object([
+      ("name", "Locales".into()),
+      ("presentationHint", "locals".into()),
+      ("variablesReference", self.references.len().into()),
+      ("expensive", false.into()),
+    ]);
+    self.references.push(Reference::Module(frame));
+    let module = Json::object([
+      ("name", "Modulo".into()),
+      ("variablesReference", self.references.len().into()),
+      ("expensive", false.into()),
+    ]);
+    Ok(Json::object([("scopes", vec![locals, module].into())]))
+  }
+  fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+    let variables = match arguments
+      .get("variablesReference")
+      .as_i64()
+      .and_then(|id| self.references.get((id - 1) as usize))
+    {
+      Some(Reference::Locals(frame)) => local_variables(frame),
+      Some(Reference::Module(frame)) => module_variables(frame),
+      Some(Reference::Value(value)) => Self::children(value),
+      None => return Err("La referencia ya no existe".to_string()),
+    };
+    let variables: Vec<Json> = variables
+      .into_iter()
+      .map(|(name, value)| self.variable(name, &value))
+      .collect();
+    Ok(Json::object([("variables", variables.into())]))
+  }
+  fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+    if self.running {
+      return Err("El programa se esta ejecutando".to_string());
+    }
+    let vm = self.vm()?.clone();
+    let frame = match arguments.get("frameId") {
+      Json::Null => current_frame(&thread_frames(&main_thread(&vm)))
+        .cloned()
+        .ok_or_else(|| "No hay ninguna llamada en curso".to_string())?,
+      id => self.frame(id)?,
+    };
+    let expression = arguments.get("expression").as_str().unwrap_or_default();
+    let value = evaluate(&vm, &frame, expression)?;
+    Ok(Json::object([
+      ("result", self.display(&value).into()),
+      ("type", value.get_type().into()),
+      ("variablesReference", self.reference(&value).into()),
+    ]))
+  }
+  /// Profundidad de la llamada actual del hilo, desde donde se avanza
+  fn depth(&mut self, thread_id: i64) -> usize {
+    self
+      .thread(thread_id)
+      .ok()
+      .and_then(|thread| Location::of(&thread_frames(&thread)))
+      .map(|location| location.depth)
+      .unwrap_or(0)
+  }
+  /// Responde un mensaje, retorna falso si el editor termino la sesion
+  fn handle(&mut self, request: Json) -> bool {
+    let arguments = request.get("arguments").clone();
+    let thread_id = arguments.get("threadId").as_i64().unwrap_or(MAIN_THREAD);
+    let command = request.get("command").as_str().unwrap_or_default();
+    let result = match command {
+      "initialize" => {
+        self.client.respond(
+          &request,
+          Ok(Json::object([
+            ("supportsConfigurationDoneRequest", true.into()),
+            ("supportsTerminateRequest", true.into()),
+          ])),
+        );
+        self.client.event("initialized", Json::Null);
+        return true;
+      }
+      "launch" => self.launch(&arguments),
+      "setBreakpoints" => self.set_breakpoints(&arguments),
+      "setExceptionBreakpoints" => Ok(Json::object([("breakpoints", vec![].into())])),
+      "configurationDone" => {
+        self.client.respond(&request, Ok(Json::Null));
+        if self.stop_on_entry {
+          self.check_pause();
+          self.stop("entry", MAIN_THREAD);
+        } else {
+          self.resume(None);
+        }
+        return true;
+      }
+      "threads" => {
+        let threads: Vec<Json> = self
+          .threads()
+          .into_iter()
+          .map(|(id, _)| {
+            let name = if id == MAIN_THREAD {
+              "principal".to_string()
+            } else {
+              format!("hilo {id}")
+            };
+            Json::object([("id", id.into()), ("name", name.into())])
+          })
+          .collect();
+        Ok(Json::object([("threads", threads.into())]))
+      }
+      "stackTrace" => self.stack_trace(&arguments),
+      "scopes" => self.scopes(&arguments),
+      "variables" => self.variables(&arguments),
+      "evaluate" => self.evaluate(&arguments),
+      "continue" => {
+        self.resume(None);
+        Ok(Json::object([("allThreadsContinued", true.into())]))
+      }
+      "next" => {
+        let depth = self.depth(thread_id);
+        self.resume(Some((thread_id, Step::Over(depth))));
+        Ok(Json::Null)
+      }
+      "stepIn" => {
+        self.resume(Some((thread_id, Step::Into)));
+        Ok(Json::Null)
+      }
+      "stepOut" => {
+        let depth = self.depth(thread_id);
+        self.resume(Some((thread_id, Step::Out(depth))));
+        Ok(Json::Null)
+      }
+      "pause" => {
+        self.pause = true;
+        Ok(Json::Null)
+      }
+      // Despues de `terminate` el editor aun envia `disconnect` para cerrar la sesion
+      "terminate" => {
+        if self.vm.is_some() {
+          self.finish(1);
+        }
+        Ok(Json::Null)
+      }
+      "disconnect" => {
+        if let Some(vm) = self.vm.take() {
+          vm.read().clear_stack();
+        }
+        if let Some(output) = self.output.take() {
+          output.stop();
+        }
+        self.client.respond(&request, Ok(Json::Null));
+        return false;
+      }
+      command => Err(format!("Comando '{command}' no soportado")),
+    };
+    self.client.respond(&request, result);
+    true
+  }
+  /// Ejecuta un ciclo del planificador si el programa no esta detenido
+  fn run(&mut self) {
+    if let Some((reason, thread_id)) = self.check_pause() {
+      return self.stop(reason, thread_id);
+    }
+    let Some(vm) = self.vm.clone() else {
+      self.running = false;
+      return;
+    };
+    let process_manager = vm.read().get_process_manager();
+    let result = process_manager.read().run_instruction();
+    match result {
+      InterpretResult::Continue => {}
+      InterpretResult::Ok => self.finish(0),
+      result => {
+        if let Some(message) = vm.read().error_message(&result) {
+          self.client.output("stderr", message);
+        }
+        vm.read().clear_stack();
+        self.finish(1);
+      }
+    }
+  }
+}
+
+/// Lo que imprime el programa mientras se ejecuta, se lee en otro hilo y se envia como eventos
+/// `output`
+struct Capture {
+  redirect: sys::Redirect,
+  forwarder: JoinHandle<()>,
+}
+impl Capture {
+  fn start(client: Arc<Client>) -> Option<Self> {
+    let (redirect, mut output) = sys::redirect_stdout()?;
+    let forwarder = std::thread::spawn(move || {
+      let mut buffer = [0; 4096];
+      while let Ok(len @ 1..) = output.read(&mut buffer) {
+        client.output(
+          "stdout",
+          String::from_utf8_lossy(&buffer[..len]).to_string(),
+        );
+      }
+    });
+    Some(Self {
+      redirect,
+      forwarder,
+    })
+  }
+  /// Devuelve la salida estandar y espera a que se envie todo lo que quedo en la tuberia
+  fn stop(self) {
+    let _ = std::io::stdout().flush();
+    self.redirect.restore();
+    let _ = self.forwarder.join();
+  }
+}
+
+/// Atiende a un editor con el protocolo de adaptadores de depuracion (DAP) por la entrada y la
+/// salida estandar, la salida del programa se envia como eventos `output`
+///
+/// `path` es el programa que se depura si el mensaje `launch` no indica otro
+pub fn debug_adapter(path: Option<PathBuf>, options: VMOptions) {
+  // Los mensajes usan su propia copia de la salida, la del programa se cambia al ejecutarlo
+  let writer: Box<dyn Write + Send> = match sys::protocol_output() {
+    Some(stdout) => Box::new(stdout),
+    None => Box::new(std::io::stdout()),
+  };
+  let client = Arc::new(Client {
+    writer: Mutex::new((0, writer)),
+  });
+  serve(client, path, options)
+}
+fn serve(client: Arc<Client>, path: Option<PathBuf>, options: VMOptions) {
+  let messages = read_messages(std::io::stdin());
+  let mut adapter = Adapter {
+    client,
+    options,
+    path,
+    vm: None,
+    running: false,
+    stop_on_entry: false,
+    breakpoints: vec![],
+    step: None,
+    pause: false,
+    thread_ids: HashMap::new(),
+    next_thread_id: MAIN_THREAD,
+    previous: HashMap::new(),
+    frames: vec![],
+    references: vec![],
+    output: None,
+  };
+  loop {
+    let message = if adapter.running {
+      match messages.try_recv() {
+        Ok(message) => Some(message),
+        Err(TryRecvError::Empty) => None,
+        Err(TryRecvError::Disconnected) => return,
+      }
+    } else {
+      match messages.recv() {
+        Ok(message) => Some(message),
+        Err(_) => return,
+      }
+    };
+    match message {
+      Some(message) => {
+        if !adapter.handle(message) {
+          return;
+        }
+      }
+      None => adapter.run(),
+    }
+  }
+}
+
+#[cfg(unix)]
+mod sys {
+  use std::ffi::c_int;
+  use std::fs::File;
+  use std::os::unix::io::FromRawFd as _;
+
+  extern "C" {
+    fn dup(fd: c_int) -> c_int;
+    fn dup2(old: c_int, new: c_int) -> c_int;
+    fn pipe(fds: *mut c_int) -> c_int;
+    fn close(fd: c_int) -> c_int;
+  }
+  const STDOUT: c_int = 1;
+
+  /// Una copia de la salida estandar que no cambia al redirigirla
+  pub fn protocol_output() -> Option<File> {
+    let stdout = unsafe { dup(STDOUT) };
+    (stdout >= 0).then(|| unsafe { File::from_raw_fd(stdout) })
+  }
+
+  /// La salida estandar original mientras el programa escribe en la tuberia
+  pub struct Redirect {
+    stdout: c_int,
+  }
+  impl Redirect {
+    /// Al volver a la salida original se cierra la tuberia y quien la lee llega al final
+    pub fn restore(self) {
+      unsafe {
+        dup2(self.stdout, STDOUT);
+        close(self.stdout);
+      }
+    }
+  }
+
+  /// Cambia la salida estandar por una tuberia, lo que imprime el programa se lee de ella
+  pub fn redirect_stdout() -> Option<(Redirect, File)> {
+    unsafe {
+      let stdout = dup(STDOUT);
+      if stdout < 0 {
+        return None;
+      }
+      let mut fds = [0; 2];
+      if pipe(fds.as_mut_ptr()) != 0 {
+        close(stdout);
+        return None;
+      }
+      let [read, write] = fds;
+      if dup2(write, STDOUT) < 0 {
+        close(stdout);
+        close(read);
+        close(write);
+        return None;
+      }
+      close(write);
+      Some((Redirect { stdout }, File::from_raw_fd(read)))
+    }
+  }
+}
+#[cfg(windows)]
+mod sys {
+  use std::ffi::c_void;
+  use std::fs::File;
+  use std::os::windows::io::FromRawHandle as _;
+  use std::ptr::null_mut;
+
+  type Handle = *mut c_void;
+
+  #[link(name = "kernel32")]
+  extern "system" {
+    fn GetStdHandle(std_handle: u32) -> Handle;
+    fn SetStdHandle(std_handle: u32, handle: Handle) -> i32;
+    fn CreatePipe(read: *mut Handle, write: *mut Handle, attributes: *mut c_void, size: u32)
+      -> i32;
+    fn DuplicateHandle(
+      source_process: Handle,
+      source: Handle,
+      target_process: Handle,
+      target: *mut Handle,
+      access: u32,
+      inherit: i32,
+      options: u32,
+    ) -> i32;
+    fn GetCurrentProcess() -> Handle;
+    fn CloseHandle(handle: Handle) -> i32;
+  }
+  const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+  const DUPLICATE_SAME_ACCESS: u32 = 2;
+  const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+
+  fn stdout() -> Option<Handle> {
+    let handle = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+    (!handle.is_null() && handle != INVALID_HANDLE_VALUE).then_some(handle)
+  }
+
+  /// Una copia de la salida estandar que no cambia al redirigirla
+  pub fn protocol_output() -> Option<File> {
+    let stdout = stdout()?;
+    let mut copy = null_mut();
+    unsafe {
+      let process = GetCurrentProcess();
+      if DuplicateHandle(
+        process,
+        stdout,
+        process,
+        &mut copy,
+        0,
+        0,
+        DUPLICATE_SAME_ACCESS,
+      ) == 0
+      {
+        return None;
+      }
+      Some(File::from_raw_handle(copy))
+    }
+  }
+
+  /// La salida estandar original mientras el programa escribe en la tuberia
+  pub struct Redirect {
+    stdout: Handle,
+    write: Handle,
+  }
+  impl Redirect {
+    /// Al volver a la salida original se cierra la tuberia y quien la lee llega al final
+    pub fn restore(self) {
+      unsafe {
+        SetStdHandle(STD_OUTPUT_HANDLE, self.stdout);
+        CloseHandle(self.write);
+      }
+    }
+  }
+
+  /// Cambia la salida estandar por una tuberia, lo que imprime el programa se lee de ella. Rust
+  /// busca la salida estandar en cada escritura, asi que `print!` usa la tuberia
+  pub fn redirect_stdout() -> Option<(Redirect, File)> {
+    let stdout = stdout()?;
+    let (mut read, mut write) = (null_mut(), null_mut());
+    unsafe {
+      if CreatePipe(&mut read, &mut write, null_mut(), 0) == 0 {
+        return None;
+      }
+      if SetStdHandle(STD_OUTPUT_HANDLE, write) == 0 {
+        CloseHandle(read);
+        CloseHandle(write);
+        return None;
+      }
+      Some((Redirect { stdout, write }, File::from_raw_handle(read)))
+    }
+  }
+}
+#[cfg(not(any(unix, windows)))]
+mod sys {
+  use std::fs::File;
+
+  pub fn protocol_output() -> Option<File> {
+    None
+  }
+
+  pub struct Redirect;
+  impl Redirect {
+    pub fn restore(self) {}
+  }
+
+  /// La salida del programa se mezcla con los mensajes del protocolo
+  pub fn redirect_stdout() -> Option<(Redirect, File)> {
+    None
+  }
+}
@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use super::stack::{CallFrame, VarsManager};
+use super::vm::{AsyncThread, VM};
+use super::{InterpretResult, VMOptions};
+use crate::compiler::{Compiler, PromiseData, Value};
+use crate::MultiRefHash;
+
+mod dap;
+mod prompt;
+pub use dap::debug_adapter;
+pub use prompt::debug;
+
+#[derive(Clone, PartialEq)]
+struct Location {
+  path: PathBuf,
+  /// Empieza en 0, como en `Position`
+  line: usize,
+  /// Cuantas llamadas hay en el hilo
+  depth: usize,
+}
+impl Location {
+  /// La posicion de la llamada mas reciente que no es una funcion nativa
+  fn of(frames: &[CallFrame]) -> Option<Self> {
+    let frame = current_frame(frames)?;
+    Some(Self {
+      path: absolute(&frame.source_path()?),
+      line: frame.next_line(),
+      depth: frames.len(),
+    })
+  }
+  /// Si la ejecucion llego a otra linea desde la posicion anterior
+  fn changed(&self, previous: Option<&Location>) -> bool {
+    previous.is_none_or(|previous| previous.path != self.path || previous.line != self.line)
+  }
+}
+
+struct Breakpoint {
+  path: PathBuf,
+  /// Empieza en 1, como en los editores
+  line: usize,
+}
+impl Breakpoint {
+  /// La ruta se compara completa o, si no existe, como el final de la ruta del archivo
+  fn matches(&self, location: &Location) -> bool {
+    location.line + 1 == self.line
+      && (location.path == self.path || location.path.ends_with(&self.path))
+  }
+}
+/// Se detiene al llegar a la linea de un punto, no en cada instruccion de esa linea
+fn at_breakpoint(
+  breakpoints: &[Breakpoint],
+  location: &Location,
+  previous: Option<&Location>,
+) -> bool {
+  location.changed(previous)
+    && breakpoints
+      .iter()
+      .any(|breakpoint| breakpoint.matches(location))
+}
+
+/// Hasta donde avanza el hilo antes de volver a detenerse, sin contar los puntos de interrupcion
+enum Step {
+  Continue,
+  Into,
+  /// Con la profundidad de la llamada desde la que se avanza
+  Over(usize),
+  Out(usize),
+}
+impl Step {
+  fn should_pause(&self, location: &Location, previous: Option<&Location>) -> bool {
+    let changed = location.changed(previous);
+    match self {
+      Self::Continue => false,
+      Self::Into => changed || previous.is_some_and(|p| p.depth != location.depth),
+      Self::Over(depth) => changed && location.depth <= *depth,
+      Self::Out(depth) => location.depth < *depth,
+    }
+  }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+  path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+/// Un turno de una instruccion para revisar la posicion antes de cada una
+fn debug_options(options: VMOptions) -> VMOptions {
+  VMOptions {
+    time_slice: 1,
+    ..options
+  }
+}
+fn main_thread(vm: &MultiRefHash<VM>) -> MultiRefHash<AsyncThread> {
+  let process_manager = vm.read().get_process_manager();
+  let main = process_manager.read().get_main();
+  let thread = main.read().get_async();
+  thread
+}
+/// Las llamadas del hilo y de los que espera, hasta la que ejecuta la siguiente instruccion
+fn thread_frames(thread: &MultiRefHash<AsyncThread>) -> Vec<CallFrame> {
+  let threads = thread.read().active_threads();
+  threads
+    .iter()
+    .flat_map(|thread| thread.read().get_calls().clone())
+    .collect()
+}
+fn current_frame(frames: &[CallFrame]) -> Option<&CallFrame> {
+  frames.iter().rev().find(|frame| !frame.is_native())
+}
+/// Si es el entorno de un modulo o el de su cuerpo
+fn is_module_scope(vars: &VarsManager) -> bool {
+  vars.is_module() || vars.get_link().is_none_or(|link| link.read().is_module())
+}
+/// Las variables de la llamada y de los bloques que la contienen, sin las del modulo
+fn local_variables(frame: &CallFrame) -> Vec<(String, Value)> {
+  let mut variables: Vec<(String, Value)> = frame
+    .named_slots()
+    .into_iter()
+    .map(|(name, cell)| (name, cell.read().clone()))
+    .collect();
+  let mut scope = Some(frame.current_vars());
+  while let Some(vars) = scope {
+    let vars = vars.read();
+    if is_module_scope(&vars) {
+      break;
+    }
+    variables.extend(vars.variables());
+    scope = vars.get_link();
+  }
+  variables
+}
+/// Las variables del modulo donde se declaro la funcion de la llamada
+fn module_variables(frame: &CallFrame) -> Vec<(String, Value)> {
+  let mut variables = vec![];
+  let mut scope = Some(frame.current_vars());
+  while let Some(vars) = scope {
+    let vars = vars.read();
+    if is_module_scope(&vars) {
+      variables.extend(vars.variables());
+    }
+    if vars.is_module() {
+      break;
+    }
+    scope = vars.get_link();
+  }
+  variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+  variables
+}
+/// Evalua la expresion en un entorno hijo del de la llamada, con sus variables locales copiadas,
+/// y al terminar copia de vuelta los cambios en esas variables
+fn evaluate(vm: &MultiRefHash<VM>, frame: &CallFrame, code: &str) -> Result<Value, String> {
+  let path = frame
+    .source_path()
+    .ok_or_else(|| "No se puede evaluar en una funcion nativa".to_string())?;
+  let code = code.trim_end();
+  let code = if code.ends_with([';', '}']) {
+    code.to_string()
+  } else {
+    format!("{code};")
+  };
+  let ast = crate::agal_parser::Parser::new(&code, &path)
+    .produce_ast()
+    .map_err(|e| e.message)?;
+  let compiler = Compiler::expression(&ast)?;
+
+  let slots = frame.named_slots();
+  let scope: MultiRefHash<VarsManager> = VarsManager::crate_child(frame.current_vars()).into();
+  for (name, cell) in &slots {
+    scope.write().declare(name, cell.read().clone(), false);
+  }
+  let process_manager = vm.read().get_process_manager();
+  let main = process_manager.read().get_main();
+  let (thread, promise) = AsyncThread::from_frame(CallFrame::new_compiler(compiler, scope.clone()));
+  thread.write().set_module(main);
+  loop {
+    let result = thread
+      .read()
+      .run_slice(usize::MAX, process_manager.read().get_budget(), false);
+    match result {
+      InterpretResult::Continue if thread.read().is_waiting() => {
+        return Err("La expresion espera una promesa que aun no se cumple".to_string())
+      }
+      InterpretResult::Continue => {}
+      InterpretResult::Ok => break,
+      InterpretResult::RuntimeError(e)
+      | InterpretResult::CompileError(e)
+      | InterpretResult::LimitExceeded(e) => return Err(e),
+    }
+  }
+  for (name, cell) in slots {
+    if let Some(value) = scope.read().get(&name) {
+      *cell.write() = value.clone();
+    }
+  }
+  match promise.get_data() {
+    PromiseData::Ok(value) => Ok(value.read().clone()),
+    PromiseData::Err(error) => Err(error),
+    PromiseData::Pending => Ok(Value::Never),
+  }
+}
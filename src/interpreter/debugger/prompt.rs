@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::io::{BufRead as _, Write as _};
+use std::path::{Path, PathBuf};
+
+use super::{
+  absolute, at_breakpoint, current_frame, debug_options, evaluate, local_variables, main_thread,
+  module_variables, thread_frames, Breakpoint, Location, Step,
+};
+use crate::compiler::{Compiler, Value};
+use crate::interpreter::stack::CallFrame;
+use crate::interpreter::vm::VM;
+use crate::interpreter::{libs, InterpretResult, VMOptions};
+use crate::MultiRefHash;
+
+const HELP: &str = "Comandos:
+  punto, b <archivo:linea|linea>  agrega un punto de interrupcion
+  quitar, d <archivo:linea|linea> quita un punto de interrupcion
+  puntos                          lista los puntos de interrupcion
+  continuar, c                    continua hasta el siguiente punto
+  siguiente, n                    avanza una linea sin entrar en las llamadas
+  entrar, s                       avanza una linea entrando en las llamadas
+  fuera, o                        continua hasta salir de la llamada actual
+  locales, l                      muestra las variables de la llamada actual
+  modulo, m                       muestra las variables del modulo de la llamada actual
+  evaluar, p <expresion>          evalua una expresion en la llamada actual
+  pila, bt                        muestra las llamadas
+  ayuda, h                        muestra esta ayuda
+  salir, q                        termina el programa";
+
+struct Debugger {
+  vm: MultiRefHash<VM>,
+  main_path: PathBuf,
+  breakpoints: Vec<Breakpoint>,
+  step: Step,
+  sources: HashMap<PathBuf, Option<Vec<String>>>,
+}
+impl Debugger {
+  /// Solo se sigue al modulo principal y a los que importa
+  fn frames(&self) -> Vec<CallFrame> {
+    thread_frames(&main_thread(&self.vm))
+  }
+  fn source_line(&mut self, path: &Path, line: usize) -> Option<String> {
+    let lines = self.sources.entry(path.to_path_buf()).or_insert_with(|| {
+      std::fs::read_to_string(path)
+        .ok()
+        .map(|code| code.lines().map(str::to_string).collect())
+    });
+    lines.as_ref()?.get(line).cloned()
+  }
+  fn show_location(&mut self, location: &Location) {
+    println!(
+      "Detenido en {}:{}",
+      location.path.display(),
+      location.line + 1
+    );
+    if let Some(code) = self.source_line(&location.path, location.line) {
+      println!("{:>5} | {code}", location.line + 1);
+    }
+  }
+  fn parse_breakpoint(&self, arg: &str) -> Result<Breakpoint, String> {
+    let (path, line) = match arg.rsplit_once(':') {
+      Some((path, line)) => (absolute(Path::new(path)), line),
+      None => (self.main_path.clone(), arg),
+    };
+    match line.trim().parse() {
+      Ok(line) if line > 0 => Ok(Breakpoint { path, line }),
+      _ => Err(format!(
+        "Se esperaba un numero de linea, se recibio '{line}'"
+      )),
+    }
+  }
+  fn inspect(&self, value: &Value) -> String {
+    let process_manager = self.vm.read().get_process_manager();
+    let thread = process_manager.read().get_root_thread();
+    let thread = thread.read();
+    libs::inspect(value, &thread)
+  }
+  fn print_locals(&self) {
+    self.print_variables(local_variables, "No hay variables locales");
+  }
+  fn print_module(&self) {
+    self.print_variables(module_variables, "No hay variables en el modulo");
+  }
+  fn print_variables(&self, variables: fn(&CallFrame) -> Vec<(String, Value)>, empty: &str) {
+    let frames = self.frames();
+    let Some(frame) = current_frame(&frames) else {
+      return println!("No hay ninguna llamada en curso");
+    };
+    let variables = variables(frame);
+    if variables.is_empty() {
+      println!("{empty}");
+    }
+    for (name, value) in variables {
+      println!("  {name} = {}", self.inspect(&value));
+    }
+  }
+  fn print_stack(&self) {
+    let frames = self.frames();
+    if frames.is_empty() {
+      return println!("No hay ninguna llamada en curso");
+    }
+    // La llamada mas reciente aun no ejecuta su siguiente instruccion, las demas esperan a la
+    // llamada que hicieron
+    for (index, frame) in frames.iter().rev().enumerate() {
+      let location = if index == 0 {
+        frame.next_location()
+      } else {
+        frame.location()
+      };
+      println!("  #{index} {location}");
+    }
+  }
+  fn evaluate(&self, code: &str) -> Result<Value, String> {
+    let frames = self.frames();
+    let frame =
+      current_frame(&frames).ok_or_else(|| "No hay ninguna llamada en curso".to_string())?;
+    evaluate(&self.vm, frame, code)
+  }
+  /// Lee comandos hasta uno que continue la ejecucion, retorna falso para terminar el programa
+  fn prompt(&mut self, input: &mut impl Iterator<Item = String>) -> bool {
+    loop {
+      print!("(depurar) ");
+      let _ = std::io::stdout().flush();
+      let Some(line) = input.next() else {
+        println!();
+        return false;
+      };
+      let line = line.trim();
+      let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+      let arg = arg.trim();
+      let depth = Location::of(&self.frames())
+        .map(|location| location.depth)
+        .unwrap_or(0);
+      match command {
+        "" => {}
+        "punto" | "b" => match self.parse_breakpoint(arg) {
+          Ok(breakpoint) => {
+            println!(
+              "Punto de interrupcion en {}:{}",
+              breakpoint.path.display(),
+              breakpoint.line
+            );
+            self.breakpoints.push(breakpoint);
+          }
+          Err(e) => println!("{e}"),
+        },
+        "quitar" | "d" => match self.parse_breakpoint(arg) {
+          Ok(breakpoint) => {
+            let len = self.breakpoints.len();
+            self
+              .breakpoints
+              .retain(|b| b.path != breakpoint.path || b.line != breakpoint.line);
+            if len == self.breakpoints.len() {
+              println!("No hay un punto de interrupcion en esa linea");
+            }
+          }
+          Err(e) => println!("{e}"),
+        },
+        "puntos" => {
+          if self.breakpoints.is_empty() {
+            println!("No hay puntos de interrupcion");
+          }
+          for breakpoint in &self.breakpoints {
+            println!("  {}:{}", breakpoint.path.display(), breakpoint.line);
+          }
+        }
+        "continuar" | "c" => {
+          self.step = Step::Continue;
+          return true;
+        }
+        "siguiente" | "n" => {
+          self.step = Step::Over(depth);
+          return true;
+        }
+        "entrar" | "s" => {
+          self.step = Step::Into;
+          return true;
+        }
+        "fuera" | "o" => {
+          self.step = Step::Out(depth);
+          return true;
+        }
+        "locales" | "l" => self.print_locals(),
+        "modulo" | "m" => self.print_module(),
+        "evaluar" | "p" => match self.evaluate(arg) {
+          Ok(value) => println!("{}", self.inspect(&value)),
+          Err(e) => println!("Error: {e}"),
+        },
+        "pila" | "bt" => self.print_stack(),
+        "ayuda" | "h" => println!("{HELP}"),
+        "salir" | "q" => return false,
+        command => println!("Comando '{command}' desconocido, usa 'ayuda'"),
+      }
+    }
+  }
+}
+
+/// Ejecuta el programa deteniendose antes de la primera linea y en los puntos de interrupcion,
+/// los comandos se leen de la entrada estandar
+pub fn debug(compiler: Compiler, options: VMOptions) -> InterpretResult {
+  let main_path = absolute(&compiler.path);
+  let vm = VM::new(compiler, debug_options(options));
+  let mut debugger = Debugger {
+    vm: vm.clone(),
+    main_path,
+    breakpoints: vec![],
+    step: Step::Into,
+    sources: HashMap::new(),
+  };
+  let stdin = std::io::stdin();
+  let mut input = stdin.lock().lines().map_while(Result::ok);
+  let mut previous: Option<Location> = None;
+  let process_manager = vm.read().get_process_manager();
+  loop {
+    let location = Location::of(&debugger.frames());
+    if let Some(location) = &location {
+      let previous = previous.as_ref();
+      if at_breakpoint(&debugger.breakpoints, location, previous)
+        || debugger.step.should_pause(location, previous)
+      {
+        debugger.show_location(location);
+        if !debugger.prompt(&mut input) {
+          vm.read().clear_stack();
+          return InterpretResult::Ok;
+        }
+      }
+    }
+    if location.is_some() {
+      previous = location;
+    }
+    let result = process_manager.read().run_instruction();
+    match result {
+      InterpretResult::Continue => {}
+      InterpretResult::Ok => {
+        println!("El programa termino");
+        return result;
+      }
+      result => {
+        if let Some(message) = vm.read().error_message(&result) {
+          eprintln!("{message}");
+        }
+        vm.read().clear_stack();
+        return result;
+      }
+    }
+  }
+}
@@ -22,7 +22,7 @@ fn inspect_value(value: &Value, thread: &Thread) -> String {
   }
   .apply(&value.to_aga_string(thread))
 }
-fn inspect(value: &Value, thread: &Thread) -> String {
+pub fn inspect(value: &Value, thread: &Thread) -> String {
   match value {
     Value::Object(Object::Map(_, i)) => i
       .read()
@@ -1,11 +1,193 @@
-use crate::compiler::{Object, Value};
+use crate::compiler::{Function, NativeValue, Object, Promise, PromiseData, Value};
 use crate::functions_names;
+use crate::interpreter::{Combinator, Thread};
 use crate::util::OnError;
+use crate::MultiRefHash;
 
 pub const LIB_NAME: &str = ":constructores";
 const CADENA: &str = "Cadena";
 const LIST: &str = "Lista";
 
+const PROMISE: &str = "Promesa";
+const PROMISE_ALL: &str = "todas";
+const PROMISE_RACE: &str = "carrera";
+const PROMISE_ANY: &str = "cualquiera";
+const PROMISE_ALL_SETTLED: &str = "resueltas";
+const PROMISE_NEW: &str = "nueva";
+
+const HANDLE_PROMISE: &str = "promesa";
+const HANDLE_RESOLVE: &str = "resuelve";
+const HANDLE_REJECT: &str = "rechaza";
+
+/// Promesa que se cumple segun el combinador con las promesas de la lista
+fn combine(
+  name: &str,
+  args: Vec<Value>,
+  thread: &Thread,
+  combinator: Combinator,
+) -> Result<Value, String> {
+  let list = match args.first() {
+    Some(list) if list.is_array() => list.as_strict_array(thread)?,
+    _ => Err(format!("{name}: Se esperaba una lista de promesas"))?,
+  };
+  // Los valores que no son promesas cuentan como promesas cumplidas
+  let promises = list
+    .into_iter()
+    .map(|value| {
+      if value.is_promise() {
+        value.as_promise()
+      } else {
+        Promise::from(value)
+      }
+    })
+    .collect();
+  let result = Promise::new();
+  thread
+    .get_async()
+    .read()
+    .get_process_manager()
+    .read()
+    .get_reactions()
+    .add_group(promises, combinator, result.clone());
+  Ok(Value::Promise(result))
+}
+
+fn get_pending(name: &str, data: &MultiRefHash<NativeValue>) -> Result<Promise, String> {
+  let promise = data
+    .write()
+    .get_promise()
+    .cloned()
+    .on_error(|_| format!("{name}: Se esperaba una promesa"))?;
+  if !matches!(promise.get_data(), PromiseData::Pending) {
+    Err(format!("{name}: La promesa ya se cumplio"))?
+  }
+  Ok(promise)
+}
+/// Una promesa pendiente junto con las funciones para cumplirla desde el programa
+fn promise_handle() -> Value {
+  let promise = Promise::new();
+  let data = crate::compiler::Instance::new(format!("<{LIB_NAME}>::{PROMISE}"));
+  data.set_instance_property(HANDLE_PROMISE, Value::Promise(promise.clone()), true);
+  data.set_instance_property(
+    HANDLE_RESOLVE,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{HANDLE_RESOLVE}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, _, data| {
+          let promise = get_pending(HANDLE_RESOLVE, &data)?;
+          promise.set_value(args.first().cloned().unwrap_or(Value::Null));
+          Ok(Value::Null)
+        },
+        custom_data: promise.clone().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  data.set_instance_property(
+    HANDLE_REJECT,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{HANDLE_REJECT}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, thread, data| {
+          let promise = get_pending(HANDLE_REJECT, &data)?;
+          let error = args
+            .first()
+            .map(|error| error.to_aga_string(thread))
+            .unwrap_or_default();
+          promise.set_err(error);
+          Ok(Value::Null)
+        },
+        custom_data: promise.into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  Value::Object(Object::Map(Default::default(), data.into()))
+}
+
+fn promise_value() -> Value {
+  let hashmap = crate::compiler::Instance::new(format!("<{LIB_NAME}>::{PROMISE}"));
+  hashmap.set_instance_property(
+    PROMISE_ALL,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{PROMISE_ALL}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, thread, _| combine(PROMISE_ALL, args, thread, Combinator::All),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    PROMISE_RACE,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{PROMISE_RACE}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, thread, _| combine(PROMISE_RACE, args, thread, Combinator::Race),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    PROMISE_ANY,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{PROMISE_ANY}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, thread, _| combine(PROMISE_ANY, args, thread, Combinator::Any),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    PROMISE_ALL_SETTLED,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{PROMISE_ALL_SETTLED}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, args, thread, _| {
+          combine(PROMISE_ALL_SETTLED, args, thread, Combinator::AllSettled)
+        },
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    PROMISE_NEW,
+    Value::Object(
+      Function::Native {
+        name: format!("<{PROMISE}>::{PROMISE_NEW}"),
+        path: format!("<{LIB_NAME}>::{PROMISE}"),
+        chunk: Default::default(),
+        func: |_, _, _, _| Ok(promise_handle()),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  Value::Object(Object::Map(Default::default(), hashmap.into()))
+}
+
 pub fn lib_value() -> Value {
   let hashmap = crate::compiler::Instance::new(format!("<{LIB_NAME}>"));
 
@@ -55,5 +237,6 @@ pub fn lib_value() -> Value {
     ),
     true,
   );
+  hashmap.set_instance_property(PROMISE, promise_value(), true);
   Value::Object(Object::Map(Default::default(), hashmap.into()))
 }
@@ -2,6 +2,7 @@ use std::io::Write;
 
 use crate::compiler::{Class, Value};
 use crate::functions_names::{CONSOLE, CONSTRUCTOR, STRING};
+use crate::interpreter::Permission;
 use crate::util::{OnError, OnSome, SetColor};
 
 pub const LIB_NAME: &str = ":sa";
@@ -90,12 +91,12 @@ pub fn lib_value() -> Value {
           path: format!("<{LIB_NAME}>::{PATH}"),
           chunk: Default::default(),
           func: |this, _, thread, _| {
-            Ok(
-              match std::path::Path::new(&this.to_aga_string(thread)).is_file() {
-                true => Value::True,
-                false => Value::False,
-              },
-            )
+            let path = this.to_aga_string(thread);
+            thread.check_permission(Permission::FileSystem(&path))?;
+            Ok(match std::path::Path::new(&path).is_file() {
+              true => Value::True,
+              false => Value::False,
+            })
           },
           custom_data: ().into(),
         }
@@ -111,12 +112,12 @@ pub fn lib_value() -> Value {
           path: format!("<{LIB_NAME}>::{PATH}"),
           chunk: Default::default(),
           func: |this, _, thread, _| {
-            Ok(
-              match std::path::Path::new(&this.to_aga_string(thread)).is_dir() {
-                true => Value::True,
-                false => Value::False,
-              },
-            )
+            let path = this.to_aga_string(thread);
+            thread.check_permission(Permission::FileSystem(&path))?;
+            Ok(match std::path::Path::new(&path).is_dir() {
+              true => Value::True,
+              false => Value::False,
+            })
           },
           custom_data: ().into(),
         }
@@ -197,6 +198,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{READ_FILE}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::File::open(&path)
             .ok()
             .on_some_option(|mut file| {
@@ -227,6 +229,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{CREATE_FILE}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::File::create(&path)
             .ok()
             .map(|_| Value::Never)
@@ -250,6 +253,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{WRITE_FILE}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           let buffer = args
             .get(1)
             .map(|t| t.as_strict_buffer(thread))
@@ -280,6 +284,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{REMOVE_FILE}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::remove_file(&path)
             .ok()
             .map(|_| Value::Never)
@@ -304,6 +309,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{READ_DIR}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::read_dir(&path)
             .ok()
             .on_some_option(|dir| {
@@ -337,6 +343,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{REMOVE_DIR}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::remove_dir(&path)
             .ok()
             .map(|_| Value::Never)
@@ -360,6 +367,7 @@ pub fn lib_value() -> Value {
             .first()
             .map(|t| t.to_aga_string(thread))
             .on_error(|_| format!("{CREATE_DIR}: Se esperaba una ruta"))?;
+          thread.check_permission(Permission::FileSystem(&path))?;
           std::fs::create_dir(&path)
             .ok()
             .map(|_| Value::Never)
@@ -4,12 +4,19 @@ mod console;
 mod constructors;
 mod fs;
 mod math;
+mod native;
 mod net;
 mod time;
+pub use console::inspect;
+pub use native::LIB_PREFIX as NATIVE_PREFIX;
 
-pub fn libs(lib_name: String, mut cache: DataCache, resolver: impl FnOnce(&str) -> Value) -> Value {
+pub fn libs(
+  lib_name: String,
+  mut cache: DataCache,
+  resolver: impl FnOnce(&str) -> Value,
+) -> Result<Value, String> {
   if cache.has(&lib_name) {
-    return cache.get(&lib_name);
+    return Ok(cache.get(&lib_name));
   }
   let value = match lib_name.as_str() {
     constructors::LIB_NAME => constructors::lib_value(),
@@ -18,8 +25,11 @@ pub fn libs(lib_name: String, mut cache: DataCache, resolver: impl FnOnce(&str)
     time::LIB_NAME => time::lib_value(),
     net::LIB_NAME => net::lib_value(),
     fs::LIB_NAME => fs::lib_value(),
-    path => resolver(path),
+    path => match path.strip_prefix(NATIVE_PREFIX) {
+      Some(library) => native::lib_value(std::path::Path::new(library))?,
+      None => resolver(path),
+    },
   };
   cache.set(lib_name, value.clone());
-  value
+  Ok(value)
 }
@@ -0,0 +1,363 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::path::Path;
+
+use crate::compiler::{
+  ChunkGroup, FromValue, Function, Instance, IntoValue, NativeValue, Object, Value,
+};
+use crate::ffi::extension::{Api, Handle, RegisterFn, Type, ABI_VERSION};
+use crate::interpreter::Thread;
+use crate::MultiRefHash;
+
+/// `importa ':nativo/ruta/libx.so'` carga una biblioteca compartida
+pub const LIB_PREFIX: &str = ":nativo/";
+/// Funcion que debe exportar la biblioteca, con la forma de `RegisterFn`
+const REGISTER: &CStr = c"agal_registrar";
+
+static API: Api = Api {
+  version: ABI_VERSION,
+  null: api_null,
+  boolean: api_boolean,
+  number: api_number,
+  string: api_string,
+  list: api_list,
+  list_push: api_list_push,
+  list_len: api_list_len,
+  list_get: api_list_get,
+  type_of: api_type_of,
+  as_boolean: api_as_boolean,
+  as_number: api_as_number,
+  as_string: api_as_string,
+  error: api_error,
+};
+
+thread_local! {
+  /// Los valores de la llamada actual por su direccion, asi un manejador invalido no se usa
+  static HANDLES: RefCell<HashMap<usize, Box<Value>>> = Default::default();
+  static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn handle(value: Value) -> *mut Handle {
+  let mut value = Box::new(value);
+  let handle = &mut *value as *mut Value as *mut Handle;
+  HANDLES.with(|handles| handles.borrow_mut().insert(handle as usize, value));
+  handle
+}
+fn with_value<R>(handle: *mut Handle, callback: impl FnOnce(Option<&Value>) -> R) -> R {
+  HANDLES.with(|handles| callback(handles.borrow().get(&(handle as usize)).map(|v| &**v)))
+}
+fn list_of(handle: *mut Handle) -> Option<MultiRefHash<Vec<Value>>> {
+  with_value(handle, |value| match value {
+    Some(Value::Object(Object::Array(list))) => Some(list.clone()),
+    _ => None,
+  })
+}
+unsafe fn bytes_to_string(bytes: *const u8, len: usize) -> String {
+  if bytes.is_null() || len == 0 {
+    return String::new();
+  }
+  String::from_utf8_lossy(std::slice::from_raw_parts(bytes, len)).into_owned()
+}
+
+extern "C" fn api_null() -> *mut Handle {
+  handle(Value::Null)
+}
+extern "C" fn api_boolean(value: bool) -> *mut Handle {
+  handle(Value::from(value))
+}
+extern "C" fn api_number(value: f64) -> *mut Handle {
+  handle(value.into_value())
+}
+unsafe extern "C" fn api_string(bytes: *const u8, len: usize) -> *mut Handle {
+  handle(Value::String(bytes_to_string(bytes, len)))
+}
+extern "C" fn api_list() -> *mut Handle {
+  handle(Value::Object(Vec::<Value>::new().into()))
+}
+extern "C" fn api_list_push(list: *mut Handle, value: *mut Handle) -> bool {
+  let value = with_value(value, |value| value.cloned());
+  match (list_of(list), value) {
+    (Some(list), Some(value)) => {
+      list.write().push(value);
+      true
+    }
+    _ => false,
+  }
+}
+extern "C" fn api_list_len(list: *mut Handle) -> usize {
+  list_of(list).map_or(0, |list| list.read().len())
+}
+extern "C" fn api_list_get(list: *mut Handle, index: usize) -> *mut Handle {
+  match list_of(list).and_then(|list| list.read().get(index).cloned()) {
+    Some(value) => handle(value),
+    None => std::ptr::null_mut(),
+  }
+}
+extern "C" fn api_type_of(value: *mut Handle) -> Type {
+  with_value(value, |value| match value {
+    None | Some(Value::Null | Value::Never) => Type::Null,
+    Some(Value::True | Value::False) => Type::Boolean,
+    Some(Value::Number(_)) => Type::Number,
+    Some(Value::String(_)) => Type::String,
+    Some(Value::Object(Object::Array(_))) => Type::List,
+    Some(_) => Type::Other,
+  })
+}
+unsafe extern "C" fn api_as_boolean(value: *mut Handle, out: *mut bool) -> bool {
+  let boolean = with_value(value, |value| match value {
+    Some(Value::True) => Some(true),
+    Some(Value::False) => Some(false),
+    _ => None,
+  });
+  match boolean {
+    Some(boolean) if !out.is_null() => {
+      *out = boolean;
+      true
+    }
+    _ => false,
+  }
+}
+unsafe extern "C" fn api_as_number(value: *mut Handle, out: *mut f64) -> bool {
+  // Los numeros complejos no tienen equivalente
+  let number = with_value(value, |value| f64::from_value(value.cloned()?).ok());
+  match number {
+    Some(number) if !out.is_null() => {
+      *out = number;
+      true
+    }
+    _ => false,
+  }
+}
+unsafe extern "C" fn api_as_string(
+  value: *mut Handle,
+  bytes: *mut *const u8,
+  len: *mut usize,
+) -> bool {
+  // El texto sigue en su caja dentro de `HANDLES` hasta que termina la llamada
+  let string = with_value(value, |value| match value {
+    Some(Value::String(string)) => Some((string.as_ptr(), string.len())),
+    _ => None,
+  });
+  match string {
+    Some((pointer, length)) if !bytes.is_null() && !len.is_null() => {
+      *bytes = pointer;
+      *len = length;
+      true
+    }
+    _ => false,
+  }
+}
+unsafe extern "C" fn api_error(bytes: *const u8, len: usize) -> *mut Handle {
+  let message = bytes_to_string(bytes, len);
+  ERROR.with(|error| *error.borrow_mut() = Some(message));
+  std::ptr::null_mut()
+}
+
+/// Funcion nativa de todas las funciones de extensiones, la de la extension esta en los datos
+fn call(
+  _: Value,
+  args: Vec<Value>,
+  _: &mut Thread,
+  data: MultiRefHash<NativeValue>,
+) -> Result<Value, String> {
+  let func = match &*data.read() {
+    NativeValue::Extension(func) => *func,
+    _ => Err("Se esperaba una funcion de una extension nativa".to_string())?,
+  };
+  // Los valores de la llamada se liberan al terminar, aunque la extension no los use
+  let previous = HANDLES.with(|handles| std::mem::take(&mut *handles.borrow_mut()));
+  let args: Vec<*mut Handle> = args.into_iter().map(handle).collect();
+  let result = unsafe { func(&API, args.as_ptr(), args.len()) };
+  let value = with_value(result, |value| value.cloned());
+  let error = ERROR.with(|error| error.borrow_mut().take());
+  HANDLES.with(|handles| *handles.borrow_mut() = previous);
+  match error {
+    Some(error) => Err(error),
+    None => Ok(value.unwrap_or(Value::Never)),
+  }
+}
+
+/// Carga la biblioteca y retorna un objeto con sus funciones, queda cargada hasta que termina
+/// el programa
+pub fn lib_value(path: &Path) -> Result<Value, String> {
+  let lib_name = format!("{LIB_PREFIX}{}", path.display());
+  let library = sys::open(path).map_err(|e| format!("{lib_name}: {e}"))?;
+  let register = sys::symbol(library, REGISTER).map_err(|e| format!("{lib_name}: {e}"))?;
+  let register: RegisterFn = unsafe { std::mem::transmute(register) };
+  let module = unsafe { register(ABI_VERSION).as_ref() }.ok_or_else(|| {
+    format!(
+      "{lib_name}: '{}' no retorno un modulo",
+      REGISTER.to_string_lossy()
+    )
+  })?;
+  if module.version != ABI_VERSION {
+    Err(format!(
+      "{lib_name}: La extension usa la version {} y el interprete la version {ABI_VERSION}",
+      module.version
+    ))?
+  }
+  let functions = if module.functions.is_null() || module.len == 0 {
+    &[]
+  } else {
+    unsafe { std::slice::from_raw_parts(module.functions, module.len) }
+  };
+
+  let hashmap = Instance::new(format!("<{lib_name}>"));
+  for function in functions {
+    if function.name.is_null() {
+      Err(format!("{lib_name}: Una funcion no tiene nombre"))?
+    }
+    let name = unsafe { CStr::from_ptr(function.name) }.to_string_lossy();
+    hashmap.set_instance_property(
+      &name,
+      Value::Object(
+        Function::Native {
+          name: format!("<{lib_name}>::{name}"),
+          path: format!("<{lib_name}>"),
+          chunk: ChunkGroup::default().into(),
+          func: call,
+          custom_data: MultiRefHash::new(NativeValue::Extension(function.func)),
+        }
+        .into(),
+      ),
+      true,
+    );
+  }
+  Ok(Value::Object(Object::Map(
+    Default::default(),
+    hashmap.into(),
+  )))
+}
+
+#[cfg(unix)]
+mod sys {
+  use std::ffi::{c_char, c_int, c_void, CStr, CString};
+  use std::os::unix::ffi::OsStrExt;
+  use std::path::Path;
+
+  const RTLD_NOW: c_int = 2;
+
+  extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *mut c_char;
+  }
+
+  fn last_error() -> String {
+    let error = unsafe { dlerror() };
+    if error.is_null() {
+      return "Error desconocido".to_string();
+    }
+    unsafe { CStr::from_ptr(error) }
+      .to_string_lossy()
+      .into_owned()
+  }
+  pub fn open(path: &Path) -> Result<*mut c_void, String> {
+    let path = CString::new(path.as_os_str().as_bytes())
+      .map_err(|_| "La ruta no puede contener caracteres nulos".to_string())?;
+    let library = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+    if library.is_null() {
+      Err(last_error())
+    } else {
+      Ok(library)
+    }
+  }
+  pub fn symbol(library: *mut c_void, name: &CStr) -> Result<*mut c_void, String> {
+    let symbol = unsafe { dlsym(library, name.as_ptr()) };
+    if symbol.is_null() {
+      Err(last_error())
+    } else {
+      Ok(symbol)
+    }
+  }
+}
+
+#[cfg(windows)]
+mod sys {
+  use std::ffi::{c_char, c_void, CStr};
+  use std::os::windows::ffi::OsStrExt;
+  use std::path::Path;
+
+  #[link(name = "kernel32")]
+  extern "system" {
+    fn LoadLibraryW(name: *const u16) -> *mut c_void;
+    fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+  }
+
+  pub fn open(path: &Path) -> Result<*mut c_void, String> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let library = unsafe { LoadLibraryW(path.as_ptr()) };
+    if library.is_null() {
+      Err(std::io::Error::last_os_error().to_string())
+    } else {
+      Ok(library)
+    }
+  }
+  pub fn symbol(library: *mut c_void, name: &CStr) -> Result<*mut c_void, String> {
+    let symbol = unsafe { GetProcAddress(library, name.as_ptr()) };
+    if symbol.is_null() {
+      Err(format!(
+        "No se encontro '{}': {}",
+        name.to_string_lossy(),
+        std::io::Error::last_os_error()
+      ))
+    } else {
+      Ok(symbol)
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::process::Command;
+
+  use crate::interpreter::{Motor, VMOptions};
+
+  /// Compila `tests/c/extension.c`, retorna `None` si no hay compilador de C
+  fn compile_example(dir: &std::path::Path) -> Option<()> {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let status = Command::new("cc")
+      .args(["-shared", "-fPIC", "-o"])
+      .arg(dir.join("libejemplo.so"))
+      .arg(format!("{root}/tests/c/extension.c"))
+      .arg(format!("-I{root}/include"))
+      .status()
+      .ok()?;
+    assert!(status.success(), "No se pudo compilar la extension");
+    Some(())
+  }
+
+  #[test]
+  fn test_c_extension() {
+    let dir = std::env::temp_dir().join(format!("agal_extension_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    if compile_example(&dir).is_none() {
+      eprintln!("No se encontro 'cc', se omite la prueba");
+      return;
+    }
+    let code = "importa ':nativo/libejemplo.so' como E;
+def suma = E::suma(1, 2.5);
+def saludo = E::saludo('Agal');
+def tipos = E::tipos(nada, cierto, 1, 'a', [], E);
+def mensaje = '';
+intenta { E::suma('x'); } captura (e) { mensaje = e; }
+exporta nombre suma;
+exporta nombre saludo;
+exporta nombre tipos;
+exporta nombre mensaje;";
+    let mut motor = Motor::new(VMOptions::default());
+    motor.load_source(code, dir.join("extension.aga")).unwrap();
+    let result = motor.run();
+    std::fs::remove_dir_all(&dir).ok();
+    result.unwrap();
+    assert_eq!(motor.get_export::<f64>("suma").unwrap(), 3.5);
+    assert_eq!(motor.get_export::<String>("saludo").unwrap(), "Hola, Agal");
+    assert_eq!(
+      motor.get_export::<Vec<f64>>("tipos").unwrap(),
+      vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]
+    );
+    let message: String = motor.get_export("mensaje").unwrap();
+    assert!(message.contains("suma recibe dos numeros"));
+  }
+}
@@ -1,10 +1,10 @@
-use std::net::TcpListener;
-use std::sync::Arc;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
 
 use crate::compiler::{Function, NativeValue, Promise, Value};
 use crate::interpreter::stack::CallFrame;
 use crate::interpreter::vm::AsyncThread;
-use crate::interpreter::VarsManager;
+use crate::interpreter::{Permission, VarsManager};
 use crate::util::{OnError, OnSome};
 use crate::MultiRefHash;
 
@@ -25,9 +25,12 @@ fn handle_client(
   socket: std::net::SocketAddr,
   callback: MultiRefHash<crate::compiler::Function>,
   module: MultiRefHash<crate::interpreter::ModuleThread>,
+  clients: &Mutex<Vec<Promise>>,
 ) {
   let data = crate::compiler::Instance::new(format!("<{LIB_NAME}>::{TCP_SOCKET}"));
 
+  // Al cancelar se cierra la conexion, asi tambien se desbloquea una lectura pendiente
+  let shutdown = stream.try_clone().ok();
   let stream: MultiRefHash<crate::compiler::NativeValue> = stream.into();
 
   let locals = VarsManager::crate_child(callback.read().get_scope().unwrap());
@@ -36,6 +39,16 @@ fn handle_client(
   thread.write().set_module(module.clone());
   thread.write().print_on_error();
 
+  if let Some(shutdown) = shutdown {
+    promise.on_cancel(move || {
+      let _ = shutdown.shutdown(Shutdown::Both);
+    });
+  }
+  let mut clients = clients.lock().unwrap();
+  clients.retain(|client| matches!(client.get_data(), crate::compiler::PromiseData::Pending));
+  clients.push(promise.clone());
+  drop(clients);
+
   data.set_instance_property(
     TCP_SOCKET_READ,
     Value::Object(crate::compiler::Object::Function(MultiRefHash::new(
@@ -171,6 +184,8 @@ pub fn lib_value() -> Value {
               format!("{TCP_SERVER}: Se esperaba una función como segundo argumento")
             })?;
 
+          thread.check_permission(Permission::Net(&addr))?;
+
           let promise = Promise::new();
           let value = Value::Promise(promise.clone());
 
@@ -181,11 +196,43 @@ pub fn lib_value() -> Value {
 
           let clone_listener = Arc::clone(&listener);
           let module = thread.get_async().read().get_module();
+          let clients: Arc<Mutex<Vec<Promise>>> = Default::default();
+
+          // Cancelar la promesa del servidor cancela a sus clientes y detiene el ciclo, para
+          // desbloquear `accept` se conecta a si mismo
+          let mut wake_addr = listener
+            .local_addr()
+            .on_error(|e| format!("{TCP_SERVER}: Error al verificar el servidor: {}", e))?;
+          if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(std::net::Ipv4Addr::LOCALHOST.into());
+          }
+          // La funcion solo la tiene el hilo del servidor, el recolector no debe liberarla
+          let process_manager = thread.get_async().read().get_process_manager();
+          let pin = process_manager.read().get_collector().pin(Value::Object(
+            crate::compiler::Object::Function(callback.clone()),
+          ));
+          let cancel_clients = Arc::clone(&clients);
+          let cancel_process_manager = process_manager.clone();
+          promise.on_cancel(move || {
+            for client in cancel_clients.lock().unwrap().drain(..) {
+              client.cancel();
+            }
+            cancel_process_manager.read().get_collector().unpin(pin);
+            let _ = TcpStream::connect(wake_addr);
+          });
+
           std::thread::spawn(move || loop {
-            match clone_listener.accept() {
-              Ok((stream, addr)) => handle_client(stream, addr, callback.clone(), module.clone()),
+            let accepted = clone_listener.accept();
+            if promise.is_canceled() {
+              break;
+            }
+            match accepted {
+              Ok((stream, addr)) => {
+                handle_client(stream, addr, callback.clone(), module.clone(), &clients)
+              }
               Err(e) => {
                 promise.set_err(format!("{TCP_SERVER}: Error al aceptar conexión: {}", e));
+                process_manager.read().get_collector().unpin(pin);
                 break;
               }
             }
@@ -1,9 +1,17 @@
-use crate::compiler::Value;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::compiler::{Function, Promise, Value};
+use crate::interpreter::{Thread, TimerAction};
+use crate::MultiRefHash;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub const LIB_NAME: &str = ":tmp";
 const NOW: &str = "ahora";
 const ZONE: &str = "ZONA";
+const SLEEP: &str = "espera";
+const TIMEOUT: &str = "temporizador";
+const INTERVAL: &str = "intervalo";
+
+const TIMER: &str = "Temporizador";
+const TIMER_CANCEL: &str = "cancela";
 
 #[cfg(windows)]
 mod c_time {
@@ -109,6 +117,72 @@ mod native_time {
   }
 }
 
+fn get_millis(name: &str, args: &[Value]) -> Result<Duration, String> {
+  match args.first() {
+    Some(millis) if millis.is_number() => {
+      let millis: Result<usize, String> = millis.as_number()?.floor().into();
+      Ok(Duration::from_millis(millis? as u64))
+    }
+    _ => Err(format!(
+      "{name}: Se esperaba un numero de milisegundos como primer argumento"
+    )),
+  }
+}
+fn get_callback(name: &str, args: &[Value]) -> Result<MultiRefHash<Function>, String> {
+  match args.get(1) {
+    Some(callback) if callback.is_function() => Ok(callback.as_function()),
+    _ => Err(format!(
+      "{name}: Se esperaba una funcion como segundo argumento"
+    )),
+  }
+}
+/// Programa la funcion y retorna un objeto para cancelarla
+fn schedule(name: &str, args: &[Value], thread: &Thread, repeat: bool) -> Result<Value, String> {
+  let delay = get_millis(name, args)?;
+  let callback = get_callback(name, args)?;
+  let module = thread.get_async().read().get_module();
+  let id = module.read().get_process_manager().read().get_timers().add(
+    delay,
+    if repeat { Some(delay) } else { None },
+    TimerAction::Call(callback, module.clone()),
+  );
+
+  let data = crate::compiler::Instance::new(format!("<{LIB_NAME}>::{TIMER}"));
+  data.set_instance_property(
+    TIMER_CANCEL,
+    Value::Object(
+      Function::Native {
+        name: format!("<{TIMER}>::{TIMER_CANCEL}"),
+        path: format!("<{LIB_NAME}>::{TIMER}"),
+        chunk: Default::default(),
+        func: |_, _, thread, timer| {
+          let id = timer
+            .read()
+            .get_timer()
+            .ok_or_else(|| format!("{TIMER_CANCEL}: Se esperaba un temporizador"))?;
+          let canceled = thread
+            .get_async()
+            .read()
+            .get_module()
+            .read()
+            .get_process_manager()
+            .read()
+            .get_timers()
+            .cancel(id);
+          Ok(if canceled { Value::True } else { Value::False })
+        },
+        custom_data: MultiRefHash::new(crate::compiler::NativeValue::Timer(id)),
+      }
+      .into(),
+    ),
+    true,
+  );
+  Ok(Value::Object(crate::compiler::Object::Map(
+    Default::default(),
+    data.into(),
+  )))
+}
+
 pub fn lib_value() -> Value {
   let hashmap = crate::compiler::Instance::new(format!("<{LIB_NAME}>"));
 
@@ -133,6 +207,62 @@ pub fn lib_value() -> Value {
     true,
   );
 
+  hashmap.set_instance_property(
+    SLEEP,
+    Value::Object(
+      crate::compiler::Function::Native {
+        name: format!("<{LIB_NAME}>::{SLEEP}"),
+        path: format!("<{LIB_NAME}>"),
+        chunk: crate::compiler::ChunkGroup::default().into(),
+        func: |_, args, thread, _| {
+          let delay = get_millis(SLEEP, &args)?;
+          let promise = Promise::new();
+          thread
+            .get_async()
+            .read()
+            .get_module()
+            .read()
+            .get_process_manager()
+            .read()
+            .get_timers()
+            .add(delay, None, TimerAction::Resolve(promise.clone()));
+          Ok(Value::Promise(promise))
+        },
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    TIMEOUT,
+    Value::Object(
+      crate::compiler::Function::Native {
+        name: format!("<{LIB_NAME}>::{TIMEOUT}"),
+        path: format!("<{LIB_NAME}>"),
+        chunk: crate::compiler::ChunkGroup::default().into(),
+        func: |_, args, thread, _| schedule(TIMEOUT, &args, thread, false),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    INTERVAL,
+    Value::Object(
+      crate::compiler::Function::Native {
+        name: format!("<{LIB_NAME}>::{INTERVAL}"),
+        path: format!("<{LIB_NAME}>"),
+        chunk: crate::compiler::ChunkGroup::default().into(),
+        func: |_, args, thread, _| schedule(INTERVAL, &args, thread, true),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+
   hashmap.set_instance_property(
     ZONE,
     Value::Object(
@@ -1,16 +1,28 @@
 mod cache;
+mod debugger;
 mod libs;
+mod modules;
+mod motor;
+mod permissions;
 pub mod proto;
 mod stack;
 mod vm;
-pub use stack::VarsManager;
-pub use vm::{ModuleThread, Thread};
+pub use debugger::{debug, debug_adapter};
+pub use motor::{HostModule, Motor};
+pub use permissions::{Grant, Permission, Permissions};
+pub use stack::{InterpretResult, VarsManager};
+pub use vm::{
+  wake_scheduler, Combinator, ModuleThread, Reaction, ReactionKind, Thread, TimerAction, VMOptions,
+};
 
-pub fn interpret(compiler: crate::compiler::Compiler) -> Result<crate::compiler::Value, ()> {
-  let vm = vm::VM::new(compiler);
+pub fn interpret(
+  compiler: crate::compiler::Compiler,
+  options: VMOptions,
+) -> Result<crate::compiler::Value, InterpretResult> {
+  let vm = vm::VM::new(compiler, options);
   match vm.read().interpret() {
-    stack::InterpretResult::Ok => {}
-    _ => Err(())?,
+    InterpretResult::Ok => {}
+    result => Err(result)?,
   }
   let value = vm.read().as_value();
   Ok(value)
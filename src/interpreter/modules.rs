@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Archivo que marca la raiz de un proyecto, con sus dependencias y alias
+const MANIFEST: &str = "proyecto.toml";
+/// Carpeta donde se buscan los paquetes que no estan en el manifiesto
+const MODULES_DIR: &str = "agal_modulos";
+/// Archivo que se importa al importar una carpeta
+const INDEX: &str = "indice.aga";
+const EXTENSION: &str = "aga";
+
+const DEPENDENCIES_SECTION: &str = "dependencias";
+const ALIASES_SECTION: &str = "alias";
+
+/// Lo que se usa del manifiesto, las rutas son relativas a la carpeta del manifiesto
+#[derive(Debug, Default)]
+struct Manifest {
+  root: PathBuf,
+  dependencies: HashMap<String, String>,
+  aliases: Vec<(String, String)>,
+}
+impl Manifest {
+  /// Busca el manifiesto en la carpeta del archivo y en las carpetas superiores
+  fn find(dir: &Path) -> Result<Option<Self>, String> {
+    for dir in dir.ancestors() {
+      let path = dir.join(MANIFEST);
+      if path.is_file() {
+        let source = std::fs::read_to_string(&path)
+          .map_err(|e| format!("No se pudo leer '{}': {e}", path.display()))?;
+        return Self::parse(&source, dir)
+          .map(Some)
+          .map_err(|e| format!("{}: {e}", path.display()));
+      }
+    }
+    Ok(None)
+  }
+  /// Lee las secciones `[dependencias]` y `[alias]`, las demas secciones se ignoran
+  fn parse(source: &str, root: &Path) -> Result<Self, String> {
+    let mut manifest = Self {
+      root: root.to_path_buf(),
+      ..Default::default()
+    };
+    let mut section = String::new();
+    for (index, line) in source.lines().enumerate() {
+      let line = strip_comment(line).trim();
+      if line.is_empty() {
+        continue;
+      }
+      if let Some(name) = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+      {
+        section = name.trim().to_string();
+        continue;
+      }
+      let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| format!("linea {}: Se esperaba 'nombre = \"valor\"'", index + 1))?;
+      let key = unquote(key.trim());
+      let value = unquote(value.trim());
+      match section.as_str() {
+        DEPENDENCIES_SECTION => {
+          manifest.dependencies.insert(key, value);
+        }
+        ALIASES_SECTION => manifest.aliases.push((key, value)),
+        _ => {}
+      }
+    }
+    // El alias mas largo gana, asi `@app/util` tiene prioridad sobre `@app`
+    manifest
+      .aliases
+      .sort_by_key(|(alias, _)| std::cmp::Reverse(alias.len()));
+    Ok(manifest)
+  }
+  fn alias(&self, specifier: &str) -> Option<PathBuf> {
+    self.aliases.iter().find_map(|(alias, target)| {
+      let rest = specifier.strip_prefix(alias.as_str())?;
+      let rest = match rest {
+        "" => "",
+        rest => rest.strip_prefix('/')?,
+      };
+      Some(self.root.join(target).join(rest))
+    })
+  }
+  fn dependency(&self, name: &str) -> Option<PathBuf> {
+    self.dependencies.get(name).map(|path| self.root.join(path))
+  }
+}
+
+fn strip_comment(line: &str) -> &str {
+  let mut in_string = false;
+  for (index, char) in line.char_indices() {
+    match char {
+      '"' => in_string = !in_string,
+      '#' if !in_string => return &line[..index],
+      _ => {}
+    }
+  }
+  line
+}
+fn unquote(value: &str) -> String {
+  value
+    .strip_prefix('"')
+    .and_then(|value| value.strip_suffix('"'))
+    .unwrap_or(value)
+    .to_string()
+}
+
+/// Un archivo, el mismo con la extension `.aga` o el indice de la carpeta
+fn candidates(path: &Path) -> Vec<PathBuf> {
+  let mut candidates = vec![path.to_path_buf()];
+  if path.extension().is_none() {
+    candidates.push(path.with_extension(EXTENSION));
+  }
+  candidates.push(path.join(INDEX));
+  candidates
+}
+
+/// Encuentra el archivo de un modulo importado desde `importer`:
+/// - Los alias del manifiesto se reemplazan por su ruta.
+/// - Las rutas que empiezan con `./`, `../` o son absolutas son relativas al archivo.
+/// - Los nombres se buscan en las dependencias del manifiesto, luego en las carpetas
+///   `agal_modulos` desde la carpeta del archivo hacia arriba y por ultimo junto al archivo.
+///
+/// Retorna la ruta canonica, o un error con las rutas que se revisaron (cada una tambien con
+/// la extension y como carpeta)
+pub fn resolve(specifier: &str, importer: &Path) -> Result<PathBuf, String> {
+  let importer = importer
+    .canonicalize()
+    .unwrap_or_else(|_| importer.to_path_buf());
+  let dir = importer.parent().unwrap_or(Path::new("."));
+  let manifest = Manifest::find(dir)?;
+
+  let mut bases = vec![];
+  let is_relative = specifier.starts_with("./") || specifier.starts_with("../");
+  if let Some(path) = manifest.as_ref().and_then(|m| m.alias(specifier)) {
+    bases.push(path);
+  } else if is_relative || Path::new(specifier).is_absolute() {
+    bases.push(dir.join(specifier));
+  } else {
+    let (name, rest) = specifier.split_once('/').unwrap_or((specifier, ""));
+    if let Some(path) = manifest.as_ref().and_then(|m| m.dependency(name)) {
+      bases.push(path.join(rest));
+    }
+    for dir in dir.ancestors() {
+      bases.push(dir.join(MODULES_DIR).join(specifier));
+    }
+    bases.push(dir.join(specifier));
+  }
+
+  let candidates: Vec<PathBuf> = bases.iter().flat_map(|base| candidates(base)).collect();
+  for candidate in &candidates {
+    if candidate.is_file() {
+      return candidate
+        .canonicalize()
+        .map_err(|e| format!("No se pudo leer el modulo '{specifier}': {e}"));
+    }
+  }
+  let searched = bases
+    .iter()
+    .map(|base| format!("\n\t\t{}", base.display()))
+    .collect::<String>();
+  Err(format!(
+    "No se encontro el modulo '{specifier}', se busco en:{searched}"
+  ))
+}
+
+/// Encuentra una extension nativa de `:nativo/ruta`, la ruta es relativa al archivo que la importa
+pub fn resolve_native(path: &str, importer: &Path) -> Result<PathBuf, String> {
+  let dir = importer.parent().unwrap_or(Path::new("."));
+  dir
+    .join(path)
+    .canonicalize()
+    .map_err(|_| format!("No se encontro la extension nativa '{path}'"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_manifest() {
+    let source = r#"
+[paquete]
+nombre = "ejemplo" # se ignora
+
+[dependencias]
+util = "libs/util" # comentario
+"con#gato" = "libs/gato"
+
+[alias]
+"@app" = "src"
+"@app/comun" = "comun"
+"#;
+    let root = Path::new("/proyecto");
+    let manifest = Manifest::parse(source, root).unwrap();
+    assert_eq!(manifest.dependencies.len(), 2);
+    assert_eq!(manifest.dependency("util"), Some(root.join("libs/util")));
+    assert_eq!(
+      manifest.dependency("con#gato"),
+      Some(root.join("libs/gato"))
+    );
+    assert_eq!(manifest.dependency("nombre"), None);
+    assert_eq!(manifest.aliases[0].0, "@app/comun");
+    assert_eq!(
+      manifest.alias("@app/comun/a"),
+      Some(root.join("comun").join("a"))
+    );
+    assert_eq!(
+      manifest.alias("@app/otro"),
+      Some(root.join("src").join("otro"))
+    );
+    assert_eq!(manifest.alias("@application"), None);
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let error = Manifest::parse("[alias]\n\nsin valor", Path::new("/")).unwrap_err();
+    assert!(error.starts_with("linea 3:"));
+  }
+
+  #[test]
+  fn test_resolve() {
+    let root = std::env::temp_dir().join(format!("agal_modulos_{}", std::process::id()));
+    let files = [
+      "src/principal.aga",
+      "src/otro.aga",
+      "src/junto.aga",
+      "src/carpeta/indice.aga",
+      "comun/a.aga",
+      "libs/util/indice.aga",
+      "libs/util/texto.aga",
+      "agal_modulos/paquete/indice.aga",
+    ];
+    for file in files {
+      let path = root.join(file);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, "").unwrap();
+    }
+    std::fs::write(
+      root.join(MANIFEST),
+      "[dependencias]\nutil = \"libs/util\"\n[alias]\n\"@app\" = \"src\"\n\"@app/comun\" = \"comun\"\n",
+    )
+    .unwrap();
+    let canonical = root.canonicalize().unwrap();
+    let importer = root.join("src/principal.aga");
+    let resolve = |specifier| resolve(specifier, &importer);
+    let results = [
+      ("./otro", "src/otro.aga"),
+      ("./otro.aga", "src/otro.aga"),
+      ("./carpeta", "src/carpeta/indice.aga"),
+      ("../comun/a", "comun/a.aga"),
+      ("@app/otro", "src/otro.aga"),
+      ("@app/comun/a", "comun/a.aga"),
+      ("util", "libs/util/indice.aga"),
+      ("util/texto", "libs/util/texto.aga"),
+      ("paquete", "agal_modulos/paquete/indice.aga"),
+      ("junto", "src/junto.aga"),
+    ]
+    .map(|(specifier, expected)| (resolve(specifier), Ok(canonical.join(expected))));
+    let missing = resolve("./falta");
+    std::fs::remove_dir_all(&root).ok();
+    for (result, expected) in results {
+      assert_eq!(result, expected);
+    }
+    let error = missing.unwrap_err();
+    assert!(error.starts_with("No se encontro el modulo './falta', se busco en:"));
+    assert!(error.contains(&canonical.join("src").join("./falta").display().to_string()));
+  }
+}
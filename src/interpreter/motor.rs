@@ -0,0 +1,286 @@
+use std::path::Path;
+
+use super::vm::VM;
+use super::{InterpretResult, VMOptions};
+use crate::compiler::{
+  ChunkGroup, Compiler, FromValue, Function, Instance, IntoValue, NativeFn, NativeValue, Object,
+  PromiseData, Value,
+};
+use crate::MultiRefHash;
+
+/// Un modulo definido por el programa que incluye al interprete, se importa como los de la
+/// biblioteca estandar: `importa ':juego' como J;`
+#[derive(Clone, Debug)]
+pub struct HostModule {
+  name: String,
+  instance: Instance,
+}
+impl HostModule {
+  /// El nombre se usa con `:` al inicio, `juego` y `:juego` son el mismo modulo
+  pub fn new(name: &str) -> Self {
+    let name = format!(":{}", name.trim_start_matches(':'));
+    Self {
+      instance: Instance::new(format!("<{name}>")),
+      name,
+    }
+  }
+  pub fn function(self, name: &str, func: NativeFn) -> Self {
+    self.function_with_data(name, func, ().into())
+  }
+  /// La funcion recibe `data` en cada llamada, como las funciones de las extensiones nativas
+  pub fn function_with_data(
+    self,
+    name: &str,
+    func: NativeFn,
+    data: MultiRefHash<NativeValue>,
+  ) -> Self {
+    let function = Function::Native {
+      name: format!("<{}>::{name}", self.name),
+      path: format!("<{}>", self.name),
+      chunk: ChunkGroup::default().into(),
+      func,
+      custom_data: data,
+    };
+    self.value(name, Value::Object(function.into()))
+  }
+  pub fn get_name(&self) -> &str {
+    &self.name
+  }
+  pub fn value(self, name: &str, value: impl IntoValue) -> Self {
+    self
+      .instance
+      .set_instance_property(name, value.into_value(), true);
+    self
+  }
+  fn into_value(self) -> Value {
+    Value::Object(Object::Map(Default::default(), self.instance.into()))
+  }
+}
+
+/// Ejecuta programas de Agal desde Rust
+///
+/// Los modulos y las variables globales se pueden registrar antes o despues de cargar el
+/// programa, al cargar otro programa se vuelven a registrar
+pub struct Motor {
+  options: VMOptions,
+  modules: Vec<(String, Value)>,
+  globals: Vec<(String, Value)>,
+  vm: Option<MultiRefHash<VM>>,
+}
+impl Motor {
+  pub fn new(options: VMOptions) -> Self {
+    Self {
+      options,
+      modules: vec![],
+      globals: vec![],
+      vm: None,
+    }
+  }
+  pub fn load_path(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let code = std::fs::read_to_string(path)
+      .map_err(|e| format!("No se pudo leer '{}': {e}", path.display()))?;
+    self.load_source(&code, path)
+  }
+  /// `path` se usa para los errores y para resolver las importaciones relativas
+  pub fn load_source(&mut self, code: &str, path: impl AsRef<Path>) -> Result<(), String> {
+    let compiler = crate::compile_source(code, path.as_ref())?;
+    self.load(compiler);
+    Ok(())
+  }
+  /// Carga el contenido de un archivo `.agab`, el bytecode se revisa antes de ejecutarlo
+  pub fn load_bytecode(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+    let compiler = crate::compile_bytecode(bytes)?;
+    self.load(compiler);
+    Ok(())
+  }
+  fn load(&mut self, compiler: Compiler) {
+    let vm = VM::new(compiler, self.options.clone());
+    for (name, value) in &self.modules {
+      vm.read()
+        .cache
+        .libs
+        .clone()
+        .set(name.clone(), value.clone());
+    }
+    for (name, value) in &self.globals {
+      vm.read()
+        .get_globals()
+        .write()
+        .declare(name, value.clone(), false);
+    }
+    self.vm = Some(vm);
+  }
+  fn vm(&self) -> Result<&MultiRefHash<VM>, String> {
+    self
+      .vm
+      .as_ref()
+      .ok_or_else(|| "No se ha cargado ningun programa".to_string())
+  }
+  pub fn register_module(&mut self, module: HostModule) {
+    let name = module.name.clone();
+    let value = module.into_value();
+    if let Some(vm) = &self.vm {
+      vm.read()
+        .cache
+        .libs
+        .clone()
+        .set(name.clone(), value.clone());
+    }
+    self.modules.retain(|(module, _)| module != &name);
+    self.modules.push((name, value));
+  }
+  /// Ejecuta el programa hasta que terminen el modulo principal, sus hilos y temporizadores
+  pub fn run(&self) -> Result<(), String> {
+    while !self.step()? {}
+    Ok(())
+  }
+  /// Un ciclo del planificador, retorna si el programa ya termino
+  pub fn step(&self) -> Result<bool, String> {
+    let vm = self.vm()?.read();
+    let process_manager = vm.get_process_manager();
+    let result = process_manager.read().run_instruction();
+    match result {
+      InterpretResult::Continue => Ok(false),
+      InterpretResult::Ok => Ok(true),
+      result => {
+        let message = vm.error_message(&result).unwrap_or_default();
+        vm.clear_stack();
+        Err(message)
+      }
+    }
+  }
+  /// El objeto con lo que exporta el modulo principal
+  pub fn exports(&self) -> Result<Value, String> {
+    Ok(self.vm()?.read().as_value())
+  }
+  pub fn get_export<T: FromValue>(&self, name: &str) -> Result<T, String> {
+    let vm = self.vm()?.read();
+    let thread = vm.get_process_manager().read().get_root_thread();
+    let value = vm
+      .as_value()
+      .get_instance_property(name, &thread.read())
+      .ok_or_else(|| format!("El modulo no exporta '{name}'"))?;
+    T::from_value(value)
+  }
+  /// Llama a una funcion exportada y ejecuta el programa hasta que retorne
+  pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let function: Value = self.get_export(name)?;
+    if !function.is_function() {
+      return Err(format!("'{name}' no es una funcion"));
+    }
+    let process_manager = self.vm()?.read().get_process_manager();
+    let promise = process_manager.read().call(function.as_function(), args);
+    while matches!(promise.get_data(), PromiseData::Pending) {
+      if self.step()? {
+        break;
+      }
+    }
+    match promise.get_data() {
+      PromiseData::Ok(value) => Ok(value.read().clone()),
+      PromiseData::Err(error) => Err(error),
+      PromiseData::Pending => Err(format!("'{name}' espera algo que nunca se cumplira")),
+    }
+  }
+  pub fn get_global<T: FromValue>(&self, name: &str) -> Result<T, String> {
+    let value = match &self.vm {
+      Some(vm) => vm.read().get_globals().read().get(name).cloned(),
+      None => self
+        .globals
+        .iter()
+        .find(|(global, _)| global == name)
+        .map(|(_, value)| value.clone()),
+    };
+    T::from_value(value.ok_or_else(|| format!("No existe la variable global '{name}'"))?)
+  }
+  /// Declara la variable global o cambia su valor
+  pub fn set_global(&mut self, name: &str, value: impl IntoValue) -> Result<(), String> {
+    let value = value.into_value();
+    if let Some(vm) = &self.vm {
+      let globals = vm.read().get_globals();
+      let mut globals = globals.write();
+      let is_set = if globals.has(name) {
+        globals.assign(name, value.clone())
+      } else {
+        globals.declare(name, value.clone(), false)
+      };
+      if is_set.is_none() {
+        return Err(format!("No se puede cambiar la variable global '{name}'"));
+      }
+    }
+    self.globals.retain(|(global, _)| global != name);
+    self.globals.push((name.to_string(), value));
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RECURSION: &str = "fn r(n) { si n > 30 { ret 0; } ret 1 + r(n + 1); }\n";
+
+  fn motor(max_call_depth: usize) -> Motor {
+    Motor::new(VMOptions {
+      max_call_depth,
+      ..Default::default()
+    })
+  }
+
+  #[test]
+  fn test_call_depth_limit() {
+    let mut motor = motor(10);
+    motor
+      .load_source(&format!("{RECURSION}r(0);"), "pila.aga")
+      .unwrap();
+    let error = motor.run().unwrap_err();
+    assert!(error.contains("Desbordamiento de pila: se supero el limite de 10 llamadas"));
+  }
+
+  #[test]
+  fn test_call_depth_limit_is_catchable() {
+    let mut motor = motor(25);
+    let code = format!(
+      "{RECURSION}def mensaje = \"\";\nintenta {{ r(0); }} captura (e) {{ mensaje = e; }}\nexporta nombre mensaje;"
+    );
+    motor.load_source(&code, "pila.aga").unwrap();
+    motor.run().unwrap();
+    let message: String = motor.get_export("mensaje").unwrap();
+    assert!(message.starts_with("Desbordamiento de pila: se supero el limite de 25 llamadas"));
+    // El mensaje lleva solo las llamadas mas recientes
+    assert_eq!(message.matches("\n\ten r <").count(), 20);
+    assert!(message.ends_with("\n\t... y 5 llamadas mas"));
+  }
+
+  #[test]
+  fn test_call_depth_within_limit() {
+    let mut motor = motor(100);
+    motor
+      .load_source(
+        &format!("{RECURSION}def total = r(0);\nexporta nombre total;"),
+        "pila.aga",
+      )
+      .unwrap();
+    motor.run().unwrap();
+    let total: f64 = motor.get_export("total").unwrap();
+    assert_eq!(total, 31.0);
+  }
+
+  #[test]
+  fn test_reactions_after_main_ends() {
+    let mut motor = motor(100);
+    let code = "importa ':constructores' como K;
+def error = [''];
+def total = [0];
+def h = K::Promesa::nueva();
+h::promesa::captura(fn(e) { error[0] = e; });
+K::Promesa::todas([1, 2])::entonces(fn(l) { total[0] = l[0] + l[1]; });
+h::rechaza('mal');
+exporta nombre error;
+exporta nombre total;";
+    motor.load_source(code, "promesas.aga").unwrap();
+    motor.run().unwrap();
+    assert_eq!(motor.get_export::<Vec<String>>("error").unwrap(), ["mal"]);
+    assert_eq!(motor.get_export::<Vec<f64>>("total").unwrap(), [3.0]);
+  }
+}
@@ -0,0 +1,218 @@
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+
+/// Nombre con el que empiezan los errores de permisos, para poder distinguirlos al capturarlos
+pub const PERMISSION_ERROR: &str = "PermisoDenegado";
+
+/// Acceso que necesita una funcion nativa
+pub enum Permission<'a> {
+  FileSystem(&'a str),
+  Net(&'a str),
+  /// Cargar una extension nativa, que puede hacer cualquier cosa
+  Native(&'a str),
+  /// Crear procesos, queda reservado para las funciones nativas que los creen, aun no hay
+  /// ninguna
+  #[allow(dead_code)]
+  Process,
+}
+impl Display for Permission<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::FileSystem(path) => write!(
+        f,
+        "No se permite acceder a la ruta '{path}' (usa --permitir-sa)"
+      ),
+      Self::Net(address) => write!(
+        f,
+        "No se permite usar la direccion '{address}' (usa --permitir-red)"
+      ),
+      Self::Native(path) => write!(
+        f,
+        "No se permite cargar la extension nativa '{path}' (usa --permitir-nativo)"
+      ),
+      Self::Process => write!(f, "No se permite crear procesos (usa --permitir-proceso)"),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum Grant<T> {
+  #[default]
+  Denied,
+  All,
+  Only(Vec<T>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Permissions {
+  /// Sin el modo seguro todo esta permitido
+  pub secure: bool,
+  pub file_system: Grant<PathBuf>,
+  /// Direcciones como `host:puerto`, o solo `host` para cualquier puerto
+  pub net: Grant<String>,
+  pub process: bool,
+  pub native: bool,
+}
+impl Permissions {
+  pub fn allow_paths(&mut self, paths: &str) {
+    self.file_system = Grant::Only(
+      paths
+        .split(',')
+        .map(|path| normalize(Path::new(path)))
+        .collect(),
+    );
+  }
+  pub fn allow_addresses(&mut self, addresses: &str) {
+    self.net = Grant::Only(addresses.split(',').map(str::to_string).collect());
+  }
+  pub fn check(&self, permission: Permission) -> Result<(), String> {
+    if !self.secure {
+      return Ok(());
+    }
+    let allowed = match &permission {
+      Permission::FileSystem(path) => match &self.file_system {
+        Grant::Denied => false,
+        Grant::All => true,
+        Grant::Only(roots) => {
+          let path = normalize(Path::new(path));
+          roots.iter().any(|root| path.starts_with(root))
+        }
+      },
+      Permission::Net(address) => match &self.net {
+        Grant::Denied => false,
+        Grant::All => true,
+        Grant::Only(addresses) => addresses.iter().any(|allowed| {
+          allowed == address
+            || address
+              .rsplit_once(':')
+              .is_some_and(|(host, _)| host == allowed)
+        }),
+      },
+      Permission::Native(_) => self.native,
+      Permission::Process => self.process,
+    };
+    if allowed {
+      Ok(())
+    } else {
+      Err(format!("{PERMISSION_ERROR}: {permission}"))
+    }
+  }
+}
+
+/// Ruta absoluta sin `.` ni `..`, para que no se pueda salir de una ruta permitida
+fn normalize(path: &Path) -> PathBuf {
+  if let Ok(path) = path.canonicalize() {
+    return path;
+  }
+  // La ruta aun no existe, por ejemplo al crear un archivo
+  let absolute = std::env::current_dir().unwrap_or_default().join(path);
+  let mut normalized = PathBuf::new();
+  for component in absolute.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir => {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  match (normalized.parent(), normalized.file_name()) {
+    (Some(parent), Some(name)) => parent
+      .canonicalize()
+      .map(|parent| parent.join(name))
+      .unwrap_or(normalized),
+    _ => normalized,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn secure() -> Permissions {
+    Permissions {
+      secure: true,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_everything_allowed_without_secure_mode() {
+    let permissions = Permissions::default();
+    assert!(permissions.check(Permission::FileSystem("/")).is_ok());
+    assert!(permissions.check(Permission::Net("localhost:80")).is_ok());
+    assert!(permissions.check(Permission::Native("libx.so")).is_ok());
+    assert!(permissions.check(Permission::Process).is_ok());
+  }
+
+  #[test]
+  fn test_secure_mode_denies_by_default() {
+    let permissions = secure();
+    let error = permissions.check(Permission::FileSystem("/")).unwrap_err();
+    assert!(error.starts_with(PERMISSION_ERROR));
+    assert!(error.contains("--permitir-sa"));
+    let error = permissions
+      .check(Permission::Net("localhost:80"))
+      .unwrap_err();
+    assert!(error.contains("--permitir-red"));
+    let error = permissions
+      .check(Permission::Native("libx.so"))
+      .unwrap_err();
+    assert!(error.contains("--permitir-nativo"));
+    let error = permissions.check(Permission::Process).unwrap_err();
+    assert!(error.contains("--permitir-proceso"));
+  }
+
+  #[test]
+  fn test_grant_all() {
+    let permissions = Permissions {
+      file_system: Grant::All,
+      net: Grant::All,
+      native: true,
+      process: true,
+      ..secure()
+    };
+    assert!(permissions.check(Permission::FileSystem("/")).is_ok());
+    assert!(permissions.check(Permission::Net("localhost:80")).is_ok());
+    assert!(permissions.check(Permission::Native("libx.so")).is_ok());
+    assert!(permissions.check(Permission::Process).is_ok());
+  }
+
+  #[test]
+  fn test_allowed_paths() {
+    let root = std::env::temp_dir().join(format!("agal_permisos_{}", std::process::id()));
+    let allowed = root.join("datos");
+    std::fs::create_dir_all(&allowed).unwrap();
+    std::fs::create_dir_all(root.join("otros")).unwrap();
+    let mut permissions = secure();
+    permissions.allow_paths(&format!("{},/no/existe", allowed.display()));
+    let check = |path: std::path::PathBuf| {
+      permissions
+        .check(Permission::FileSystem(&path.to_string_lossy()))
+        .is_ok()
+    };
+    let results = [
+      check(allowed.clone()),
+      // Los archivos que aun no existen, como al crearlos
+      check(allowed.join("nuevo.txt")),
+      check(allowed.join("..").join("otros")),
+      check(allowed.join("..").join("datos").join("nuevo.txt")),
+      check(root.join("otros")),
+    ];
+    std::fs::remove_dir_all(&root).ok();
+    assert_eq!(results, [true, true, false, true, false]);
+  }
+
+  #[test]
+  fn test_allowed_addresses() {
+    let mut permissions = secure();
+    permissions.allow_addresses("localhost:8080,ejemplo.com");
+    let check = |address| permissions.check(Permission::Net(address)).is_ok();
+    assert!(check("localhost:8080"));
+    assert!(!check("localhost:9000"));
+    assert!(check("ejemplo.com:443"));
+    assert!(check("ejemplo.com"));
+    assert!(!check("otro.com:443"));
+    assert!(!check("sub.ejemplo.com:443"));
+  }
+}
@@ -1,4 +1,5 @@
 mod function;
+mod promise;
 mod string;
 
 pub fn proto(
@@ -12,6 +13,7 @@ pub fn proto(
   let value = match value_type {
     crate::compiler::FUNCTION_TYPE | crate::compiler::NATIVE_FUNCTION_TYPE => function::prototype(),
     crate::compiler::STRING_TYPE => string::prototype(),
+    crate::compiler::PROMISE_TYPE => promise::prototype(),
     _ => {
       return None;
     }
@@ -0,0 +1,117 @@
+use crate::{
+  compiler::{ChunkGroup, Function, Object, Promise, Value},
+  interpreter::{Reaction, ReactionKind, Thread},
+  MultiRefHash,
+};
+
+const TYPE: &str = "<promesa>";
+
+const THEN: &str = "entonces";
+const CATCH: &str = "captura";
+const FINALLY: &str = "finalmente";
+const CANCEL: &str = "cancela";
+
+/// Crea una promesa que se cumple cuando la reaccion termina
+fn react(
+  name: &str,
+  this: Value,
+  args: Vec<Value>,
+  thread: &Thread,
+  kind: fn(MultiRefHash<Function>) -> ReactionKind,
+) -> Result<Value, String> {
+  if !this.is_promise() {
+    Err(format!("{name}: Se esperaba una promesa"))?
+  }
+  let source = this.as_promise();
+  let callback = match args.first() {
+    Some(callback) if callback.is_function() => callback.as_function(),
+    _ => Err(format!("{name}: Se esperaba una funcion como argumento"))?,
+  };
+  let result = Promise::new();
+  let async_thread = thread.get_async();
+  let async_thread = async_thread.read();
+  async_thread
+    .get_process_manager()
+    .read()
+    .get_reactions()
+    .add(Reaction {
+      source,
+      kind: kind(callback),
+      result: result.clone(),
+      module: async_thread.get_module(),
+    });
+  Ok(Value::Promise(result))
+}
+
+pub fn prototype() -> Value {
+  let hashmap = crate::compiler::Instance::new(TYPE.to_string());
+
+  hashmap.set_instance_property(
+    THEN,
+    Value::Object(
+      Function::Native {
+        path: "".to_string(),
+        name: format!("{TYPE}::{THEN}"),
+        func: |this, args, thread, _| react(THEN, this, args, thread, ReactionKind::Then),
+        chunk: ChunkGroup::default().into(),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    CATCH,
+    Value::Object(
+      Function::Native {
+        path: "".to_string(),
+        name: format!("{TYPE}::{CATCH}"),
+        func: |this, args, thread, _| react(CATCH, this, args, thread, ReactionKind::Catch),
+        chunk: ChunkGroup::default().into(),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+  hashmap.set_instance_property(
+    FINALLY,
+    Value::Object(
+      Function::Native {
+        path: "".to_string(),
+        name: format!("{TYPE}::{FINALLY}"),
+        func: |this, args, thread, _| react(FINALLY, this, args, thread, ReactionKind::Finally),
+        chunk: ChunkGroup::default().into(),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+
+  hashmap.set_instance_property(
+    CANCEL,
+    Value::Object(
+      Function::Native {
+        path: "".to_string(),
+        name: format!("{TYPE}::{CANCEL}"),
+        func: |this, _, _, _| {
+          if !this.is_promise() {
+            Err(format!("{CANCEL}: Se esperaba una promesa"))?
+          }
+          Ok(if this.as_promise().cancel() {
+            Value::True
+          } else {
+            Value::False
+          })
+        },
+        chunk: ChunkGroup::default().into(),
+        custom_data: ().into(),
+      }
+      .into(),
+    ),
+    true,
+  );
+
+  Value::Object(Object::Map(Default::default(), hashmap.into()))
+}
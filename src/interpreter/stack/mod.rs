@@ -1,6 +1,10 @@
 use std::fmt::Display;
 
-use crate::{compiler::Function, MultiRefHash};
+use crate::{
+  compiler::{Function, Value},
+  util::{Color, Position},
+  MultiRefHash,
+};
 
 mod vars_manager;
 pub use vars_manager::VarsManager;
@@ -11,6 +15,8 @@ pub enum InterpretResult {
   Continue,
   CompileError(String),
   RuntimeError(String),
+  /// El programa supero un limite de ejecucion, no se puede capturar
+  LimitExceeded(String),
 }
 
 #[derive(Clone)]
@@ -18,6 +24,7 @@ pub struct CallFrame {
   ip: usize,
   function: MultiRefHash<Function>,
   locals: Vec<MultiRefHash<VarsManager>>,
+  slots: Vec<MultiRefHash<Value>>,
 }
 impl CallFrame {
   pub fn new_compiler(
@@ -26,7 +33,7 @@ impl CallFrame {
   ) -> Self {
     Self::new(
       compiler.function.into(),
-      vec![VarsManager::crate_child(vars).into()],
+      vec![VarsManager::crate_module(vars).into()],
     )
   }
   pub fn new(function: MultiRefHash<Function>, locals: Vec<MultiRefHash<VarsManager>>) -> Self {
@@ -34,22 +41,70 @@ impl CallFrame {
       ip: 0,
       function,
       locals,
+      slots: vec![],
     }
   }
+  /// Solo los marcos de funciones del programa se pueden reutilizar en una llamada en cola
+  pub fn can_reuse(&self) -> bool {
+    matches!(&*self.function.read(), Function::Value { .. })
+  }
+  pub fn reuse(
+    &mut self,
+    function: MultiRefHash<Function>,
+    locals: Vec<MultiRefHash<VarsManager>>,
+  ) {
+    *self = Self::new(function, locals);
+  }
   pub fn get_ip(&self) -> usize {
     self.ip
   }
+  pub fn source_path(&self) -> Option<std::path::PathBuf> {
+    Some(self.function.read().source_path()?.to_path_buf())
+  }
+  pub fn is_native(&self) -> bool {
+    matches!(&*self.function.read(), Function::Native { .. })
+  }
+  /// Posicion en el codigo fuente de la siguiente instruccion
+  pub fn next_position(&self) -> Position {
+    self.current_chunk().read().get_position(self.ip)
+  }
+  pub fn next_line(&self) -> usize {
+    self.current_chunk().read().get_line(self.ip)
+  }
+  /// Las variables locales con nombre que estan vivas en la siguiente instruccion
+  pub fn named_slots(&self) -> Vec<(String, MultiRefHash<Value>)> {
+    let chunk = self.current_chunk();
+    let chunk = chunk.read();
+    chunk
+      .locals_at(self.ip)
+      .into_iter()
+      .filter_map(|local| {
+        let cell = self.slots.get(local.slot as usize)?;
+        Some((local.name.clone(), cell.clone()))
+      })
+      .collect()
+  }
   pub fn current_chunk(&self) -> MultiRefHash<crate::compiler::ChunkGroup> {
-    self.function.write().chunk()
+    self.function.read().chunk()
   }
-  pub fn current_line(&self) -> usize {
+  /// Posicion en el codigo fuente de la ultima instruccion leida
+  pub fn current_position(&self) -> Position {
     let instruction = self.ip.saturating_sub(1);
-    let instruction = if instruction > self.ip {
-      0
-    } else {
-      instruction
-    };
-    self.current_chunk().read().get_line(instruction)
+    self.current_chunk().read().get_position(instruction)
+  }
+  /// `en nombre <archivo:linea:columna>` de donde se declaro la funcion
+  pub fn function_location(&self) -> String {
+    self.function.read().location()
+  }
+  /// Como `location` pero con la siguiente instruccion, la que aun no se ejecuta
+  pub fn next_location(&self) -> String {
+    let position = (!self.is_native()).then(|| self.next_position());
+    self.function.read().location_at(position)
+  }
+  /// `en nombre <archivo:linea:columna>` con la instruccion que se esta ejecutando
+  pub fn location(&self) -> String {
+    let position = (!self.is_native()).then(|| self.current_position());
+    self.function.read().location_at(position)
   }
   pub fn read(&mut self) -> u8 {
     let byte = self.peek();
@@ -106,6 +161,46 @@ impl CallFrame {
   pub fn pop_vars(&mut self) -> MultiRefHash<VarsManager> {
     self.locals.pop().unwrap()
   }
+  /// Retorna la celda de una ranura local, creandola si aun no existe
+  pub fn slot(&mut self, slot: u8) -> MultiRefHash<Value> {
+    let index = slot as usize;
+    if index >= self.slots.len() {
+      self.slots.resize_with(index + 1, Default::default);
+    }
+    self.slots[index].clone()
+  }
+  pub fn set_slot(&mut self, slot: u8, value: Value) {
+    *self.slot(slot).write() = value;
+  }
+  /// Al salir de un bloque sus celdas se descartan, asi cada iteracion captura una celda nueva
+  pub fn truncate_slots(&mut self, len: u8) {
+    self.slots.truncate(len as usize);
+  }
+  pub fn capture(&self, index: u8) -> Option<MultiRefHash<Value>> {
+    self.function.read().get_capture(index)
+  }
+  pub fn close_over(&mut self, value: &Value) -> Result<Value, String> {
+    let mut cells = vec![];
+    for upvalue in value.get_upvalues() {
+      let cell = if upvalue.is_local {
+        Some(self.slot(upvalue.index))
+      } else {
+        self.capture(upvalue.index)
+      };
+      cells.push(cell.ok_or_else(|| "No se pudo capturar una variable".to_string())?);
+    }
+    Ok(value.close_over(self.current_vars(), cells))
+  }
+  /// Lo que el marco mantiene vivo: la funcion, sus entornos y sus variables capturables
+  pub fn references(
+    &self,
+  ) -> (
+    &MultiRefHash<Function>,
+    &[MultiRefHash<VarsManager>],
+    &[MultiRefHash<Value>],
+  ) {
+    (&self.function, &self.locals, &self.slots)
+  }
   pub fn in_class(&self) -> Option<MultiRefHash<crate::compiler::Class>> {
     self.function.read().get_in_class()
   }
@@ -125,7 +220,56 @@ pub fn call_stack_to_string(stack: &[CallFrame]) -> String {
   let mut index = stack.len();
   while index > 0 {
     index -= 1;
-    string.push_str(&format!("\n\t{}", stack[index].function.read().location()));
+    string.push_str(&format!("\n\t{}", stack[index].location()));
   }
   string
 }
+/// La linea del codigo fuente donde se detuvo la llamada mas reciente que tenga un archivo `.aga`,
+/// con la columna senalada
+pub fn source_excerpt(stack: &[CallFrame]) -> Option<String> {
+  use crate::util::SetColor as _;
+  let (path, position) = stack.iter().rev().find_map(|frame| {
+    let path = frame.function.read().source_path()?.to_path_buf();
+    if path.extension()? != "aga" {
+      return None;
+    }
+    Some((path, frame.current_position()))
+  })?;
+  let source = std::fs::read_to_string(&path).ok()?;
+  let line = source.lines().nth(position.line)?;
+
+  let number = (position.line + 1).to_string();
+  let margin = " ".repeat(number.len());
+  let bar = "|".to_string().set_color(Color::Cyan);
+  let caret = "^".to_string().set_color(Color::Red);
+  Some(format!(
+    "{margin}{} {}:{}:{}\n{margin} {bar}\n{} {bar} {line}\n{margin} {bar} {}{caret}",
+    "-->".to_string().set_color(Color::Cyan),
+    path.display(),
+    number,
+    position.column + 1,
+    number.set_color(Color::Cyan),
+    " ".repeat(position.column),
+  ))
+}
+/// Como `call_stack_to_string` pero solo con las llamadas mas recientes
+pub fn call_stack_summary(stack: &[CallFrame], limit: usize) -> String {
+  if stack.len() <= limit {
+    return call_stack_to_string(stack);
+  }
+  let hidden = stack.len() - limit;
+  format!(
+    "{}\n\t... y {hidden} llamadas mas",
+    call_stack_to_string(&stack[hidden..])
+  )
+}
+/// Con lo que empieza el error de superar el limite de llamadas, que ya incluye sus llamadas
+pub const STACK_OVERFLOW: &str = "Desbordamiento de pila";
+/// Las llamadas que se agregan al mostrar un error, salvo si el mensaje ya las incluye
+pub fn error_call_stack(error: &str, stack: &[CallFrame], limit: usize) -> String {
+  if error.starts_with(STACK_OVERFLOW) {
+    String::new()
+  } else {
+    call_stack_summary(stack, limit)
+  }
+}
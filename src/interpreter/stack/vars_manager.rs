@@ -14,6 +14,8 @@ pub struct VarsManager {
   variables: HashMap<String, Value>,
   constants: HashSet<String>,
   link: Option<MultiRefHash<VarsManager>>,
+  /// El entorno de un modulo, las variables de su cuerpo se declaran en un entorno hijo
+  is_module: bool,
 }
 impl VarsManager {
   pub fn new() -> Self {
@@ -21,6 +23,7 @@ impl VarsManager {
       variables: HashMap::new(),
       constants: HashSet::new(),
       link: None,
+      is_module: false,
     }
   }
   pub fn get_global() -> Self {
@@ -36,6 +39,15 @@ impl VarsManager {
     this.link = Some(parent);
     this
   }
+  pub fn crate_module(parent: MultiRefHash<Self>) -> Self {
+    Self {
+      is_module: true,
+      ..Self::crate_child(parent)
+    }
+  }
+  pub fn is_module(&self) -> bool {
+    self.is_module
+  }
   fn declare_keyword(&mut self, name: &str, value: Value) {
     self.variables.insert(name.to_string(), value.clone());
   }
@@ -75,6 +87,20 @@ impl VarsManager {
   pub fn get_link(&self) -> Option<MultiRefHash<Self>> {
     self.link.clone()
   }
+  pub fn values(&self) -> Vec<Value> {
+    self.variables.values().cloned().collect()
+  }
+  /// Las variables declaradas en este entorno, sin `nada`, `nulo`, `cierto` ni `falso`
+  pub fn variables(&self) -> Vec<(String, Value)> {
+    let mut variables: Vec<(String, Value)> = self
+      .variables
+      .iter()
+      .filter(|(name, _)| name.as_str() == THIS_NAME || !KEYWORDS.contains(&name.as_str()))
+      .map(|(name, value)| (name.clone(), value.clone()))
+      .collect();
+    variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+    variables
+  }
   pub fn remove(&mut self, name: &str) -> Option<Value> {
     if !self.variables.contains_key(name)
       || self.constants.contains(name)
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::profiler::Profiler;
+use super::VMOptions;
+use crate::interpreter::stack::InterpretResult;
+
+/// Recursos que consume el programa, se comparan con los limites de `VMOptions`
+#[derive(Debug)]
+pub struct Budget {
+  max_instructions: Option<usize>,
+  timeout: Option<Duration>,
+  max_allocated: Option<usize>,
+  started: Instant,
+  instructions: AtomicUsize,
+  allocated: AtomicUsize,
+  profiler: Option<Profiler>,
+}
+impl Budget {
+  pub fn new(options: &VMOptions) -> Self {
+    Self {
+      max_instructions: options.max_instructions,
+      timeout: options.timeout,
+      max_allocated: options.max_allocated,
+      started: Instant::now(),
+      instructions: AtomicUsize::new(0),
+      allocated: AtomicUsize::new(0),
+      profiler: options.profile.then(Profiler::new),
+    }
+  }
+  pub fn count_instruction(&self) {
+    self.instructions.fetch_add(1, Ordering::Relaxed);
+  }
+  /// Solo existe si se pidio perfilar la ejecucion
+  pub fn get_profiler(&self) -> Option<&Profiler> {
+    self.profiler.as_ref()
+  }
+  /// Suma los bytes aproximados de un objeto o texto creado por el programa, el total solo
+  /// crece porque no se sabe cuando se libera cada valor
+  pub fn allocate(&self, bytes: usize) {
+    self.allocated.fetch_add(bytes, Ordering::Relaxed);
+  }
+  /// Tiempo que falta para superar el tiempo maximo
+  pub fn remaining_time(&self) -> Option<Duration> {
+    self
+      .timeout
+      .map(|timeout| timeout.saturating_sub(self.started.elapsed()))
+  }
+  /// Retorna el resultado con el que se debe detener la maquina virtual si se supero algun limite
+  pub fn check(&self) -> Option<InterpretResult> {
+    if let Some(max) = self.max_instructions {
+      if self.instructions.load(Ordering::Relaxed) > max {
+        return Some(InterpretResult::LimitExceeded(format!(
+          "Se supero el limite de {max} instrucciones"
+        )));
+      }
+    }
+    if let Some(max) = self.max_allocated {
+      let allocated = self.allocated.load(Ordering::Relaxed);
+      if allocated > max {
+        return Some(InterpretResult::LimitExceeded(format!(
+          "Se supero el limite de {max} bytes asignados ({allocated} bytes)"
+        )));
+      }
+    }
+    if let Some(timeout) = self.timeout {
+      if self.started.elapsed() > timeout {
+        return Some(InterpretResult::LimitExceeded(format!(
+          "Se supero el tiempo maximo de {} ms",
+          timeout.as_millis()
+        )));
+      }
+    }
+    None
+  }
+}
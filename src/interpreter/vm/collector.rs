@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock, Weak};
+
+use crate::compiler::{
+  Class, Function, Instance, NativeValue, Object, Promise, PromiseData, Value,
+};
+use crate::interpreter::VarsManager;
+use crate::MultiRefHash;
+
+enum Node {
+  Value(Value),
+  Function(MultiRefHash<Function>),
+  Scope(MultiRefHash<VarsManager>),
+  Native(MultiRefHash<NativeValue>),
+}
+
+/// Marca todo lo que se puede alcanzar desde las raices, sin recursion para no desbordar la pila
+/// con estructuras muy profundas
+#[derive(Default)]
+pub struct Tracer {
+  visited: HashSet<usize>,
+  pending: Vec<Node>,
+}
+impl Tracer {
+  fn first_visit<T>(&mut self, reference: &MultiRefHash<T>) -> bool {
+    self.visited.insert(reference.addr())
+  }
+  pub fn value(&mut self, value: &Value) {
+    self.pending.push(Node::Value(value.clone()));
+  }
+  pub fn values(&mut self, values: impl IntoIterator<Item = Value>) {
+    self.pending.extend(values.into_iter().map(Node::Value));
+  }
+  pub fn cell(&mut self, cell: &MultiRefHash<Value>) {
+    if self.first_visit(cell) {
+      let value = cell.read().clone();
+      self.pending.push(Node::Value(value));
+    }
+  }
+  pub fn function(&mut self, function: &MultiRefHash<Function>) {
+    if self.first_visit(function) {
+      self.pending.push(Node::Function(function.clone()));
+    }
+  }
+  pub fn scope(&mut self, scope: &MultiRefHash<VarsManager>) {
+    if self.first_visit(scope) {
+      self.pending.push(Node::Scope(scope.clone()));
+    }
+  }
+  pub fn promise(&mut self, promise: &Promise) {
+    if let PromiseData::Ok(value) = promise.get_data() {
+      self.cell(&value);
+    }
+  }
+  fn native(&mut self, native: &MultiRefHash<NativeValue>) {
+    if self.first_visit(native) {
+      self.pending.push(Node::Native(native.clone()));
+    }
+  }
+  fn instance(&mut self, instance: &Instance) {
+    let mut current = Some(instance.clone());
+    while let Some(instance) = current {
+      self.values(instance.values());
+      current = instance.get_parent();
+    }
+  }
+  fn class(&mut self, class: &Class) {
+    let mut current = Some(class.clone());
+    while let Some(class) = current {
+      self.values(class.values());
+      if let Some(instance) = class.get_instance().cloned() {
+        self.instance(&instance);
+      }
+      current = class.get_parent().cloned();
+    }
+  }
+  fn is_marked<T>(&self, reference: &MultiRefHash<T>) -> bool {
+    self.visited.contains(&reference.addr())
+  }
+  fn mark(&mut self) {
+    while let Some(node) = self.pending.pop() {
+      match node {
+        Node::Value(value) => self.trace_value(value),
+        Node::Function(function) => {
+          let (scope, captures, class, data) = {
+            let function = function.read();
+            let data = match &*function {
+              Function::Native { custom_data, .. } => Some(custom_data.clone()),
+              _ => None,
+            };
+            (
+              function.get_scope(),
+              function.get_captures().to_vec(),
+              function.get_in_class(),
+              data,
+            )
+          };
+          if let Some(scope) = scope {
+            self.scope(&scope);
+          }
+          captures.iter().for_each(|cell| self.cell(cell));
+          if let Some(class) = class {
+            self.value(&Value::Object(Object::Class(class)));
+          }
+          if let Some(data) = data {
+            self.native(&data);
+          }
+        }
+        Node::Scope(scope) => {
+          let (values, link) = {
+            let scope = scope.read();
+            (scope.values(), scope.get_link())
+          };
+          self.values(values);
+          if let Some(link) = link {
+            self.scope(&link);
+          }
+        }
+        Node::Native(native) => {
+          let (value, promise) = match &*native.read() {
+            NativeValue::Promise(promise) => (None, Some(promise.clone())),
+            NativeValue::ValuePromise(value, promise) => {
+              (Some(value.clone()), Some(promise.clone()))
+            }
+            NativeValue::None
+            | NativeValue::TcpStream(_)
+            | NativeValue::Timer(_)
+            | NativeValue::Extension(_)
+            | NativeValue::Callback(_) => (None, None),
+          };
+          if let Some(value) = value {
+            self.native(&value);
+          }
+          if let Some(promise) = promise {
+            self.promise(&promise);
+          }
+        }
+      }
+    }
+  }
+  fn trace_value(&mut self, value: Value) {
+    match value {
+      Value::Object(Object::Map(properties, instance)) if self.first_visit(&properties) => {
+        let values: Vec<Value> = properties.read().values().cloned().collect();
+        self.values(values);
+        if let Some(instance) = instance.cloned() {
+          self.instance(&instance);
+        }
+      }
+      Value::Object(Object::Array(list)) if self.first_visit(&list) => {
+        let values = list.read().clone();
+        self.values(values);
+      }
+      Value::Object(Object::Function(function)) => self.function(&function),
+      Value::Object(Object::Class(class)) if self.first_visit(&class) => {
+        let class = class.read().clone();
+        self.class(&class);
+      }
+      Value::Lazy(lazy) => {
+        if let Some(value) = lazy.get().clone() {
+          self.pending.push(Node::Value(value));
+        }
+        self.function(&lazy.get_once());
+      }
+      Value::Ref(reference) => self.cell(&reference.get_cell()),
+      Value::Iterator(cell) => self.cell(&cell),
+      Value::Promise(promise) => self.promise(&promise),
+      _ => {}
+    }
+  }
+}
+
+/// Busca las clausuras que ya no se pueden alcanzar desde el programa y rompe los ciclos que
+/// las mantienen vivas, como una funcion guardada en el mismo entorno que captura
+#[derive(Debug)]
+pub struct Collector {
+  closures: Mutex<Vec<Weak<RwLock<Function>>>>,
+  /// Valores que usa codigo nativo fuera de los hilos, como la funcion de un servidor
+  pins: Mutex<Vec<(usize, Value)>>,
+  next_pin: AtomicUsize,
+  created: AtomicUsize,
+  /// Clausuras nuevas que deben crearse antes de la siguiente busqueda
+  next_collection: AtomicUsize,
+  threshold: Option<usize>,
+}
+impl Collector {
+  pub fn new(threshold: Option<usize>) -> Self {
+    Self {
+      closures: Default::default(),
+      pins: Default::default(),
+      next_pin: AtomicUsize::new(0),
+      created: AtomicUsize::new(0),
+      next_collection: AtomicUsize::new(threshold.unwrap_or_default()),
+      threshold,
+    }
+  }
+  pub fn register(&self, function: &MultiRefHash<Function>) {
+    if self.threshold.is_none() {
+      return;
+    }
+    self.closures.lock().unwrap().push(function.downgrade());
+    self.created.fetch_add(1, Ordering::Relaxed);
+  }
+  /// Mantiene vivo el valor hasta que se llame a `unpin` con el identificador retornado
+  pub fn pin(&self, value: Value) -> usize {
+    let id = self.next_pin.fetch_add(1, Ordering::Relaxed);
+    self.pins.lock().unwrap().push((id, value));
+    id
+  }
+  pub fn unpin(&self, id: usize) {
+    self.pins.lock().unwrap().retain(|(pin, _)| *pin != id);
+  }
+  pub fn should_collect(&self) -> bool {
+    self.threshold.is_some()
+      && self.created.load(Ordering::Relaxed) >= self.next_collection.load(Ordering::Relaxed)
+  }
+  /// Marca desde las raices del `tracer` y libera las clausuras que no se alcanzaron,
+  /// retorna cuantas se liberaron
+  pub fn collect(&self, mut tracer: Tracer) -> usize {
+    let pins: Vec<Value> = self
+      .pins
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(_, value)| value.clone())
+      .collect();
+    tracer.values(pins);
+    tracer.mark();
+
+    let mut released: Vec<MultiRefHash<Function>> = vec![];
+    let mut closures = self.closures.lock().unwrap();
+    closures.retain(|closure| match closure.upgrade().map(MultiRefHash::from) {
+      Some(function) if tracer.is_marked(&function) => true,
+      Some(function) => {
+        released.push(function);
+        false
+      }
+      None => false,
+    });
+    let live = closures.len();
+    drop(closures);
+
+    // Se sueltan fuera del candado, al liberar un entorno se pueden liberar otras clausuras
+    let count = released.len();
+    for function in released {
+      function.write().release();
+    }
+    // Con muchas clausuras vivas se espera mas para no recorrerlas en cada busqueda
+    self.created.store(0, Ordering::Relaxed);
+    self.next_collection.store(
+      self.threshold.unwrap_or_default().max(live),
+      Ordering::Relaxed,
+    );
+    count
+  }
+}
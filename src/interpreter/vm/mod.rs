@@ -2,7 +2,7 @@ use std::fmt::Debug;
 use std::path::Path;
 
 use super::cache::Cache;
-use super::stack::{call_stack_to_string, CallFrame, InterpretResult};
+use super::stack::{error_call_stack, CallFrame, InterpretResult};
 use super::VarsManager;
 use crate::compiler::{Compiler, Value};
 use crate::MultiRefHash;
@@ -10,17 +10,69 @@ use crate::MultiRefHash;
 mod thread;
 pub use thread::{AsyncThread, ModuleThread, Thread};
 
+mod budget;
+mod collector;
 mod process;
+mod profiler;
+mod reactions;
+pub use reactions::{Combinator, Reaction, ReactionKind};
+mod timers;
+pub use process::wake_scheduler;
+pub use timers::TimerAction;
+
+/// Llamadas que se muestran al reportar un error
+const CALL_STACK_SUMMARY: usize = 20;
+
+/// Opciones de ejecucion para quien incluya el interprete en su programa
+#[derive(Clone, Debug)]
+pub struct VMOptions {
+  /// Cantidad maxima de llamadas anidadas en un hilo antes de lanzar un desbordamiento de pila
+  pub max_call_depth: usize,
+  /// Instrucciones que ejecuta un hilo antes de ceder el turno a los demas
+  pub time_slice: usize,
+  /// Instrucciones que se pueden ejecutar entre todos los hilos
+  pub max_instructions: Option<usize>,
+  pub timeout: Option<std::time::Duration>,
+  /// Bytes aproximados que se pueden asignar al crear objetos, listas y textos durante toda la
+  /// ejecucion, lo que se libera no se descuenta
+  pub max_allocated: Option<usize>,
+  pub permissions: super::Permissions,
+  /// Una promesa rechazada que nadie espero detiene el programa en lugar de solo advertirlo
+  pub fatal_rejections: bool,
+  /// Clausuras nuevas antes de buscar ciclos que ya no se usan, sin valor nunca se buscan
+  pub gc_threshold: Option<usize>,
+  /// Al terminar muestra cuanto costo cada funcion, linea y operacion
+  pub profile: bool,
+  /// Archivo donde se escriben las pilas de llamadas para un grafico de llama
+  pub profile_stacks: Option<std::path::PathBuf>,
+}
+impl Default for VMOptions {
+  fn default() -> Self {
+    Self {
+      max_call_depth: 10_000,
+      time_slice: 256,
+      max_instructions: None,
+      timeout: None,
+      max_allocated: None,
+      permissions: Default::default(),
+      fatal_rejections: false,
+      gc_threshold: Some(10_000),
+      profile: false,
+      profile_stacks: None,
+    }
+  }
+}
 
 #[derive(Clone, Debug)]
 pub struct VM {
   pub cache: Cache,
+  pub options: VMOptions,
   globals: MultiRefHash<VarsManager>,
   process_manager: MultiRefHash<process::ProcessManager>,
 }
 
 impl VM {
-  pub fn new(compiler: Compiler) -> MultiRefHash<Self> {
+  pub fn new(compiler: Compiler, options: VMOptions) -> MultiRefHash<Self> {
     //let compiler = {let compiler = compiler;compiler.function.chunk().write()._print();compiler};
     let globals: MultiRefHash<VarsManager> = VarsManager::get_global().into();
 
@@ -28,7 +80,8 @@ impl VM {
     let vm: MultiRefHash<VM> = Self {
       globals,
       cache: Default::default(),
-      process_manager: process::ProcessManager::new(module.clone()).into(),
+      process_manager: process::ProcessManager::new(module.clone(), &options).into(),
+      options,
     }
     .into();
     module.write().set_vm(vm.clone());
@@ -40,9 +93,19 @@ impl VM {
   pub fn as_value(&self) -> Value {
     self.process_manager.read().as_value()
   }
+  /// Las variables que comparten todos los modulos, como `nulo` o `cierto`
+  pub fn get_globals(&self) -> MultiRefHash<VarsManager> {
+    self.globals.clone()
+  }
   pub fn get_process_manager(&self) -> MultiRefHash<process::ProcessManager> {
     self.process_manager.clone()
   }
+  /// Las variables globales y los modulos y prototipos guardados siempre estan vivos
+  fn trace(&self, tracer: &mut collector::Tracer) {
+    tracer.scope(&self.globals);
+    tracer.values(self.cache.libs.values());
+    tracer.values(self.cache.proto.values());
+  }
   pub fn run(&self) -> InterpretResult {
     loop {
       let data = self.process_manager.read().run_instruction();
@@ -56,21 +119,32 @@ impl VM {
       return data;
     }
   }
-  pub fn interpret(&self) -> InterpretResult {
-    let result = self.run();
+  /// El mensaje de un error de la ejecucion, con las llamadas donde ocurrio
+  pub fn error_message(&self, result: &InterpretResult) -> Option<String> {
     let thread = self.process_manager.read().get_root_thread();
-    match &result {
+    match result {
       InterpretResult::RuntimeError(e) => {
         let calls = thread.read().get_calls().clone();
-        thread.write().runtime_error(&format!(
+        Some(format!(
           "Error en tiempo de ejecucion\n\t{}\n\t{}\n",
           e,
-          call_stack_to_string(&calls)
+          error_call_stack(e, &calls, CALL_STACK_SUMMARY)
         ))
       }
-      InterpretResult::CompileError(e) => thread
-        .write()
-        .runtime_error(&format!("Error en compilacion\n\t{}", e,)),
+      InterpretResult::CompileError(e) => Some(format!("Error en compilacion\n\t{}", e,)),
+      InterpretResult::LimitExceeded(e) => Some(format!("Ejecucion detenida\n\t{e}")),
+      _ => None,
+    }
+  }
+  pub fn interpret(&self) -> InterpretResult {
+    let result = self.run();
+    let thread = self.process_manager.read().get_root_thread();
+    match (&result, self.error_message(&result)) {
+      (InterpretResult::LimitExceeded(_), Some(message)) => {
+        eprintln!("{message}");
+        thread.write().clear_stack();
+      }
+      (_, Some(message)) => thread.write().runtime_error(&message),
       _ => {}
     };
     let stack = thread.read().get_stack().clone();
@@ -79,8 +153,22 @@ impl VM {
         .write()
         .runtime_error(&format!("Error de pila no vacia | {stack:?}"));
     }
+    self.report_profile();
     result
   }
+  /// Muestra el perfil de la ejecucion y escribe las pilas de llamadas si se pidieron
+  fn report_profile(&self) {
+    let process_manager = self.process_manager.read();
+    let Some(profiler) = process_manager.get_budget().get_profiler() else {
+      return;
+    };
+    eprintln!("{}", profiler.report());
+    if let Some(path) = &self.options.profile_stacks {
+      if let Err(e) = std::fs::write(path, profiler.folded_stacks()) {
+        eprintln!("No se pudo escribir '{}': {e}", path.display());
+      }
+    }
+  }
   pub fn resolve(
     this: MultiRefHash<Self>,
     path: &Path,
@@ -1,11 +1,56 @@
-use std::{collections::VecDeque, fmt::Debug, sync::RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::Duration;
+use std::{collections::VecDeque, fmt::Debug};
 
 use crate::{
-  interpreter::{stack::InterpretResult, Thread},
+  compiler::{Function, Promise, PromiseData, Value},
+  interpreter::{stack::CallFrame, stack::InterpretResult, Thread, VarsManager},
   MultiRefHash,
 };
 
+use super::budget::Budget;
+use super::collector::{Collector, Tracer};
+use super::reactions::{Reaction, ReactionKind, Reactions};
 use super::thread::{AsyncThread, ModuleThread};
+use super::timers::{TimerAction, Timers};
+use super::VMOptions;
+
+/// Avisa al planificador que puede continuar. Las promesas se resuelven y los hilos llegan
+/// desde otros hilos del sistema (como el servidor TCP), por eso no puede ser parte de la VM
+struct Waker {
+  generation: Mutex<usize>,
+  condvar: Condvar,
+}
+static WAKER: Waker = Waker {
+  generation: Mutex::new(0),
+  condvar: Condvar::new(),
+};
+pub fn wake_scheduler() {
+  let mut generation = WAKER.generation.lock().unwrap();
+  *generation = generation.wrapping_add(1);
+  WAKER.condvar.notify_all();
+}
+fn current_generation() -> usize {
+  *WAKER.generation.lock().unwrap()
+}
+/// Duerme hasta que llegue un aviso posterior a `generation`
+fn park(generation: usize, timeout: Option<Duration>) {
+  let guard = WAKER.generation.lock().unwrap();
+  let is_same = |current: &mut usize| *current == generation;
+  match timeout {
+    Some(timeout) => drop(WAKER.condvar.wait_timeout_while(guard, timeout, is_same)),
+    None => drop(WAKER.condvar.wait_while(guard, is_same)),
+  }
+}
+
+/// Promesa rechazada dentro de un hilo, con la pila donde ocurrio el error
+#[derive(Debug)]
+struct Rejection {
+  promise: Promise,
+  error: String,
+  stack: String,
+}
 
 #[derive(Debug)]
 pub struct ProcessManager {
@@ -13,31 +58,99 @@ pub struct ProcessManager {
   sub_threads: RwLock<VecDeque<MultiRefHash<AsyncThread>>>,
   waiting_threads: RwLock<VecDeque<MultiRefHash<AsyncThread>>>,
   interrupt_threads: RwLock<VecDeque<MultiRefHash<AsyncThread>>>,
+  budget: Budget,
+  time_slice: usize,
+  timers: Timers,
+  reactions: Reactions,
+  rejections: Mutex<Vec<Rejection>>,
+  fatal_rejections: bool,
+  /// El modulo principal termino pero quedan temporizadores o hilos por ejecutar
+  main_done: AtomicBool,
+  collector: Collector,
 }
 
 impl ProcessManager {
-  pub fn new(main: MultiRefHash<ModuleThread>) -> Self {
+  pub fn new(main: MultiRefHash<ModuleThread>, options: &VMOptions) -> Self {
     Self {
       main,
       sub_threads: Default::default(),
       waiting_threads: Default::default(),
       interrupt_threads: Default::default(),
+      budget: Budget::new(options),
+      time_slice: options.time_slice.max(1),
+      timers: Default::default(),
+      reactions: Default::default(),
+      rejections: Default::default(),
+      fatal_rejections: options.fatal_rejections,
+      main_done: AtomicBool::new(false),
+      collector: Collector::new(options.gc_threshold),
     }
   }
+  pub fn get_budget(&self) -> &Budget {
+    &self.budget
+  }
+  pub fn get_timers(&self) -> &Timers {
+    &self.timers
+  }
+  pub fn get_reactions(&self) -> &Reactions {
+    &self.reactions
+  }
+  pub fn get_collector(&self) -> &Collector {
+    &self.collector
+  }
   pub fn as_value(&self) -> crate::compiler::Value {
     self.main.read().clone().into_value()
   }
+  pub fn get_main(&self) -> MultiRefHash<ModuleThread> {
+    self.main.clone()
+  }
   pub fn get_root_thread(&self) -> MultiRefHash<Thread> {
     self.main.read().get_async().read().get_thread()
   }
+  /// Los hilos que se ejecutan aparte del modulo principal, como las funciones asincronas
+  pub fn get_threads(&self) -> Vec<MultiRefHash<AsyncThread>> {
+    [
+      &self.interrupt_threads,
+      &self.sub_threads,
+      &self.waiting_threads,
+    ]
+    .iter()
+    .flat_map(|threads| threads.read().unwrap().iter().cloned().collect::<Vec<_>>())
+    .collect()
+  }
+  /// Un ciclo del planificador: cada hilo listo ejecuta un turno de hasta `time_slice`
+  /// instrucciones o hasta que se bloquee
   pub fn run_instruction(&self) -> InterpretResult {
-    // Ejecuta una instruccion de cada hilo de interrupcion, por ser prioritarios
+    if let Some(result) = self.budget.check() {
+      return result;
+    }
+    // Se lee antes de revisar los hilos para no perder un aviso que llegue mientras tanto
+    let generation = current_generation();
+    // Al inicio del ciclo ningun hilo esta a medio ejecutar, todo lo vivo esta en las raices
+    if self.collector.should_collect() {
+      self.collect_cycles();
+    }
+    // Los hilos de interrupcion tienen su turno primero, por ser prioritarios
     self.run_interrupt_threads();
+    self.fire_timers();
+    self.fire_reactions();
     self.poll_waiting_threads();
+    if self.is_idle() {
+      // Las reacciones que quedaban del ultimo turno del hilo principal ya se cumplieron
+      if self.main_done.load(Ordering::Relaxed) && !self.has_pending_work() {
+        return self.report_rejections();
+      }
+      let timeout = match (self.budget.remaining_time(), self.timers.next_delay()) {
+        (Some(budget), Some(timer)) => Some(budget.min(timer)),
+        (budget, timer) => budget.or(timer),
+      };
+      park(generation, timeout);
+      return InterpretResult::Continue;
+    }
 
     let first_item = self.sub_threads.write().unwrap().pop_front();
-    if let Some(thread) = first_item {
-      let response = thread.read().simple_run_instruction(true);
+    if let Some(thread) = first_item.filter(|thread| !thread.read().is_canceled()) {
+      let response = thread.read().run_slice(self.time_slice, &self.budget, true);
       if thread.read().is_waiting() {
         self
           .waiting_threads
@@ -48,12 +161,227 @@ impl ProcessManager {
         self.sub_threads.write().unwrap().push_back(thread);
       }
     }
-    // El hilo debe ejecutarse una vez por cada ciclo para que no se bloquee
-    self.main.read().run_instruction()
+    if !self.main_done.load(Ordering::Relaxed) {
+      // El hilo debe ejecutarse una vez por cada ciclo para que no se bloquee
+      let result = self.main.read().run_slice(self.time_slice, &self.budget);
+      if !matches!(result, InterpretResult::Ok) {
+        return result;
+      }
+      self.main_done.store(true, Ordering::Relaxed);
+    }
+    if self.has_pending_work() {
+      InterpretResult::Continue
+    } else {
+      self.report_rejections()
+    }
+  }
+  /// Libera las clausuras que no se alcanzan desde los hilos, la maquina virtual, los
+  /// temporizadores ni las reacciones
+  fn collect_cycles(&self) {
+    let mut tracer = Tracer::default();
+    let main = self.main.read();
+    main.get_vm().read().trace(&mut tracer);
+    main.trace(&mut tracer);
+    drop(main);
+    for threads in [
+      &self.sub_threads,
+      &self.waiting_threads,
+      &self.interrupt_threads,
+    ] {
+      for thread in threads.read().unwrap().iter() {
+        thread.read().trace(&mut tracer);
+      }
+    }
+    self.timers.trace(&mut tracer);
+    self.reactions.trace(&mut tracer);
+    for rejection in self.rejections.lock().unwrap().iter() {
+      tracer.promise(&rejection.promise);
+    }
+    self.collector.collect(tracer);
+  }
+  /// Guarda el error de una promesa, si al terminar nadie la espero se reporta
+  pub fn track_rejection(&self, promise: Promise, error: String, stack: String) {
+    let mut rejections = self.rejections.lock().unwrap();
+    rejections.retain(|rejection| !rejection.promise.is_observed());
+    rejections.push(Rejection {
+      promise,
+      error,
+      stack,
+    });
+  }
+  /// La pila del error de una promesa, para cuando el error pasa a otra promesa
+  fn rejection_stack(&self, promise: &Promise) -> String {
+    self
+      .rejections
+      .lock()
+      .unwrap()
+      .iter()
+      .find(|rejection| &rejection.promise == promise)
+      .map(|rejection| rejection.stack.clone())
+      .unwrap_or_default()
+  }
+  fn report_rejections(&self) -> InterpretResult {
+    let rejections = std::mem::take(&mut *self.rejections.lock().unwrap());
+    for Rejection {
+      promise,
+      error,
+      stack,
+    } in rejections
+    {
+      if promise.is_observed() {
+        continue;
+      }
+      if self.fatal_rejections {
+        return InterpretResult::RuntimeError(format!(
+          "Promesa rechazada sin manejar: {error}{stack}"
+        ));
+      }
+      eprintln!("Advertencia: promesa rechazada sin manejar\n\t{error}{stack}");
+    }
+    InterpretResult::Ok
+  }
+  /// Los hilos que esperan sin un temporizador pendiente no mantienen vivo el programa,
+  /// podrian esperar para siempre. Las reacciones de promesas que ya terminaron si, aunque el
+  /// hilo principal las haya cumplido en su ultimo turno
+  fn has_pending_work(&self) -> bool {
+    !self.timers.is_empty()
+      || !self.sub_threads.read().unwrap().is_empty()
+      || !self.interrupt_threads.read().unwrap().is_empty()
+      || self.reactions.has_settled()
+  }
+  /// Hilo que ejecuta la funcion, la promesa se cumple con lo que retorne
+  fn call_thread(
+    callback: MultiRefHash<Function>,
+    args: Vec<Value>,
+    module: MultiRefHash<ModuleThread>,
+  ) -> (MultiRefHash<AsyncThread>, Promise) {
+    let scope = callback.read().get_scope().unwrap_or_default();
+    let locals = VarsManager::crate_child(scope);
+    let frame = CallFrame::new(callback, vec![locals.into()]);
+    let (thread, promise) = AsyncThread::from_frame(frame);
+    thread.write().set_module(module);
+    // El primer argumento debe quedar arriba de la pila
+    for arg in args.into_iter().rev() {
+      thread.read().push(arg);
+    }
+    (thread, promise)
+  }
+  /// Ejecuta la funcion en un hilo nuevo, la promesa se cumple con lo que retorne. Los errores
+  /// solo rechazan la promesa, si nadie la maneja se reportan como las demas
+  fn spawn_call(
+    &self,
+    callback: MultiRefHash<Function>,
+    args: Vec<Value>,
+    module: MultiRefHash<ModuleThread>,
+  ) -> Promise {
+    let (thread, promise) = Self::call_thread(callback, args, module);
+    self.sub_threads.write().unwrap().push_back(thread);
+    promise
+  }
+  /// Llama a la funcion desde el programa que incluye al interprete. Los errores no se muestran,
+  /// quedan en la promesa para quien llamo
+  pub fn call(&self, callback: MultiRefHash<Function>, args: Vec<Value>) -> Promise {
+    let (thread, promise) = Self::call_thread(callback, args, self.main.clone());
+    promise.observe();
+    self.push_sub_thread(thread);
+    promise
+  }
+  fn fire_timers(&self) {
+    for action in self.timers.take_due() {
+      match action {
+        TimerAction::Resolve(promise) => promise.set_value(Value::Null),
+        TimerAction::Call(callback, module) => {
+          // Nadie espera a un temporizador, sus errores se muestran al ocurrir
+          let (thread, _) = Self::call_thread(callback, vec![], module);
+          thread.write().print_on_error();
+          self.sub_threads.write().unwrap().push_back(thread);
+        }
+      }
+    }
+  }
+  /// Cumple `result` con el resultado, si el valor es otra promesa espera a que esa se cumpla
+  fn settle(
+    &self,
+    result: Promise,
+    data: PromiseData,
+    module: MultiRefHash<ModuleThread>,
+    source: &Promise,
+  ) {
+    match data {
+      PromiseData::Ok(value) => {
+        let value = value.read().clone();
+        if let Value::Promise(inner) = value {
+          self.reactions.add(Reaction {
+            source: inner,
+            kind: ReactionKind::Adopt,
+            result,
+            module,
+          });
+        } else {
+          result.set_value(value);
+        }
+      }
+      PromiseData::Err(error) => {
+        result.set_err(error.clone());
+        self.track_rejection(result, error, self.rejection_stack(source));
+      }
+      PromiseData::Pending => {}
+    }
+  }
+  fn fire_reactions(&self) {
+    for (reaction, data) in self.reactions.take_settled() {
+      let Reaction {
+        source,
+        kind,
+        result,
+        module,
+      } = reaction;
+      let (callback, args, kind) = match (kind, data) {
+        (ReactionKind::Then(callback), PromiseData::Ok(value)) => {
+          (callback, vec![value.read().clone()], ReactionKind::Adopt)
+        }
+        (ReactionKind::Catch(callback), PromiseData::Err(error)) => {
+          (callback, vec![Value::String(error)], ReactionKind::Adopt)
+        }
+        (ReactionKind::Finally(callback), data) => (callback, vec![], ReactionKind::Replace(data)),
+        (ReactionKind::Replace(original), PromiseData::Ok(_)) => {
+          self.settle(result, original, module, &source);
+          continue;
+        }
+        (_, data) => {
+          self.settle(result, data, module, &source);
+          continue;
+        }
+      };
+      self.reactions.add(Reaction {
+        source: self.spawn_call(callback, args, module.clone()),
+        kind,
+        result,
+        module,
+      });
+    }
+    for (result, data) in self.reactions.take_settled_groups() {
+      match data {
+        PromiseData::Ok(value) => result.set_value(value.read().clone()),
+        PromiseData::Err(error) => {
+          result.set_err(error.clone());
+          self.track_rejection(result, error, String::new());
+        }
+        PromiseData::Pending => {}
+      }
+    }
   }
 
+  /// Todos los hilos esperan una promesa, ejecutar instrucciones solo gastaria el procesador
+  fn is_idle(&self) -> bool {
+    self.interrupt_threads.read().unwrap().is_empty()
+      && self.sub_threads.read().unwrap().is_empty()
+      && (self.main_done.load(Ordering::Relaxed)
+        || self.main.read().get_async().read().is_blocked())
+  }
   pub fn push_sub_thread(&self, thread: MultiRefHash<AsyncThread>) {
     self.sub_threads.write().unwrap().push_back(thread);
+    wake_scheduler();
   }
   pub fn push_interrupt_thread(&self, thread: MultiRefHash<AsyncThread>) {
     self
@@ -61,6 +389,7 @@ impl ProcessManager {
       .write()
       .unwrap()
       .push_back(thread.clone());
+    wake_scheduler();
   }
   fn poll_waiting_threads(&self) {
     let mut waiting = self.waiting_threads.write().unwrap();
@@ -69,6 +398,9 @@ impl ProcessManager {
     let mut still_waiting = VecDeque::new();
 
     for thread in waiting.drain(..) {
+      if thread.read().is_canceled() {
+        continue;
+      }
       if thread.read().is_waiting() {
         still_waiting.push_back(thread);
       } else {
@@ -84,7 +416,12 @@ impl ProcessManager {
     let mut remaining = VecDeque::new();
 
     while let Some(thread) = interrupts.pop_front() {
-      let response = thread.read().simple_run_instruction(false);
+      if thread.read().is_canceled() {
+        continue;
+      }
+      let response = thread
+        .read()
+        .run_slice(self.time_slice, &self.budget, false);
       if matches!(response, InterpretResult::Continue) {
         remaining.push_back(thread);
       }
@@ -0,0 +1,230 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::Thread;
+use crate::compiler::{ChunkGroup, OpCode};
+use crate::interpreter::stack::CallFrame;
+use crate::MultiRefHash;
+
+/// Filas que se muestran de cada tabla del reporte
+const REPORT_LIMIT: usize = 20;
+
+/// Lo que costo ejecutar una funcion o una linea, el tiempo es solo el de sus instrucciones
+#[derive(Default, Clone, Copy)]
+struct Cost {
+  instructions: usize,
+  time: Duration,
+}
+impl Cost {
+  fn add(&mut self, time: Duration) {
+    self.instructions += 1;
+    self.time += time;
+  }
+}
+
+struct FunctionProfile {
+  /// `en nombre <archivo:linea:columna>` de donde se declaro
+  location: String,
+  path: Option<PathBuf>,
+  cost: Cost,
+  /// Mantiene vivo el codigo para que su direccion no se reutilice
+  _chunk: MultiRefHash<ChunkGroup>,
+}
+
+/// Las funciones se identifican por su codigo, asi todas las clausuras de una misma funcion
+/// cuentan juntas
+#[derive(Default)]
+struct Profile {
+  functions: HashMap<usize, FunctionProfile>,
+  lines: HashMap<(usize, usize), Cost>,
+  opcodes: HashMap<u8, usize>,
+  stacks: HashMap<Vec<usize>, usize>,
+}
+impl Profile {
+  fn function(&mut self, frame: &CallFrame) -> usize {
+    let chunk = frame.current_chunk();
+    let id = chunk.addr();
+    self.functions.entry(id).or_insert_with(|| FunctionProfile {
+      location: frame.function_location(),
+      path: frame.source_path(),
+      cost: Cost::default(),
+      _chunk: chunk,
+    });
+    id
+  }
+}
+
+/// La instruccion que un hilo esta por ejecutar
+pub struct Sample {
+  stack: Vec<usize>,
+  line: Option<usize>,
+  opcode: u8,
+  started: Instant,
+}
+
+/// Cuenta las instrucciones y el tiempo de cada funcion, linea y operacion
+#[derive(Debug)]
+pub struct Profiler {
+  started: Instant,
+  profile: Mutex<Profile>,
+}
+impl std::fmt::Debug for Profile {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "<perfil de {} funciones>", self.functions.len())
+  }
+}
+impl Profiler {
+  pub fn new() -> Self {
+    Self {
+      started: Instant::now(),
+      profile: Default::default(),
+    }
+  }
+  pub fn sample(&self, thread: &Thread) -> Option<Sample> {
+    let calls = thread.get_calls();
+    let frame = calls.last()?;
+    let mut profile = self.profile.lock().unwrap();
+    let stack = calls.iter().map(|frame| profile.function(frame)).collect();
+    Some(Sample {
+      stack,
+      line: (!frame.is_native()).then(|| frame.next_line()),
+      opcode: frame.peek(),
+      started: Instant::now(),
+    })
+  }
+  /// Suma el tiempo desde que se tomo la muestra a la funcion y linea donde ocurrio
+  pub fn record(&self, sample: Sample) {
+    let time = sample.started.elapsed();
+    let mut profile = self.profile.lock().unwrap();
+    let Some(&function) = sample.stack.last() else {
+      return;
+    };
+    if let Some(entry) = profile.functions.get_mut(&function) {
+      entry.cost.add(time);
+    }
+    if let Some(line) = sample.line {
+      profile.lines.entry((function, line)).or_default().add(time);
+    }
+    *profile.opcodes.entry(sample.opcode).or_default() += 1;
+    *profile.stacks.entry(sample.stack).or_default() += 1;
+  }
+  pub fn report(&self) -> String {
+    let profile = self.profile.lock().unwrap();
+    let total: usize = profile.opcodes.values().sum();
+    let total_time: Duration = profile.functions.values().map(|f| f.cost.time).sum();
+    let percent = |time: Duration| {
+      if total_time.is_zero() {
+        0.0
+      } else {
+        time.as_secs_f64() * 100.0 / total_time.as_secs_f64()
+      }
+    };
+    let mut report = format!(
+      "Perfil: {total} instrucciones, {} en instrucciones de {} en total\n",
+      millis(total_time),
+      millis(self.started.elapsed())
+    );
+
+    let mut functions: Vec<&FunctionProfile> = profile.functions.values().collect();
+    functions.sort_by_key(|function| Reverse(function.cost.time));
+    report.push_str("\nFunciones por tiempo propio:\n");
+    for function in functions.iter().take(REPORT_LIMIT) {
+      report.push_str(&format!(
+        "{:>12} {:>6.2}% {:>12}  {}\n",
+        millis(function.cost.time),
+        percent(function.cost.time),
+        function.cost.instructions,
+        function.location
+      ));
+    }
+
+    // Varias funciones pueden estar en la misma linea
+    let mut lines: HashMap<(&Path, usize), Cost> = HashMap::new();
+    for ((function, line), cost) in &profile.lines {
+      let Some(path) = profile.functions[function].path.as_deref() else {
+        continue;
+      };
+      let entry = lines.entry((path, *line)).or_default();
+      entry.instructions += cost.instructions;
+      entry.time += cost.time;
+    }
+    let mut lines: Vec<((&Path, usize), Cost)> = lines.into_iter().collect();
+    lines.sort_by_key(|(_, cost)| Reverse(cost.time));
+    let mut sources: HashMap<&Path, Vec<String>> = HashMap::new();
+    report.push_str("\nLineas por tiempo:\n");
+    for ((path, line), cost) in lines.into_iter().take(REPORT_LIMIT) {
+      let code = sources
+        .entry(path)
+        .or_insert_with(|| {
+          std::fs::read_to_string(path)
+            .map(|code| code.lines().map(str::to_string).collect())
+            .unwrap_or_default()
+        })
+        .get(line)
+        .map(|code| code.trim().to_string())
+        .unwrap_or_default();
+      report.push_str(&format!(
+        "{:>12} {:>6.2}% {:>12}  {}:{} | {code}\n",
+        millis(cost.time),
+        percent(cost.time),
+        cost.instructions,
+        path.display(),
+        line + 1
+      ));
+    }
+
+    let mut opcodes: Vec<(&u8, &usize)> = profile.opcodes.iter().collect();
+    opcodes.sort_by_key(|(_, count)| Reverse(**count));
+    report.push_str("\nOperaciones:\n");
+    for (opcode, count) in opcodes.into_iter().take(REPORT_LIMIT) {
+      let name = format!("{:?}", OpCode::from(*opcode));
+      report.push_str(&format!(
+        "{count:>12} {:>6.2}%  {name}\n",
+        *count as f64 * 100.0 / total.max(1) as f64
+      ));
+    }
+    report
+  }
+  /// Una linea por cada pila de llamadas con las instrucciones que ejecuto, el formato que
+  /// leen las herramientas de graficos de llama como `flamegraph.pl` o `inferno`
+  pub fn folded_stacks(&self) -> String {
+    let profile = self.profile.lock().unwrap();
+    let mut lines: Vec<String> = profile
+      .stacks
+      .iter()
+      .map(|(stack, count)| {
+        let frames: Vec<String> = stack
+          .iter()
+          .map(|id| without_colors(&profile.functions[id].location).replace(';', ","))
+          .collect();
+        format!("{} {count}", frames.join(";"))
+      })
+      .collect();
+    lines.sort();
+    lines.join("\n") + "\n"
+  }
+}
+
+fn millis(time: Duration) -> String {
+  format!("{:.3} ms", time.as_secs_f64() * 1000.0)
+}
+fn without_colors(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(char) = chars.next() {
+    if char == '\x1b' {
+      // Las secuencias de color terminan en `m`
+      for char in chars.by_ref() {
+        if char == 'm' {
+          break;
+        }
+      }
+    } else {
+      plain.push(char);
+    }
+  }
+  plain
+}
@@ -0,0 +1,217 @@
+use std::sync::Mutex;
+
+use crate::compiler::{Function, Instance, Object, Promise, PromiseData, Value};
+use crate::MultiRefHash;
+
+use super::collector::Tracer;
+use super::thread::ModuleThread;
+
+const FULFILLED: &str = "cumplida";
+const REJECTED: &str = "rechazada";
+const STATUS: &str = "estado";
+const VALUE: &str = "valor";
+const REASON: &str = "razon";
+
+/// Lo que se hace cuando se cumple la promesa de una reaccion
+#[derive(Clone, Debug)]
+pub enum ReactionKind {
+  /// `entonces`: llama a la funcion con el valor, los errores pasan sin cambios
+  Then(MultiRefHash<Function>),
+  /// `captura`: llama a la funcion con el error, los valores pasan sin cambios
+  Catch(MultiRefHash<Function>),
+  /// `finalmente`: llama a la funcion sin argumentos y luego pasa el resultado original
+  Finally(MultiRefHash<Function>),
+  /// Copia el resultado, si es otra promesa espera tambien a esa
+  Adopt,
+  /// Cuando termina `finalmente` se usa el resultado original en lugar del suyo
+  Replace(PromiseData),
+}
+
+#[derive(Debug)]
+pub struct Reaction {
+  pub source: Promise,
+  pub kind: ReactionKind,
+  /// La promesa que se retorno al programa
+  pub result: Promise,
+  pub module: MultiRefHash<ModuleThread>,
+}
+
+/// Combinadores de `Promesa`
+#[derive(Clone, Copy, Debug)]
+pub enum Combinator {
+  /// Todos los valores, o el primer error
+  All,
+  /// El primer resultado, sea valor o error
+  Race,
+  /// El primer valor, o un error si todas fallan
+  Any,
+  /// El estado de cada promesa cuando todas terminan
+  AllSettled,
+}
+
+#[derive(Debug)]
+struct Group {
+  promises: Vec<Promise>,
+  combinator: Combinator,
+  result: Promise,
+}
+impl Group {
+  fn outcome(&self) -> Option<PromiseData> {
+    let data: Vec<PromiseData> = self.promises.iter().map(Promise::get_data).collect();
+    let is_pending = |data: &PromiseData| matches!(data, PromiseData::Pending);
+    match self.combinator {
+      Combinator::All => {
+        if let Some(PromiseData::Err(error)) =
+          data.iter().find(|data| matches!(data, PromiseData::Err(_)))
+        {
+          return Some(PromiseData::Err(error.clone()));
+        }
+        if data.iter().any(is_pending) {
+          return None;
+        }
+        let values = data
+          .into_iter()
+          .map(|data| match data {
+            PromiseData::Ok(value) => value.read().clone(),
+            _ => Value::Never,
+          })
+          .collect::<Vec<_>>();
+        Some(list(values))
+      }
+      Combinator::Race => data.into_iter().find(|data| !is_pending(data)),
+      Combinator::Any => {
+        if let Some(value) = data.iter().find(|data| matches!(data, PromiseData::Ok(_))) {
+          return Some(value.clone());
+        }
+        if data.iter().any(is_pending) {
+          return None;
+        }
+        let errors = data
+          .into_iter()
+          .filter_map(|data| match data {
+            PromiseData::Err(error) => Some(error),
+            _ => None,
+          })
+          .collect::<Vec<_>>();
+        Some(PromiseData::Err(format!(
+          "Todas las promesas fallaron: [{}]",
+          errors.join(", ")
+        )))
+      }
+      Combinator::AllSettled => {
+        if data.iter().any(is_pending) {
+          return None;
+        }
+        let values = data.into_iter().map(settled_value).collect::<Vec<_>>();
+        Some(list(values))
+      }
+    }
+  }
+}
+
+fn list(values: Vec<Value>) -> PromiseData {
+  PromiseData::Ok(MultiRefHash::new(Value::Object(values.into())))
+}
+/// Objeto con el `estado` de la promesa y su `valor` o `razon`
+fn settled_value(data: PromiseData) -> Value {
+  let instance = Instance::new("<Promesa>::Resultado".to_string());
+  let (status, key, value) = match data {
+    PromiseData::Ok(value) => (FULFILLED, VALUE, value.read().clone()),
+    PromiseData::Err(error) => (REJECTED, REASON, Value::String(error)),
+    PromiseData::Pending => unreachable!(),
+  };
+  instance.set_instance_property(STATUS, Value::String(status.to_string()), true);
+  instance.set_instance_property(key, value, true);
+  Value::Object(Object::Map(Default::default(), instance.into()))
+}
+
+/// Reacciones y combinadores que esperan a que se cumplan sus promesas
+#[derive(Debug, Default)]
+pub struct Reactions {
+  reactions: Mutex<Vec<Reaction>>,
+  groups: Mutex<Vec<Group>>,
+}
+impl Reactions {
+  pub fn add(&self, reaction: Reaction) {
+    reaction.source.observe();
+    self.reactions.lock().unwrap().push(reaction);
+  }
+  pub fn add_group(&self, promises: Vec<Promise>, combinator: Combinator, result: Promise) {
+    promises.iter().for_each(Promise::observe);
+    self.groups.lock().unwrap().push(Group {
+      promises,
+      combinator,
+      result,
+    });
+  }
+  pub fn trace(&self, tracer: &mut Tracer) {
+    for reaction in self.reactions.lock().unwrap().iter() {
+      tracer.promise(&reaction.source);
+      tracer.promise(&reaction.result);
+      match &reaction.kind {
+        ReactionKind::Then(callback)
+        | ReactionKind::Catch(callback)
+        | ReactionKind::Finally(callback) => tracer.function(callback),
+        ReactionKind::Replace(PromiseData::Ok(value)) => tracer.cell(value),
+        ReactionKind::Replace(_) | ReactionKind::Adopt => {}
+      }
+    }
+    for group in self.groups.lock().unwrap().iter() {
+      group
+        .promises
+        .iter()
+        .for_each(|promise| tracer.promise(promise));
+      tracer.promise(&group.result);
+    }
+  }
+  /// Saca las reacciones cuya promesa ya termino, junto con su resultado
+  pub fn take_settled(&self) -> Vec<(Reaction, PromiseData)> {
+    let mut settled = vec![];
+    let mut reactions = self.reactions.lock().unwrap();
+    let mut index = 0;
+    while index < reactions.len() {
+      // Cancelar la promesa que se retorno al programa cancela la reaccion
+      if reactions[index].result.is_canceled() {
+        reactions.remove(index);
+        continue;
+      }
+      let data = reactions[index].source.get_data();
+      if matches!(data, PromiseData::Pending) {
+        index += 1;
+      } else {
+        settled.push((reactions.remove(index), data));
+      }
+    }
+    settled
+  }
+  /// Hay reacciones o combinadores que se pueden cumplir en el siguiente ciclo
+  pub fn has_settled(&self) -> bool {
+    let reactions = self.reactions.lock().unwrap();
+    reactions
+      .iter()
+      .any(|reaction| !matches!(reaction.source.get_data(), PromiseData::Pending))
+      || self
+        .groups
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|group| group.outcome().is_some())
+  }
+  /// Saca los combinadores que ya tienen resultado
+  pub fn take_settled_groups(&self) -> Vec<(Promise, PromiseData)> {
+    let mut settled = vec![];
+    self.groups.lock().unwrap().retain(|group| {
+      if group.result.is_canceled() {
+        return false;
+      }
+      match group.outcome() {
+        Some(data) => {
+          settled.push((group.result.clone(), data));
+          false
+        }
+        None => true,
+      }
+    });
+    settled
+  }
+}
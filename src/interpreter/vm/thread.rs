@@ -1,11 +1,20 @@
 use std::path::{Path, PathBuf};
 
-use super::VM;
-use crate::compiler::{Function, LazyValue, Number, Object, OpCode, Promise, PromiseData, Value};
+use super::budget::Budget;
+use super::collector::Tracer;
+use super::profiler::Sample;
+use super::{VMOptions, CALL_STACK_SUMMARY, VM};
+use crate::compiler::{
+  ChunkGroup, Function, LazyValue, Number, Object, OpCode, Promise, PromiseData, Value,
+};
 use crate::functions_names::CONSTRUCTOR;
-use crate::interpreter::stack::{CallFrame, InterpretResult};
+use crate::interpreter::libs::NATIVE_PREFIX;
+use crate::interpreter::stack::{
+  call_stack_summary, error_call_stack, source_excerpt, CallFrame, InterpretResult, STACK_OVERFLOW,
+};
 use crate::interpreter::vm::process::ProcessManager;
-use crate::interpreter::VarsManager;
+use crate::interpreter::{Permission, VarsManager};
+use crate::util::Position;
 use crate::{MultiRefHash, OnError};
 
 #[derive(Clone, Debug)]
@@ -41,61 +50,13 @@ impl ModuleThread {
     if !matches!(self.status, InterpretResult::Continue | InterpretResult::Ok) {
       return self.status.clone();
     }
+    // Mientras espera, como a un valor vago, la siguiente instruccion todavia no se ejecuta
+    if self.async_thread.read().has_blocking() {
+      return self.async_thread.read().run_instruction();
+    }
     let code = self.async_thread.read().thread.read().peek();
     match code {
-      OpCode::Import => {
-        let thread = self.async_thread.read().thread.clone();
-        thread.write().read();
-        let module = thread.write().pop();
-        let path = module.to_aga_string(&thread.read());
-        let meta_byte = thread.write().read();
-        let name_byte = thread.write().read();
-        let _is_lazy = (meta_byte & 0b10) == 0b10;
-        let alias = (meta_byte & 0b01) == 0b01;
-
-        let lib_name = if path.starts_with(":") {
-          path
-        } else {
-          Path::new(&self.path)
-            .parent()
-            .unwrap()
-            .join(path)
-            .canonicalize()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string()
-        }
-        .replace("\\\\?\\", "");
-        let value = crate::interpreter::libs::libs(
-          lib_name,
-          self.get_vm().read().cache.libs.clone(),
-          |path| {
-            let binding = Path::new(&self.path)
-              .parent()
-              .unwrap()
-              .join(path)
-              .canonicalize()
-              .unwrap_or_else(|_| PathBuf::from(path));
-            let path = binding.as_path();
-            let module = VM::resolve(self.get_vm(), path, thread.write().globals());
-            *self.async_thread.read().await_thread.write() = BlockingThread::Module(module.clone());
-            let x = module.read().clone().into_value();
-            x
-          },
-        );
-        if alias {
-          let name = thread
-            .read()
-            .current_chunk()
-            .read()
-            .read_constant(name_byte)
-            .to_aga_string(&thread.read());
-          thread.write().declare(&name, value, true);
-        }
-        thread.write().push(module);
-        InterpretResult::Continue
-      }
+      OpCode::Import => self.run_import(&self.async_thread.read(), &self.path),
       OpCode::Export => {
         let thread = self.async_thread.read().thread.clone();
         thread.write().read();
@@ -121,6 +82,131 @@ impl ModuleThread {
       _ => self.async_thread.read().run_instruction(),
     }
   }
+  /// Ejecuta `OpCode::Import` en `async_thread`, el hilo del modulo o el de una importacion
+  /// `vago`. Con `vago` solo se declara un valor vago que importa el modulo al usarse
+  fn run_import(&self, async_thread: &AsyncThread, importer: &Path) -> InterpretResult {
+    let thread = async_thread.thread.clone();
+    thread.write().read();
+    let module = thread.write().pop();
+    let path = module.to_aga_string(&thread.read());
+    let meta_byte = thread.write().read();
+    let name_byte = thread.write().read();
+    let is_lazy = (meta_byte & 0b10) == 0b10;
+    let alias = (meta_byte & 0b01) == 0b01;
+
+    let value = if is_lazy {
+      Value::Lazy(lazy_import(
+        &path,
+        importer,
+        thread.read().current_position(),
+      ))
+    } else {
+      // Los modulos se guardan por su ruta canonica, asi un archivo se ejecuta una sola vez
+      // sin importar desde donde se importe
+      let lib_name = if let Some(library) = path.strip_prefix(NATIVE_PREFIX) {
+        let canonical = match crate::interpreter::modules::resolve_native(library, importer) {
+          Ok(canonical) => canonical,
+          Err(error) => return InterpretResult::RuntimeError(error),
+        };
+        let permission = Permission::Native(&canonical.to_string_lossy());
+        if let Err(error) = thread.read().check_permission(permission) {
+          return InterpretResult::RuntimeError(error);
+        }
+        format!("{NATIVE_PREFIX}{}", canonical.display())
+      } else if path.starts_with(":") {
+        path
+      } else {
+        let canonical = match crate::interpreter::modules::resolve(&path, importer) {
+          Ok(canonical) => canonical,
+          Err(error) => return InterpretResult::RuntimeError(error),
+        };
+        if let Some(cycle) = self.import_cycle(&canonical) {
+          return InterpretResult::RuntimeError(format!("Importacion circular: {cycle}"));
+        }
+        canonical.to_string_lossy().to_string()
+      }
+      .replace("\\\\?\\", "");
+      let value =
+        crate::interpreter::libs::libs(lib_name, self.get_vm().read().cache.libs.clone(), |path| {
+          let module = VM::resolve(self.get_vm(), Path::new(path), thread.write().globals());
+          *async_thread.await_thread.write() = BlockingThread::Module(module.clone());
+          let x = module.read().clone().into_value();
+          x
+        });
+      match value {
+        Ok(value) => value,
+        Err(error) => return InterpretResult::RuntimeError(error),
+      }
+    };
+    if alias {
+      let name = thread
+        .read()
+        .current_chunk()
+        .read()
+        .read_constant(name_byte)
+        .to_aga_string(&thread.read());
+      thread.write().declare(&name, value.clone(), true);
+    }
+    thread.write().push(value);
+    InterpretResult::Continue
+  }
+  /// Si el modulo ya se esta importando, retorna la cadena de importaciones que vuelve a el.
+  /// Importarlo de nuevo lo ejecutaria otra vez antes de que termine
+  fn import_cycle(&self, path: &Path) -> Option<String> {
+    // Cada modulo espera al que importa, desde el principal hasta el que esta importando ahora
+    let mut chain = vec![];
+    let mut current = Some(self.get_process_manager().read().get_main());
+    while let Some(module) = current {
+      let module = module.read();
+      chain.push(
+        module
+          .path
+          .canonicalize()
+          .unwrap_or_else(|_| module.path.clone()),
+      );
+      current = module.async_thread.read().awaited_module();
+    }
+    let start = chain.iter().position(|module| module == path)?;
+    let cycle = chain[start..]
+      .iter()
+      .chain([&path.to_path_buf()])
+      .map(|module| module.display().to_string())
+      .collect::<Vec<_>>();
+    Some(cycle.join(" -> "))
+  }
+  /// Ejecuta un turno de hasta `slice` instrucciones, o hasta que el modulo quede bloqueado
+  pub fn run_slice(&self, slice: usize, budget: &Budget) -> InterpretResult {
+    if !matches!(self.status, InterpretResult::Continue | InterpretResult::Ok) {
+      return self.status.clone();
+    }
+    let mut executed = 0;
+    while executed < slice {
+      let async_thread = self.async_thread.read();
+      if !async_thread.has_blocking() {
+        let (count, result) = async_thread.run_batch(slice - executed, budget, false);
+        executed += count;
+        if !matches!(result, InterpretResult::Continue) {
+          return result;
+        }
+        if executed >= slice || budget.check().is_some() {
+          break;
+        }
+      }
+      drop(async_thread);
+      // Importar, exportar, esperar o un bloque que bloquea al hilo van de a una instruccion
+      budget.count_instruction();
+      executed += 1;
+      let sample = self.async_thread.read().sample(budget);
+      let result = self.run_instruction();
+      if let (Some(profiler), Some(sample)) = (budget.get_profiler(), sample) {
+        profiler.record(sample);
+      }
+      if !matches!(result, InterpretResult::Continue) || self.async_thread.read().is_blocked() {
+        return result;
+      }
+    }
+    InterpretResult::Continue
+  }
   pub fn push_call(&self, frame: CallFrame) {
     self.async_thread.read().push_call(frame)
   }
@@ -141,6 +227,30 @@ impl ModuleThread {
   ) -> MultiRefHash<crate::interpreter::vm::process::ProcessManager> {
     self.get_vm().read().get_process_manager()
   }
+  pub fn trace(&self, tracer: &mut Tracer) {
+    tracer.value(&self.value);
+    self.async_thread.read().trace(tracer);
+  }
+}
+
+/// Valor vago que al usarse importa el modulo y se convierte en el, el codigo es el de una
+/// importacion sin `vago` desde `importer`
+fn lazy_import(path: &str, importer: &Path, position: Position) -> LazyValue {
+  let chunk = ChunkGroup::new_ref();
+  {
+    let mut chunk = chunk.write();
+    chunk.write_constant(Value::String(path.to_string()), position);
+    chunk.write_buffer(vec![OpCode::Import as u8, 0, 0], position);
+    chunk.write(OpCode::Return as u8, position);
+  }
+  Function::Script {
+    chunk,
+    path: importer.to_path_buf(),
+    scope: None.into(),
+    upvalues: vec![],
+    captures: vec![],
+  }
+  .into()
 }
 
 #[derive(Clone, Debug, Default)]
@@ -166,11 +276,41 @@ enum BlockingThread {
   Lazy(LazyValue, MultiRefHash<AsyncThread>),
 }
 impl BlockingThread {
+  fn trace(&self, tracer: &mut Tracer) {
+    match self {
+      Self::Void => {}
+      Self::Module(module) => module.read().trace(tracer),
+      Self::Await(promise) => tracer.promise(promise),
+      Self::TryCatch {
+        try_thread,
+        catch_thread,
+        ..
+      } => {
+        try_thread.read().trace(tracer);
+        catch_thread.read().trace(tracer);
+      }
+      Self::Lazy(lazy, thread) => {
+        tracer.value(&Value::Lazy(lazy.clone()));
+        thread.read().trace(tracer);
+      }
+    }
+  }
   fn run_instruction(&self) -> InterpretResult {
     match self {
       // nada que ejecutar
       Self::Void => InterpretResult::Ok,
-      Self::Module(ref_cell) => ref_cell.read().run_instruction(),
+      Self::Module(module) => match module.read().run_instruction() {
+        // Se agregan las llamadas del modulo, quien lo importo solo conoce la linea de la importacion
+        InterpretResult::RuntimeError(error) => {
+          let thread = module.read().get_async().read().get_thread();
+          let calls = thread.read().get_calls().clone();
+          InterpretResult::RuntimeError(format!(
+            "{error}{}",
+            error_call_stack(&error, &calls, CALL_STACK_SUMMARY)
+          ))
+        }
+        result => result,
+      },
       Self::Await(promise) => {
         let data = promise.get_data();
         match data {
@@ -221,6 +361,10 @@ impl BlockingThread {
         }
       }
       Self::Lazy(lazy, thread) => {
+        // El valor no termina mientras espera algo, como el modulo de una importacion `vago`
+        if thread.read().has_blocking() {
+          return thread.read().run_instruction();
+        }
         let byte = thread.read().get_thread().write().peek();
         match byte {
           OpCode::Return => {
@@ -230,6 +374,14 @@ impl BlockingThread {
             lazy.set(value);
             InterpretResult::Ok
           }
+          OpCode::Import => {
+            let async_thread = thread.read();
+            let importer = lazy.get_once().read().source_path().map(Path::to_path_buf);
+            async_thread
+              .get_module()
+              .read()
+              .run_import(&async_thread, &importer.unwrap_or_default())
+          }
           _ => thread.read().run_instruction(),
         }
       }
@@ -246,6 +398,10 @@ pub struct AsyncThread {
   print_error: bool,
 }
 impl AsyncThread {
+  /// Un hilo cancelado se descarta sin terminar de ejecutarse
+  pub fn is_canceled(&self) -> bool {
+    self.promise.is_canceled()
+  }
   pub fn is_waiting(&self) -> bool {
     let bloking_thread = &*self.await_thread.read();
     match bloking_thread {
@@ -253,6 +409,25 @@ impl AsyncThread {
       _ => false,
     }
   }
+  /// Si el hilo solo puede continuar cuando se resuelva una promesa, incluso dentro de un modulo,
+  /// un intenta/captura o un valor vago
+  pub fn is_blocked(&self) -> bool {
+    match &*self.await_thread.read() {
+      BlockingThread::Void => false,
+      BlockingThread::Await(promise) => matches!(promise.get_data(), PromiseData::Pending),
+      BlockingThread::Module(module) => module.read().get_async().read().is_blocked(),
+      BlockingThread::TryCatch {
+        try_thread,
+        catch_thread,
+        state,
+      } => match &*state.read() {
+        TryCatchState::Trying => try_thread.read().is_blocked(),
+        TryCatchState::Catching => catch_thread.read().is_blocked(),
+        _ => false,
+      },
+      BlockingThread::Lazy(_, thread) => thread.read().is_blocked(),
+    }
+  }
   pub fn set_module(&mut self, module: MultiRefHash<ModuleThread>) {
     self.module = Some(module);
   }
@@ -302,6 +477,7 @@ impl AsyncThread {
         self.thread.write().read();
         let value = self.pop();
         if value.is_promise() {
+          value.as_promise().observe();
           let blocking = BlockingThread::Await(value.as_promise());
           *self.await_thread.write() = blocking;
         }
@@ -310,27 +486,134 @@ impl AsyncThread {
       }
       _ => {
         let result = self.thread.write().run_instruction();
-        match result {
-          InterpretResult::RuntimeError(err) => {
-            self.promise.set_err(err.clone());
-            if self.print_error {
-              eprintln!("{err}\n{:?}", self.get_thread().read().get_calls());
-            }
-            if contain_error {
-              // Este es un error de la promesa, no de el programa
-              InterpretResult::Ok
-            } else {
-              InterpretResult::RuntimeError(err)
-            }
+        self.settle(result, contain_error)
+      }
+    }
+  }
+  /// Resuelve la promesa del hilo cuando este termina o falla
+  fn settle(&self, result: InterpretResult, contain_error: bool) -> InterpretResult {
+    match result {
+      InterpretResult::RuntimeError(err) => {
+        self.promise.set_err(err.clone());
+        if self.print_error {
+          let calls = self.get_thread().read().get_calls().clone();
+          let excerpt = source_excerpt(&calls)
+            .map(|excerpt| format!("\n{excerpt}"))
+            .unwrap_or_default();
+          eprintln!(
+            "{err}{}{excerpt}",
+            error_call_stack(&err, &calls, CALL_STACK_SUMMARY)
+          );
+          self.promise.observe();
+        } else if let (true, Some(module)) = (contain_error, &self.module) {
+          let calls = self.get_thread().read().get_calls().clone();
+          let mut stack = error_call_stack(&err, &calls, CALL_STACK_SUMMARY);
+          if let Some(excerpt) = source_excerpt(&calls) {
+            stack.push_str(&format!("\n{excerpt}"));
           }
-          InterpretResult::Ok => {
-            self.promise.set_value(self.pop());
-            InterpretResult::Ok
+          module.read().get_process_manager().read().track_rejection(
+            self.promise.clone(),
+            err.clone(),
+            stack,
+          );
+        }
+        if contain_error {
+          // Este es un error de la promesa, no de el programa
+          InterpretResult::Ok
+        } else {
+          InterpretResult::RuntimeError(err)
+        }
+      }
+      InterpretResult::Ok => {
+        self.promise.set_value(self.pop());
+        InterpretResult::Ok
+      }
+      result => result,
+    }
+  }
+  /// Muestra para el perfil de la siguiente instruccion, que ejecuta el ultimo de los hilos activos
+  fn sample(&self, budget: &Budget) -> Option<Sample> {
+    let profiler = budget.get_profiler()?;
+    let thread = self.active_threads().pop()?;
+    let thread = thread.read();
+    profiler.sample(&thread)
+  }
+  fn has_blocking(&self) -> bool {
+    !matches!(*self.await_thread.read(), BlockingThread::Void)
+  }
+  /// Ejecuta hasta `slice` instrucciones sin soltar el hilo. Se detiene antes de las
+  /// instrucciones que necesita manejar el hilo asincrono o el modulo, y cuando una instruccion
+  /// deja al hilo esperando algo. Retorna cuantas instrucciones ejecuto
+  fn run_batch(
+    &self,
+    slice: usize,
+    budget: &Budget,
+    contain_error: bool,
+  ) -> (usize, InterpretResult) {
+    let was_blocking = self.has_blocking();
+    let mut thread = self.thread.write();
+    for executed in 0..slice {
+      if matches!(
+        thread.peek(),
+        OpCode::Await | OpCode::Import | OpCode::Export
+      ) || budget.check().is_some()
+      {
+        return (executed, InterpretResult::Continue);
+      }
+      budget.count_instruction();
+      let profiler = budget.get_profiler();
+      let sample = profiler.and_then(|profiler| profiler.sample(&thread));
+      let result = thread.run_instruction();
+      if let (Some(profiler), Some(sample)) = (profiler, sample) {
+        profiler.record(sample);
+      }
+      if !matches!(result, InterpretResult::Continue) {
+        drop(thread);
+        return (executed + 1, self.settle(result, contain_error));
+      }
+      if !was_blocking && self.has_blocking() {
+        return (executed + 1, InterpretResult::Continue);
+      }
+    }
+    (slice, InterpretResult::Continue)
+  }
+  /// Como `simple_run_instruction` pero con un turno de hasta `slice` instrucciones
+  pub fn run_slice(&self, slice: usize, budget: &Budget, contain_error: bool) -> InterpretResult {
+    let mut executed = 0;
+    while executed < slice {
+      if !self.has_blocking() {
+        let (count, result) = self.run_batch(slice - executed, budget, contain_error);
+        executed += count;
+        if !matches!(result, InterpretResult::Continue) {
+          return result;
+        }
+        if executed >= slice || budget.check().is_some() || self.is_waiting() {
+          break;
+        }
+      }
+      // La siguiente instruccion no se puede ejecutar por lotes, como un `Await`, o el hilo
+      // espera a un intenta/captura o a un valor vago
+      budget.count_instruction();
+      executed += 1;
+      let sample = self.sample(budget);
+      let result = if self.has_blocking() {
+        match self.run_instruction() {
+          error @ (InterpretResult::RuntimeError(_) | InterpretResult::CompileError(_)) => {
+            self.settle(error, contain_error)
           }
           result => result,
         }
+      } else {
+        self.simple_run_instruction(contain_error)
+      };
+      if let (Some(profiler), Some(sample)) = (budget.get_profiler(), sample) {
+        profiler.record(sample);
+      }
+      if !matches!(result, InterpretResult::Continue) || self.is_waiting() {
+        return result;
       }
     }
+    InterpretResult::Continue
   }
   pub fn run_instruction(&self) -> InterpretResult {
     let sub_module = self.await_thread.read().clone();
@@ -342,6 +625,22 @@ impl AsyncThread {
     let data = self.await_thread.read().run_instruction();
     if !matches!(data, InterpretResult::Continue) {
       *self.await_thread.write() = BlockingThread::Void;
+      match (&sub_module, &data) {
+        // Un modulo que fallo detiene tambien a quien lo importo, igual que un valor vago a
+        // quien lo uso
+        (
+          BlockingThread::Module(_) | BlockingThread::Lazy(..),
+          InterpretResult::RuntimeError(_) | InterpretResult::CompileError(_),
+        ) => return data,
+        // En la pila quedo el valor vago, se reemplaza por su valor
+        (BlockingThread::Lazy(lazy, _), InterpretResult::Ok) => {
+          if let Some(value) = lazy.get().clone() {
+            self.pop();
+            self.push(value);
+          }
+        }
+        _ => {}
+      }
       return InterpretResult::Continue;
     }
 
@@ -350,12 +649,48 @@ impl AsyncThread {
   pub fn get_thread(&self) -> MultiRefHash<Thread> {
     self.thread.clone()
   }
+  /// Este hilo y los que espera, hasta el que ejecuta la siguiente instruccion: el de un modulo
+  /// importado, un intenta/captura o un valor vago
+  pub fn active_threads(&self) -> Vec<MultiRefHash<Thread>> {
+    let mut threads = vec![self.thread.clone()];
+    match &*self.await_thread.read() {
+      BlockingThread::Module(module) => {
+        threads.extend(module.read().get_async().read().active_threads())
+      }
+      BlockingThread::TryCatch {
+        try_thread,
+        catch_thread,
+        state,
+      } => match &*state.read() {
+        TryCatchState::Trying => threads.extend(try_thread.read().active_threads()),
+        TryCatchState::Catching => threads.extend(catch_thread.read().active_threads()),
+        _ => {}
+      },
+      BlockingThread::Lazy(_, thread) => threads.extend(thread.read().active_threads()),
+      BlockingThread::Void | BlockingThread::Await(_) => {}
+    }
+    threads
+  }
+  /// El modulo que se esta importando desde este hilo, directamente o desde un valor vago
+  fn awaited_module(&self) -> Option<MultiRefHash<ModuleThread>> {
+    match &*self.await_thread.read() {
+      BlockingThread::Module(module) => Some(module.clone()),
+      BlockingThread::Lazy(_, thread) => thread.read().awaited_module(),
+      _ => None,
+    }
+  }
   pub fn get_vm(&self) -> MultiRefHash<VM> {
     self.get_module().read().get_vm()
   }
   pub fn get_process_manager(&self) -> MultiRefHash<ProcessManager> {
     self.get_vm().read().get_process_manager()
   }
+  /// El modulo no se marca aqui, se marca desde las raices del planificador
+  pub fn trace(&self, tracer: &mut Tracer) {
+    tracer.promise(&self.promise);
+    self.thread.read().trace(tracer);
+    self.await_thread.read().trace(tracer);
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -392,6 +727,62 @@ impl Thread {
   pub fn push_call(&mut self, frame: CallFrame) {
     self.call_stack.push(frame);
   }
+  /// Los hilos sin modulo (valores vagos) no tienen maquina virtual
+  fn vm(&self) -> Option<MultiRefHash<VM>> {
+    let async_thread = self.async_thread.read();
+    async_thread
+      .as_ref()
+      .and_then(|async_thread| async_thread.read().module.clone())
+      .and_then(|module| module.read().vm.clone())
+  }
+  fn max_call_depth(&self) -> usize {
+    match self.vm() {
+      Some(vm) => vm.read().options.max_call_depth,
+      None => VMOptions::default().max_call_depth,
+    }
+  }
+  /// Las funciones nativas deben revisar los permisos antes de acceder al sistema
+  pub fn check_permission(&self, permission: crate::interpreter::Permission) -> Result<(), String> {
+    match self.vm() {
+      Some(vm) => vm.read().options.permissions.check(permission),
+      None => Ok(()),
+    }
+  }
+  /// Cuenta la memoria aproximada de un objeto, lista o texto creado por el programa
+  fn allocate(&self, bytes: usize) {
+    if let Some(vm) = self.vm() {
+      vm.read()
+        .get_process_manager()
+        .read()
+        .get_budget()
+        .allocate(bytes);
+    }
+  }
+  /// Las clausuras pueden formar ciclos con su entorno, el recolector debe conocerlas
+  fn register_closure(&self, closure: &Value) {
+    let function = match closure {
+      Value::Object(Object::Function(function)) => function.clone(),
+      Value::Lazy(lazy) => lazy.get_once(),
+      _ => return,
+    };
+    if let Some(vm) = self.vm() {
+      vm.read()
+        .get_process_manager()
+        .read()
+        .get_collector()
+        .register(&function);
+    }
+  }
+  /// Marca la pila y los marcos de llamadas
+  pub fn trace(&self, tracer: &mut Tracer) {
+    tracer.values(self.stack.read().iter().cloned());
+    for frame in self.call_stack.read().iter() {
+      let (function, locals, slots) = frame.references();
+      tracer.function(function);
+      locals.iter().for_each(|scope| tracer.scope(scope));
+      slots.iter().for_each(|cell| tracer.cell(cell));
+    }
+  }
   fn with_current_frame_mut<R>(&self, callback: impl FnOnce(&mut CallFrame) -> R) -> R {
     callback(self.call_stack.write().last_mut().unwrap())
   }
@@ -405,14 +796,7 @@ impl Thread {
     self.call_stack.read().last().unwrap().current_vars()
   }
   fn resolve(&mut self, name: &str) -> MultiRefHash<VarsManager> {
-    let mut vars = self.globals();
-    for call in self.call_stack.read().iter() {
-      let local_vars = call.resolve_vars(name);
-      if local_vars.read().has(name) {
-        vars = local_vars;
-      }
-    }
-    vars
+    self.call_stack.read().last().unwrap().resolve_vars(name)
   }
   fn declare(&self, name: &str, value: Value, is_constant: bool) -> Option<Value> {
     self
@@ -427,13 +811,11 @@ impl Thread {
     self.resolve(name).read().get(name).cloned()
   }
   pub fn runtime_error(&mut self, message: &str) {
-    let binding = self.call_stack.read();
-    let frame = binding.last().unwrap();
-    let line = frame.current_line().to_string();
-    let name = frame.to_string();
-    eprintln!("[linea {line}] en {name}\n{message}");
-
-    drop(binding);
+    // Sin marcos el error ocurrio cuando el modulo principal ya habia terminado
+    match source_excerpt(&self.call_stack.read()) {
+      Some(excerpt) => eprintln!("{message}\n{excerpt}"),
+      None => eprintln!("{message}"),
+    }
 
     self.reset_stack();
   }
@@ -446,9 +828,18 @@ impl Thread {
   pub fn pop(&mut self) -> Value {
     self.stack.pop()
   }
-  fn init(&self, value: &Value) {
-    if let Value::Lazy(lazy) = value {
+  /// Un valor vago que ya se calculo se reemplaza por su valor, si no se empieza a calcular y
+  /// al terminar se reemplaza en la pila
+  fn init(&self, value: Value) -> Value {
+    if let Value::Lazy(lazy) = &value {
+      if let Some(value) = lazy.get().clone() {
+        return value;
+      }
       let (thread, _) = AsyncThread::new();
+      // Sin el modulo el valor vago no tendria acceso a los permisos ni a los limites
+      if let Some(module) = self.get_async().read().module.clone() {
+        thread.write().set_module(module);
+      }
       let once = lazy.get_once();
       let vars = VarsManager::crate_child(
         once
@@ -461,6 +852,16 @@ impl Thread {
         .push_call(CallFrame::new(once, vec![vars.into()]));
       *self.get_async().read().await_thread.write() = BlockingThread::Lazy(lazy.clone(), thread);
     };
+    value
+  }
+  /// Posicion en el codigo fuente de la ultima instruccion leida
+  fn current_position(&self) -> Position {
+    self
+      .call_stack
+      .read()
+      .last()
+      .map(CallFrame::current_position)
+      .unwrap_or_default()
   }
   fn read(&mut self) -> u8 {
     self.with_current_frame_mut(|frame| frame.read())
@@ -489,6 +890,17 @@ impl Thread {
     this: Value,
     fun: MultiRefHash<Function>,
     args: Vec<Value>,
+  ) -> Result<InterpretResult, String> {
+    self.enter_function(this, fun, args, false)
+  }
+  /// Si `reuse_frame` es verdadero la funcion ocupa el marco actual en lugar de agregar uno nuevo,
+  /// asi la recursion en posicion de cola no hace crecer la pila de llamadas
+  fn enter_function(
+    &mut self,
+    this: Value,
+    fun: MultiRefHash<Function>,
+    args: Vec<Value>,
+    reuse_frame: bool,
   ) -> Result<InterpretResult, String> {
     let fun_clone = fun.clone();
     let function = fun_clone.read();
@@ -522,7 +934,22 @@ impl Thread {
     let locals = vec![VarsManager::crate_child(vars.clone())
       .set_this(this.clone())
       .into()];
-    self.call_stack.push(CallFrame::new(fun, locals));
+    let reuse_frame = reuse_frame && matches!(&*function, Function::Value { .. });
+    // Armar el error lee la funcion, que puede ser la misma que se esta llamando
+    drop(function);
+    if reuse_frame {
+      self.with_current_frame_mut(|frame| frame.reuse(fun, locals));
+    } else {
+      let max_call_depth = self.max_call_depth();
+      if self.call_stack.len() >= max_call_depth {
+        // El error se puede capturar, por eso lleva las llamadas en el mensaje
+        Err(format!(
+          "{STACK_OVERFLOW}: se supero el limite de {max_call_depth} llamadas{}",
+          call_stack_summary(&self.call_stack.read(), CALL_STACK_SUMMARY)
+        ))?
+      }
+      self.call_stack.push(CallFrame::new(fun, locals));
+    }
 
     if arity > args.len() {
       if arity == 1 && args.is_empty() {
@@ -544,6 +971,7 @@ impl Thread {
       arguments.push(arg.clone());
     }
     if has_rest {
+      self.allocate(rest.len() * std::mem::size_of::<Value>());
       arguments.push(Value::Object(rest.into()));
     }
     arguments.reverse();
@@ -557,6 +985,7 @@ impl Thread {
     this: Value,
     callee: Value,
     arity: usize,
+    is_tail: bool,
   ) -> Result<InterpretResult, String> {
     let mut args = vec![];
     for _ in 0..arity {
@@ -610,7 +1039,8 @@ impl Thread {
       return Ok(InterpretResult::Continue);
     }
     if callee.is_function() {
-      return self.call_function(this, callee.as_function(), args);
+      let reuse_frame = is_tail && self.with_current_frame_mut(|frame| frame.can_reuse());
+      return self.enter_function(this, callee.as_function(), args, reuse_frame);
     }
     Err("Se esperaba llamar una funcion".to_string())
   }
@@ -704,9 +1134,9 @@ impl Thread {
       }
       OpCode::SetScope => {
         let value = self.pop();
-        let vars = self.current_vars();
-        value.set_scope(vars);
-        value
+        let closure = self.with_current_frame_mut(|frame| frame.close_over(&value))?;
+        self.register_closure(&closure);
+        closure
       }
       OpCode::At => Value::Iterator(self.pop().into()),
       OpCode::AsRef => Value::Ref(self.pop().into()),
@@ -775,6 +1205,13 @@ impl Thread {
         } else {
           key.to_aga_string(self)
         };
+        let new_slots = match &object {
+          Value::Object(Object::Array(array)) => key
+            .parse::<usize>()
+            .map_or(0, |index| (index + 1).saturating_sub(array.read().len())),
+          _ => 1,
+        };
+        self.allocate(new_slots * std::mem::size_of::<Value>() + key.len());
         match object.set_object_property(&key, value) {
           Some(value) => value,
           None => {
@@ -794,7 +1231,7 @@ impl Thread {
         if is_instance {
           let key = key.to_aga_string(self);
           if let Some(value) = object.get_instance_property(&key, self) {
-            self.init(&value);
+            let value = self.init(value);
             self.push(value);
             return Ok(InterpretResult::Continue);
           }
@@ -838,10 +1275,7 @@ impl Thread {
           key.to_aga_string(self)
         };
         match object.get_object_property(&key) {
-          Some(value) => {
-            self.init(&value);
-            value
-          }
+          Some(value) => self.init(value),
           None => Err(format!(
             "No se pudo obtener la propiedad '{}' de '{}'",
             key,
@@ -859,12 +1293,48 @@ impl Thread {
         return Ok(InterpretResult::Continue);
       }
       OpCode::ArgDecl => {
-        let name = self.read_string();
+        let slot = self.read();
         let value = self.pop();
-        return match self.declare(&name, value.clone(), true) {
-          None => Err(format!("No se pudo declarar la variable '{name}'")),
-          _ => Ok(InterpretResult::Continue),
-        };
+        self.with_current_frame_mut(|frame| frame.set_slot(slot, value));
+        return Ok(InterpretResult::Continue);
+      }
+      OpCode::DeclLocal => {
+        let slot = self.read();
+        let value = self.pop();
+        self.with_current_frame_mut(|frame| frame.set_slot(slot, value.clone()));
+        value
+      }
+      OpCode::GetLocal => {
+        let slot = self.read();
+        let value = self
+          .with_current_frame_mut(|frame| frame.slot(slot))
+          .read()
+          .clone();
+        self.init(value)
+      }
+      OpCode::SetLocal => {
+        let slot = self.read();
+        let value = self.pop();
+        self.with_current_frame_mut(|frame| frame.set_slot(slot, value.clone()));
+        value
+      }
+      OpCode::GetUpvalue => {
+        let index = self.read();
+        let value = self
+          .with_current_frame_mut(|frame| frame.capture(index))
+          .on_error(|_| "No se pudo obtener la variable capturada")?
+          .read()
+          .clone();
+        self.init(value)
+      }
+      OpCode::SetUpvalue => {
+        let index = self.read();
+        let value = self.pop();
+        *self
+          .with_current_frame_mut(|frame| frame.capture(index))
+          .on_error(|_| "No se pudo obtener la variable capturada")?
+          .write() = value.clone();
+        value
       }
       OpCode::Jump => {
         let jump = self.read_short() as usize;
@@ -879,18 +1349,23 @@ impl Thread {
         return Ok(InterpretResult::Continue);
       }
       OpCode::RemoveLocals => {
-        self.with_current_frame_mut(|frame| frame.pop_vars());
+        let alive = self.read();
+        self.with_current_frame_mut(|frame| {
+          frame.pop_vars();
+          frame.truncate_slots(alive);
+        });
         return Ok(InterpretResult::Continue);
       }
       OpCode::NewLocals => {
         self.with_current_frame_mut(|frame| frame.add_vars());
         return Ok(InterpretResult::Continue);
       }
-      OpCode::Call => {
+      OpCode::Call | OpCode::TailCall => {
         let arity = self.read() as usize;
         let callee = self.pop();
         let this = self.pop();
-        return self.call_value(this, callee, arity);
+        let is_tail = instruction == OpCode::TailCall;
+        return self.call_value(this, callee, arity, is_tail);
       }
       OpCode::VarDecl => {
         let name = self.read_string();
@@ -923,8 +1398,7 @@ impl Thread {
         let value = self
           .get(&name)
           .on_error(|_| format!("No se pudo obtener la variable '{name}'"))?;
-        self.init(&value);
-        value
+        self.init(value)
       }
       OpCode::SetVar => {
         let name = self.read_string();
@@ -949,6 +1423,7 @@ impl Thread {
         if a.is_iterator() && b.is_iterator() {
           let a = a.as_strict_array(self)?;
           let b = b.as_strict_array(self)?;
+          self.allocate((a.len() + b.len()) * std::mem::size_of::<Value>());
           self.push(Value::Iterator(
             Value::Object(Object::Array(MultiRefHash::new([a, b].concat()))).into(),
           ));
@@ -957,6 +1432,7 @@ impl Thread {
         if a.is_string() || b.is_string() {
           let a = a.to_aga_string(self);
           let b = b.to_aga_string(self);
+          self.allocate(a.len() + b.len());
           self.push(Value::String(format!("{a}{b}")));
           return Ok(InterpretResult::Continue);
         }
@@ -1079,7 +1555,11 @@ impl Thread {
         let value = self.pop().to_boolean()?;
         Value::from(value)
       }
-      OpCode::ToString => Value::String(self.pop().to_aga_string(self)),
+      OpCode::ToString => {
+        let string = self.pop().to_aga_string(self);
+        self.allocate(string.len());
+        Value::String(string)
+      }
       OpCode::ConsoleOut => {
         let value = self.pop().to_aga_string(self);
         print!("{value}");
@@ -0,0 +1,176 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::compiler::{Function, Promise};
+use crate::MultiRefHash;
+
+use super::collector::Tracer;
+use super::thread::ModuleThread;
+
+/// Lo que se hace cuando se cumple el tiempo de un temporizador
+#[derive(Clone, Debug)]
+pub enum TimerAction {
+  Resolve(Promise),
+  Call(MultiRefHash<Function>, MultiRefHash<ModuleThread>),
+}
+
+#[derive(Debug)]
+struct Timer {
+  id: usize,
+  due: Instant,
+  interval: Option<Duration>,
+  action: TimerAction,
+}
+
+/// Temporizadores pendientes de `:tmp`, mientras haya alguno el programa no termina
+#[derive(Debug, Default)]
+pub struct Timers {
+  next_id: Mutex<usize>,
+  timers: Mutex<Vec<Timer>>,
+}
+impl Timers {
+  /// Con `interval` el temporizador se repite hasta que se cancele
+  pub fn add(&self, delay: Duration, interval: Option<Duration>, action: TimerAction) -> usize {
+    let mut next_id = self.next_id.lock().unwrap();
+    *next_id += 1;
+    let id = *next_id;
+    self.timers.lock().unwrap().push(Timer {
+      id,
+      due: Instant::now() + delay,
+      interval,
+      action,
+    });
+    id
+  }
+  /// Retorna si el temporizador seguia pendiente
+  pub fn cancel(&self, id: usize) -> bool {
+    let mut timers = self.timers.lock().unwrap();
+    let len = timers.len();
+    timers.retain(|timer| timer.id != id);
+    timers.len() != len
+  }
+  pub fn is_empty(&self) -> bool {
+    self.timers.lock().unwrap().is_empty()
+  }
+  /// Tiempo que falta para el proximo temporizador
+  pub fn next_delay(&self) -> Option<Duration> {
+    let now = Instant::now();
+    self
+      .timers
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|timer| timer.due.saturating_duration_since(now))
+      .min()
+  }
+  pub fn trace(&self, tracer: &mut Tracer) {
+    for timer in self.timers.lock().unwrap().iter() {
+      match &timer.action {
+        TimerAction::Resolve(promise) => tracer.promise(promise),
+        TimerAction::Call(callback, _) => tracer.function(callback),
+      }
+    }
+  }
+  /// Saca las acciones de los temporizadores que se cumplieron, los intervalos se vuelven a programar
+  pub fn take_due(&self) -> Vec<TimerAction> {
+    let now = Instant::now();
+    let mut actions = vec![];
+    self.timers.lock().unwrap().retain_mut(|timer| {
+      // Si se cancelo la promesa de `espera` ya no hay nada que hacer
+      if matches!(&timer.action, TimerAction::Resolve(promise) if promise.is_canceled()) {
+        return false;
+      }
+      if timer.due > now {
+        return true;
+      }
+      actions.push(timer.action.clone());
+      match timer.interval {
+        Some(interval) => {
+          // Si el programa se atraso no se acumulan las ejecuciones perdidas
+          timer.due = (timer.due + interval).max(now);
+          true
+        }
+        None => false,
+      }
+    });
+    actions
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::interpreter::{Motor, VMOptions};
+
+  const HOUR: Duration = Duration::from_secs(3600);
+
+  fn resolve() -> TimerAction {
+    TimerAction::Resolve(Promise::new())
+  }
+
+  #[test]
+  fn test_add_and_cancel() {
+    let timers = Timers::default();
+    let first = timers.add(HOUR, None, resolve());
+    let second = timers.add(HOUR, None, resolve());
+    assert_ne!(first, second);
+    assert!(timers.cancel(first));
+    assert!(!timers.cancel(first));
+    assert!(!timers.is_empty());
+    assert!(timers.cancel(second));
+    assert!(timers.is_empty());
+  }
+
+  #[test]
+  fn test_take_due() {
+    let timers = Timers::default();
+    timers.add(Duration::ZERO, None, resolve());
+    timers.add(HOUR, None, resolve());
+    assert_eq!(timers.take_due().len(), 1);
+    assert!(timers.take_due().is_empty());
+    assert!(timers.next_delay().is_some_and(|delay| delay <= HOUR));
+  }
+
+  #[test]
+  fn test_intervals_are_rescheduled() {
+    let timers = Timers::default();
+    timers.add(Duration::ZERO, Some(HOUR), resolve());
+    assert_eq!(timers.take_due().len(), 1);
+    assert!(timers.take_due().is_empty());
+    assert!(!timers.is_empty());
+    assert!(timers
+      .next_delay()
+      .is_some_and(|delay| delay > HOUR / 2 && delay <= HOUR));
+  }
+
+  #[test]
+  fn test_canceled_sleep_is_dropped() {
+    let timers = Timers::default();
+    let promise = Promise::new();
+    timers.add(Duration::ZERO, None, TimerAction::Resolve(promise.clone()));
+    promise.cancel();
+    assert!(timers.take_due().is_empty());
+    assert!(timers.is_empty());
+  }
+
+  #[test]
+  fn test_tmp_timers() {
+    let code = "importa ':tmp' como T;
+def veces = [0];
+def antes = [falso];
+def cancelado = T::temporizador(1, fn() { antes[0] = cierto; });
+cancelado::cancela();
+def intervalo = [nada];
+intervalo[0] = T::intervalo(1, fn() {
+  veces[0] += 1;
+  si veces[0] == 3 { intervalo[0]::cancela(); }
+});
+exporta nombre veces;
+exporta nombre antes;";
+    let mut motor = Motor::new(VMOptions::default());
+    motor.load_source(code, "tiempo.aga").unwrap();
+    motor.run().unwrap();
+    assert_eq!(motor.get_export::<Vec<f64>>("veces").unwrap(), [3.0]);
+    assert_eq!(motor.get_export::<Vec<bool>>("antes").unwrap(), [false]);
+  }
+}
@@ -0,0 +1,173 @@
+//! Interprete de Agal. Ademas del ejecutable `agalang-core`, se puede incluir en otros programas
+//! por medio de [`Motor`]
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::compiler::Compiler;
+pub use crate::util::{MultiRefHash, OnError, OnSome};
+
+#[doc(hidden)]
+pub mod agal_parser;
+pub mod compiler;
+pub mod ffi;
+mod functions_names;
+pub mod interpreter;
+#[doc(hidden)]
+pub mod tokens;
+mod util;
+
+pub use compiler::{FromValue, IntoValue, Value};
+pub use interpreter::{HostModule, Motor};
+
+pub use crate::compiler::binary::Encode;
+use crate::compiler::binary::{Decode, StructTag};
+
+pub const EXTENSION_BYTECODE: &str = "agab";
+pub const EXTENSION: &str = "aga";
+
+pub fn read_code(path: &Path) -> Option<String> {
+  let contents = std::fs::read_to_string(path);
+  match contents {
+    Ok(contents) => Some(contents),
+    Err(err) => {
+      let type_err = agal_parser::ErrorNames::PathError;
+      let err = agal_parser::ErrorTypes::Io(err);
+      agal_parser::show_error(&type_err, err);
+      None
+    }
+  }
+}
+pub fn read_bin(path: &Path) -> Option<Vec<u8>> {
+  let contents = std::fs::read(path);
+  match contents {
+    Ok(contents) => Some(contents),
+    Err(err) => {
+      let type_err = agal_parser::ErrorNames::PathError;
+      let err = agal_parser::ErrorTypes::Io(err);
+      agal_parser::show_error(&type_err, err);
+      None
+    }
+  }
+}
+pub fn compile_bytecode(vec: Vec<u8>) -> Result<Compiler, String> {
+  let compiler = Compiler::decode(&mut VecDeque::from(vec))?;
+  compiler.verify()?;
+  Ok(compiler)
+}
+/// Compila un archivo de codigo o de bytecode, los errores de sintaxis se muestran al compilar
+pub fn compile(path: &Path) -> Result<(Compiler, &str), String> {
+  match path.extension().on_some_option(|v| v.to_str()) {
+    Some(EXTENSION) => {
+      let file = read_code(path).on_error(|_| "No se pudo leer el archivo")?;
+      let ast = agal_parser::Parser::new(&file, path)
+        .produce_ast()
+        .on_error(|e| {
+          if !e.message.is_empty() {
+            agal_parser::print_error(agal_parser::error_to_string(
+              &agal_parser::ErrorNames::SyntaxError,
+              agal_parser::node_error(&e, &file),
+            ));
+          }
+          ""
+        })?;
+      Ok(((&ast).try_into()?, EXTENSION))
+    }
+    Some(EXTENSION_BYTECODE) => Ok((
+      compile_bytecode(read_bin(path).on_error(|_| "")?)?,
+      EXTENSION_BYTECODE,
+    )),
+    _ => Err("Se esperaba un archivo con extension valida".to_string()),
+  }
+}
+
+/// Compila codigo que no esta en un archivo, `path` se usa para los errores y para resolver las
+/// importaciones relativas
+pub fn compile_source(code: &str, path: &Path) -> Result<Compiler, String> {
+  let ast = agal_parser::Parser::new(code, path)
+    .produce_ast()
+    .map_err(|e| {
+      agal_parser::error_to_string(
+        &agal_parser::ErrorNames::SyntaxError,
+        agal_parser::node_error(&e, code),
+      )
+    })?;
+  (&ast).try_into()
+}
+
+pub trait ToJSON {
+  fn to_json(&self) -> String;
+}
+impl ToJSON for String {
+  fn to_json(&self) -> String {
+    let mut json = String::with_capacity(self.len() + 2);
+    json.push('"');
+    for char in self.chars() {
+      match char {
+        '\\' => json.push_str("\\\\"),
+        '"' => json.push_str("\\\""),
+        '\n' => json.push_str("\\n"),
+        '\r' => json.push_str("\\r"),
+        '\t' => json.push_str("\\t"),
+        // Los demas caracteres de control, como los de los colores, no son validos en JSON
+        char if char.is_control() => json.push_str(&format!("\\u{:04x}", char as u32)),
+        char => json.push(char),
+      }
+    }
+    json.push('"');
+    json
+  }
+}
+impl<T> ToJSON for Option<T>
+where
+  T: ToJSON,
+{
+  fn to_json(&self) -> String {
+    match self {
+      None => "null".to_string(),
+      Some(t) => t.to_json(),
+    }
+  }
+}
+impl<T> ToJSON for Vec<T>
+where
+  T: ToJSON,
+{
+  fn to_json(&self) -> String {
+    let mut data = String::new();
+    data.push('[');
+    let mut is_first = true;
+    for item in self {
+      if !is_first {
+        data.push(',');
+      }
+      data.push_str(&item.to_json());
+      is_first = false;
+    }
+    data.push(']');
+    data
+  }
+}
+impl<K, T> ToJSON for HashMap<K, T>
+where
+  T: ToJSON,
+  K: ToString,
+{
+  fn to_json(&self) -> String {
+    let mut json = String::new();
+    json.push('{');
+    let mut is_first = true;
+    for (key, value) in self.iter() {
+      if is_first {
+        is_first = false;
+      } else {
+        json.push(',');
+      }
+      json.push_str(&key.to_string().to_json());
+      json.push(':');
+      json.push_str(&value.to_json());
+    }
+    json.push('}');
+    json
+  }
+}
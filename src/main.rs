@@ -1,21 +1,11 @@
-use std::collections::VecDeque;
 use std::path::Path;
 use std::{collections::HashMap, process::ExitCode};
 
-pub use crate::util::{MultiRefHash, OnError, OnSome};
-use crate::{compiler::Compiler, interpreter::interpret};
-
-mod agal_parser;
-mod compiler;
-mod functions_names;
-mod interpreter;
-mod tokens;
-mod util;
-
-use crate::compiler::binary::{Decode, Encode, StructTag};
-
-const EXTENSION_BYTECODE: &str = "agab";
-const EXTENSION: &str = "aga";
+use agalang_core::interpreter::{self, interpret};
+use agalang_core::{
+  agal_parser, compile, read_code, tokens, Encode as _, OnError as _, OnSome, EXTENSION,
+  EXTENSION_BYTECODE,
+};
 
 fn main() -> ExitCode {
   let args = Arguments::init();
@@ -29,6 +19,20 @@ fn main() -> ExitCode {
     return ExitCode::FAILURE;
   }
 
+  // El editor puede indicar el programa en el mensaje `launch`
+  if args.action == Action::DebugAdapter {
+    let options = match vm_options(&args) {
+      Ok(options) => options,
+      Err(e) => {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+      }
+    };
+    let file = (!args.file.is_empty()).then(|| args.file.clone().into());
+    interpreter::debug_adapter(file, options);
+    return ExitCode::SUCCESS;
+  }
+
   let file_name = if args.file.is_empty() {
     let blue_usage = "\x1b[94m\x1b[1mUsage\x1b[39m:\x1b[0m";
     eprintln!("{} {} <filename>", blue_usage, args.binary);
@@ -67,7 +71,7 @@ fn main() -> ExitCode {
       None => ExitCode::FAILURE,
     };
   }
-  let (compiler, extension) = match compile(path) {
+  let (mut compiler, extension) = match compile(path) {
     Err(e) => {
       if !e.is_empty() {
         eprintln!("{e}");
@@ -76,6 +80,9 @@ fn main() -> ExitCode {
     }
     Ok(v) => v,
   };
+  if args.get_bool(&FlagName::Optimize) {
+    compiler.optimize();
+  }
 
   if args.action == Action::Compile && extension == EXTENSION {
     let code = compiler.encode();
@@ -91,8 +98,28 @@ fn main() -> ExitCode {
     };
     let _ = std::fs::write(format!("{name}.{EXTENSION_BYTECODE}"), &code);
   }
+  if args.action == Action::Debug {
+    let options = match vm_options(&args) {
+      Ok(options) => options,
+      Err(e) => {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+      }
+    };
+    return match interpreter::debug(compiler, options) {
+      interpreter::InterpretResult::Ok => ExitCode::SUCCESS,
+      _ => ExitCode::FAILURE,
+    };
+  }
   if args.action == Action::Run || args.get_bool(&FlagName::Name) {
-    return match interpret(compiler) {
+    let options = match vm_options(&args) {
+      Ok(options) => options,
+      Err(e) => {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+      }
+    };
+    return match interpret(compiler, options) {
       Err(_) => ExitCode::FAILURE,
       _ => ExitCode::SUCCESS,
     };
@@ -100,63 +127,86 @@ fn main() -> ExitCode {
   ExitCode::SUCCESS
 }
 
-fn read_code(path: &Path) -> Option<String> {
-  let contents = std::fs::read_to_string(path);
-  match contents {
-    Ok(contents) => Some(contents),
-    Err(err) => {
-      let type_err = agal_parser::ErrorNames::PathError;
-      let err = agal_parser::ErrorTypes::Io(err);
-      agal_parser::show_error(&type_err, err);
-      None
+fn vm_options(args: &Arguments) -> Result<interpreter::VMOptions, String> {
+  let positive = |flag: &FlagName, name: &str| -> Result<Option<usize>, String> {
+    let value = args.get_string(flag);
+    if value.is_empty() {
+      return Ok(None);
     }
-  }
-}
-fn read_bin(path: &Path) -> Option<Vec<u8>> {
-  let contents = std::fs::read(path);
-  match contents {
-    Ok(contents) => Some(contents),
-    Err(err) => {
-      let type_err = agal_parser::ErrorNames::PathError;
-      let err = agal_parser::ErrorTypes::Io(err);
-      agal_parser::show_error(&type_err, err);
-      None
+    match value.parse() {
+      Ok(value) if value > 0 => Ok(Some(value)),
+      _ => Err(format!("Se esperaba un numero positivo para --{name}")),
     }
+  };
+  let mut options = interpreter::VMOptions::default();
+  if let Some(depth) = positive(&FlagName::MaxCallDepth, "pila-maxima")? {
+    options.max_call_depth = depth;
   }
-}
-fn compile_bytecode(vec: Vec<u8>) -> Result<Compiler, String> {
-  Compiler::decode(&mut VecDeque::from(vec))
-}
-fn compile(path: &Path) -> Result<(Compiler, &str), String> {
-  match path.extension().on_some_option(|v| v.to_str()) {
-    Some(EXTENSION) => {
-      let file = read_code(path).on_error(|_| "No se pudo leer el archivo")?;
-      let ast = agal_parser::Parser::new(&file, path)
-        .produce_ast()
-        .on_error(|e| {
-          if !e.message.is_empty() {
-            agal_parser::print_error(agal_parser::error_to_string(
-              &agal_parser::ErrorNames::SyntaxError,
-              agal_parser::node_error(&e, &file),
-            ));
-          }
-          ""
-        })?;
-      Ok(((&ast).try_into()?, EXTENSION))
+  if let Some(slice) = positive(&FlagName::TimeSlice, "rebanada")? {
+    options.time_slice = slice;
+  }
+  options.max_instructions = positive(&FlagName::MaxInstructions, "instrucciones-maximas")?;
+  options.timeout = positive(&FlagName::Timeout, "tiempo-maximo")?
+    .map(|millis| std::time::Duration::from_millis(millis as u64));
+  options.max_allocated = positive(&FlagName::MaxAllocated, "asignacion-maxima")?;
+  options.fatal_rejections = args.get_bool(&FlagName::FatalRejections);
+  if let Some(threshold) = positive(&FlagName::GcThreshold, "umbral-ciclos")? {
+    options.gc_threshold = Some(threshold);
+  }
+  // Con un valor tambien se escriben las pilas de llamadas en ese archivo
+  match args.flags.get(&FlagName::Profile) {
+    Some(FlagValue::String(path)) => {
+      options.profile = true;
+      options.profile_stacks = Some(path.into());
     }
-    Some(EXTENSION_BYTECODE) => Ok((
-      compile_bytecode(read_bin(path).on_error(|_| "")?)?,
-      EXTENSION_BYTECODE,
-    )),
-    _ => Err("Se esperaba un archivo con extension valida".to_string()),
+    Some(FlagValue::Boolean(profile)) => options.profile = *profile,
+    None => {}
   }
-}
 
+  // Pedir un permiso activa el modo seguro, lo que no se permite queda denegado
+  let permissions = &mut options.permissions;
+  match args.flags.get(&FlagName::AllowFileSystem) {
+    Some(FlagValue::String(paths)) => permissions.allow_paths(paths),
+    Some(FlagValue::Boolean(_)) => permissions.file_system = interpreter::Grant::All,
+    None => {}
+  }
+  match args.flags.get(&FlagName::AllowNet) {
+    Some(FlagValue::String(addresses)) => permissions.allow_addresses(addresses),
+    Some(FlagValue::Boolean(_)) => permissions.net = interpreter::Grant::All,
+    None => {}
+  }
+  permissions.process = args.get_bool(&FlagName::AllowProcess);
+  permissions.native = args.get_bool(&FlagName::AllowNative);
+  permissions.secure = [
+    FlagName::Secure,
+    FlagName::AllowFileSystem,
+    FlagName::AllowNet,
+    FlagName::AllowProcess,
+    FlagName::AllowNative,
+  ]
+  .iter()
+  .any(|flag| args.flags.contains_key(flag));
+  Ok(options)
+}
 #[derive(PartialEq, Eq, Hash)]
 enum FlagName {
   Run,
   Name,
   Compress,
+  Optimize,
+  MaxCallDepth,
+  TimeSlice,
+  MaxInstructions,
+  Timeout,
+  MaxAllocated,
+  FatalRejections,
+  GcThreshold,
+  Profile,
+  Secure,
+  AllowFileSystem,
+  AllowNet,
+  AllowProcess,
+  AllowNative,
   Help,
   None,
 }
@@ -165,6 +215,20 @@ impl From<String> for FlagName {
     match value.as_str() {
       "ejecutar" | "run" | "e" | "r" => Self::Run,
       "comprimir" | "compress" | "c" => Self::Compress,
+      "optimizar" | "optimize" | "o" => Self::Optimize,
+      "pila-maxima" | "max-stack" => Self::MaxCallDepth,
+      "rebanada" | "time-slice" => Self::TimeSlice,
+      "instrucciones-maximas" | "max-instructions" => Self::MaxInstructions,
+      "tiempo-maximo" | "timeout" => Self::Timeout,
+      "asignacion-maxima" | "max-allocation" => Self::MaxAllocated,
+      "rechazos-fatales" | "fatal-rejections" => Self::FatalRejections,
+      "umbral-ciclos" | "gc-threshold" => Self::GcThreshold,
+      "perfilar" | "profile" => Self::Profile,
+      "seguro" | "secure" => Self::Secure,
+      "permitir-sa" | "allow-fs" => Self::AllowFileSystem,
+      "permitir-red" | "allow-net" => Self::AllowNet,
+      "permitir-proceso" | "allow-process" => Self::AllowProcess,
+      "permitir-nativo" | "allow-native" => Self::AllowNative,
       "ayuda" | "help" | "a" | "h" => Self::Help,
       "nombre" | "name" | "n" => Self::Name,
       _ => Self::None,
@@ -180,6 +244,8 @@ enum FlagValue {
 enum Action {
   Compile,
   Run,
+  Debug,
+  DebugAdapter,
   Help,
   SyntaxisTokens,
   Unknown(String),
@@ -194,6 +260,8 @@ impl From<String> for Action {
     match value.to_lowercase().as_str() {
       "ejecutar" | "run" | "e" | "r" => Action::Run,
       "compilar" | "compile" | "c" => Action::Compile,
+      "depurar" | "debug" | "d" => Action::Debug,
+      "dap" => Action::DebugAdapter,
       "ayuda" | "help" | "a" | "h" => Action::Help,
       "tokens" => Action::SyntaxisTokens,
       _ => Action::Unknown(value),
@@ -247,6 +315,9 @@ impl Arguments {
       if arg.starts_with('"') {
         string_arg.push_str(&arg);
         on_string_arg = true;
+      } else if let Some((key, value)) = arg.strip_prefix("--").and_then(|arg| arg.split_once('='))
+      {
+        flags.insert(key.to_string().into(), FlagValue::String(value.to_string()));
       } else if arg.starts_with("--") {
         let key: FlagName = arg.trim_start_matches("--").to_string().into();
         let next = cmd_args.peek(); // peek
@@ -297,74 +368,3 @@ impl Arguments {
     }
   }
 }
-
-pub trait ToJSON {
-  fn to_json(&self) -> String;
-}
-impl ToJSON for String {
-  fn to_json(&self) -> String {
-    format!(
-      "\"{}\"",
-      self
-        .replace('\\', "\\\\")
-        .replace('"', "\\\"")
-        .replace('\n', "\\n")
-    )
-    .replace('\r', "\\r")
-    .replace('\t', "\\t")
-    .replace('\0', "\\0")
-  }
-}
-impl<T> ToJSON for Option<T>
-where
-  T: ToJSON,
-{
-  fn to_json(&self) -> String {
-    match self {
-      None => "null".to_string(),
-      Some(t) => t.to_json(),
-    }
-  }
-}
-impl<T> ToJSON for Vec<T>
-where
-  T: ToJSON,
-{
-  fn to_json(&self) -> String {
-    let mut data = String::new();
-    data.push('[');
-    let mut is_first = true;
-    for item in self {
-      if !is_first {
-        data.push(',');
-      }
-      data.push_str(&item.to_json());
-      is_first = false;
-    }
-    data.push(']');
-    data
-  }
-}
-impl<K, T> ToJSON for HashMap<K, T>
-where
-  T: ToJSON,
-  K: ToString,
-{
-  fn to_json(&self) -> String {
-    let mut json = String::new();
-    json.push('{');
-    let mut is_first = true;
-    for (key, value) in self.iter() {
-      if is_first {
-        is_first = false;
-      } else {
-        json.push(',');
-      }
-      json.push_str(&key.to_string().to_json());
-      json.push(':');
-      json.push_str(&value.to_json());
-    }
-    json.push('}');
-    json
-  }
-}
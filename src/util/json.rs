@@ -0,0 +1,295 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::ToJSON;
+
+/// Un valor de JSON, las propiedades de los objetos conservan su orden
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+impl Json {
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+      None => Ok(value),
+      Some(char) => Err(format!("Caracter '{char}' inesperado despues del valor")),
+    }
+  }
+  pub fn object<const N: usize>(properties: [(&str, Json); N]) -> Self {
+    Self::Object(
+      properties
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect(),
+    )
+  }
+  /// La propiedad de un objeto, `Null` si no es un objeto o no la tiene
+  pub fn get(&self, key: &str) -> &Json {
+    match self {
+      Self::Object(properties) => properties
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value)
+        .unwrap_or(&Json::Null),
+      _ => &Json::Null,
+    }
+  }
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Self::String(string) => Some(string),
+      _ => None,
+    }
+  }
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Self::Bool(bool) => Some(*bool),
+      _ => None,
+    }
+  }
+  pub fn as_i64(&self) -> Option<i64> {
+    match self {
+      Self::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+      _ => None,
+    }
+  }
+  pub fn as_array(&self) -> &[Json] {
+    match self {
+      Self::Array(values) => values,
+      _ => &[],
+    }
+  }
+}
+impl ToJSON for Json {
+  fn to_json(&self) -> String {
+    match self {
+      Self::Null => "null".to_string(),
+      Self::Bool(bool) => bool.to_string(),
+      Self::Number(number) if number.is_finite() => number.to_string(),
+      Self::Number(_) => "null".to_string(),
+      Self::String(string) => string.to_json(),
+      Self::Array(values) => {
+        let values: Vec<String> = values.iter().map(ToJSON::to_json).collect();
+        format!("[{}]", values.join(","))
+      }
+      Self::Object(properties) => {
+        let properties: Vec<String> = properties
+          .iter()
+          .map(|(key, value)| format!("{}:{}", key.to_json(), value.to_json()))
+          .collect();
+        format!("{{{}}}", properties.join(","))
+      }
+    }
+  }
+}
+impl From<bool> for Json {
+  fn from(value: bool) -> Self {
+    Self::Bool(value)
+  }
+}
+impl From<i64> for Json {
+  fn from(value: i64) -> Self {
+    Self::Number(value as f64)
+  }
+}
+impl From<usize> for Json {
+  fn from(value: usize) -> Self {
+    Self::Number(value as f64)
+  }
+}
+impl From<&str> for Json {
+  fn from(value: &str) -> Self {
+    Self::String(value.to_string())
+  }
+}
+impl From<String> for Json {
+  fn from(value: String) -> Self {
+    Self::String(value)
+  }
+}
+impl From<Vec<Json>> for Json {
+  fn from(value: Vec<Json>) -> Self {
+    Self::Array(value)
+  }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+  while chars.next_if(|char| char.is_whitespace()).is_some() {}
+}
+fn expect_word(chars: &mut Peekable<Chars>, word: &str, value: Json) -> Result<Json, String> {
+  for expected in word.chars() {
+    if chars.next() != Some(expected) {
+      return Err(format!("Se esperaba '{word}'"));
+    }
+  }
+  Ok(value)
+}
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+  skip_whitespace(chars);
+  match chars.peek() {
+    Some('n') => expect_word(chars, "null", Json::Null),
+    Some('t') => expect_word(chars, "true", Json::Bool(true)),
+    Some('f') => expect_word(chars, "false", Json::Bool(false)),
+    Some('"') => parse_string(chars).map(Json::String),
+    Some('[') => {
+      chars.next();
+      let mut values = vec![];
+      skip_whitespace(chars);
+      if chars.next_if_eq(&']').is_some() {
+        return Ok(Json::Array(values));
+      }
+      loop {
+        values.push(parse_value(chars)?);
+        skip_whitespace(chars);
+        match chars.next() {
+          Some(',') => continue,
+          Some(']') => return Ok(Json::Array(values)),
+          _ => return Err("Se esperaba ',' o ']'".to_string()),
+        }
+      }
+    }
+    Some('{') => {
+      chars.next();
+      let mut properties = vec![];
+      skip_whitespace(chars);
+      if chars.next_if_eq(&'}').is_some() {
+        return Ok(Json::Object(properties));
+      }
+      loop {
+        skip_whitespace(chars);
+        let key = parse_string(chars)?;
+        skip_whitespace(chars);
+        if chars.next() != Some(':') {
+          return Err("Se esperaba ':'".to_string());
+        }
+        properties.push((key, parse_value(chars)?));
+        skip_whitespace(chars);
+        match chars.next() {
+          Some(',') => continue,
+          Some('}') => return Ok(Json::Object(properties)),
+          _ => return Err("Se esperaba ',' o '}'".to_string()),
+        }
+      }
+    }
+    Some(char) if *char == '-' || char.is_ascii_digit() => {
+      let mut number = String::new();
+      while let Some(char) =
+        chars.next_if(|char| char.is_ascii_digit() || matches!(char, '-' | '+' | '.' | 'e' | 'E'))
+      {
+        number.push(char);
+      }
+      number
+        .parse()
+        .map(Json::Number)
+        .map_err(|_| format!("Numero '{number}' invalido"))
+    }
+    Some(char) => Err(format!("Caracter '{char}' inesperado")),
+    None => Err("Se esperaba un valor".to_string()),
+  }
+}
+fn parse_hex(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+  let hex: String = chars.by_ref().take(4).collect();
+  u32::from_str_radix(&hex, 16).map_err(|_| format!("Escape '\\u{hex}' invalido"))
+}
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+  if chars.next() != Some('"') {
+    return Err("Se esperaba una cadena".to_string());
+  }
+  let mut string = String::new();
+  loop {
+    match chars.next() {
+      Some('"') => return Ok(string),
+      Some('\\') => match chars.next() {
+        Some('n') => string.push('\n'),
+        Some('r') => string.push('\r'),
+        Some('t') => string.push('\t'),
+        Some('b') => string.push('\u{8}'),
+        Some('f') => string.push('\u{c}'),
+        Some('u') => {
+          let mut code = parse_hex(chars)?;
+          // Los caracteres fuera del plano basico vienen en dos partes
+          if (0xD800..0xDC00).contains(&code)
+            && chars.next_if_eq(&'\\').is_some()
+            && chars.next_if_eq(&'u').is_some()
+          {
+            let low = parse_hex(chars)?;
+            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+          }
+          string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        Some(char) => string.push(char),
+        None => return Err("Cadena sin terminar".to_string()),
+      },
+      Some(char) => string.push(char),
+      None => return Err("Cadena sin terminar".to_string()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_values() {
+    let value = Json::parse(
+      r#" { "nulo": null, "si": true, "no": false, "numero": -1.5e2,
+        "lista": [1, "dos", []], "objeto": {} } "#,
+    )
+    .unwrap();
+    assert_eq!(value.get("nulo"), &Json::Null);
+    assert_eq!(value.get("si").as_bool(), Some(true));
+    assert_eq!(value.get("no").as_bool(), Some(false));
+    assert_eq!(value.get("numero"), &Json::Number(-150.0));
+    assert_eq!(value.get("numero").as_i64(), Some(-150));
+    assert_eq!(
+      value.get("lista").as_array(),
+      [Json::from(1i64), Json::from("dos"), Json::Array(vec![])]
+    );
+    assert_eq!(value.get("objeto"), &Json::Object(vec![]));
+    assert_eq!(value.get("falta"), &Json::Null);
+    assert_eq!(value.get("si").get("no"), &Json::Null);
+  }
+
+  #[test]
+  fn test_parse_escapes() {
+    let value = Json::parse(r#""a\"b\\c\/\n\tá😀""#).unwrap();
+    assert_eq!(value.as_str(), Some("a\"b\\c/\n\tá😀"));
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let error = |text| Json::parse(text).unwrap_err();
+    assert_eq!(error(""), "Se esperaba un valor");
+    assert_eq!(error("nul"), "Se esperaba 'null'");
+    assert_eq!(error("[1 2]"), "Se esperaba ',' o ']'");
+    assert_eq!(error(r#"{"a" 1}"#), "Se esperaba ':'");
+    assert_eq!(error("{1: 2}"), "Se esperaba una cadena");
+    assert_eq!(error(r#""abc"#), "Cadena sin terminar");
+    assert_eq!(error("1-"), "Numero '1-' invalido");
+    assert_eq!(error(r#""\uzz00""#), "Escape '\\uzz00' invalido");
+    assert_eq!(error("1 2"), "Caracter '2' inesperado despues del valor");
+  }
+
+  #[test]
+  fn test_to_json() {
+    let value = Json::object([
+      ("nombre", "agal\n".into()),
+      ("lista", vec![Json::Null, true.into(), 2usize.into()].into()),
+      ("infinito", Json::Number(f64::INFINITY)),
+    ]);
+    let text = value.to_json();
+    assert_eq!(
+      text,
+      r#"{"nombre":"agal\n","lista":[null,true,2],"infinito":null}"#
+    );
+    assert_eq!(Json::parse(&text).unwrap().get("lista"), value.get("lista"));
+  }
+}
@@ -1,4 +1,5 @@
 mod colors;
+pub mod json;
 pub mod list;
 mod multi_ref_hash;
 mod tokenize;
@@ -1,4 +1,4 @@
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, Weak};
 
 use crate::util::MutClone;
 
@@ -26,6 +26,14 @@ impl<T> MultiRefHash<T> {
   pub fn write(&'_ self) -> std::sync::RwLockWriteGuard<'_, T> {
     self.0.write().unwrap()
   }
+  /// Direccion del valor compartido, identifica al objeto mientras siga vivo
+  pub fn addr(&self) -> usize {
+    Arc::as_ptr(&self.0) as *const () as usize
+  }
+  /// Referencia que no mantiene vivo al valor
+  pub fn downgrade(&self) -> Weak<RwLock<T>> {
+    Arc::downgrade(&self.0)
+  }
 }
 impl<T> std::hash::Hash for MultiRefHash<T> {
   fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
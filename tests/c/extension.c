@@ -0,0 +1,72 @@
+/*
+ * Ejemplo de extension nativa, desde la raiz del repositorio:
+ *   cc -shared -fPIC tests/c/extension.c -Iinclude -o target/libejemplo.so
+ * y desde un programa de Agal en target/:
+ *   importa ':nativo/libejemplo.so' como E;
+ *   E::suma(1, 2); # 3
+ *
+ * La prueba `test_c_extension` de src/interpreter/libs/native.rs la compila y la usa.
+ */
+#include <string.h>
+
+#include "agal_extension.h"
+
+#ifdef _WIN32
+#define EXPORT __declspec(dllexport)
+#else
+#define EXPORT
+#endif
+
+static AgalHandle *throw_error(const AgalApi *api, const char *message) {
+  return api->error((const uint8_t *)message, strlen(message));
+}
+
+/* suma(a, b): la suma de dos numeros */
+static AgalHandle *sum(const AgalApi *api, AgalHandle *const *args, size_t len) {
+  double a, b;
+  if (len != 2 || !api->as_number(args[0], &a) || !api->as_number(args[1], &b)) {
+    return throw_error(api, "suma recibe dos numeros");
+  }
+  return api->number(a + b);
+}
+
+/* saludo(nombre): "Hola, " y el nombre */
+static AgalHandle *greet(const AgalApi *api, AgalHandle *const *args, size_t len) {
+  const uint8_t *name;
+  size_t name_len;
+  if (len != 1 || !api->as_string(args[0], &name, &name_len)) {
+    return throw_error(api, "saludo recibe una cadena");
+  }
+  uint8_t text[256] = "Hola, ";
+  size_t prefix = strlen((const char *)text);
+  if (name_len > sizeof(text) - prefix) {
+    return throw_error(api, "El nombre es muy largo");
+  }
+  memcpy(text + prefix, name, name_len);
+  return api->string(text, prefix + name_len);
+}
+
+/* tipos(...): una lista con el tipo de cada argumento */
+static AgalHandle *types(const AgalApi *api, AgalHandle *const *args, size_t len) {
+  AgalHandle *list = api->list();
+  for (size_t i = 0; i < len; i++) {
+    api->list_push(list, api->number(api->type_of(args[i])));
+  }
+  return list;
+}
+
+static const AgalExtensionFunction FUNCTIONS[] = {
+  {"suma", sum},
+  {"saludo", greet},
+  {"tipos", types},
+};
+
+static const AgalModule MODULE = {
+  AGAL_ABI_VERSION,
+  FUNCTIONS,
+  sizeof(FUNCTIONS) / sizeof(FUNCTIONS[0]),
+};
+
+EXPORT const AgalModule *agal_registrar(uint32_t version) {
+  return version == AGAL_ABI_VERSION ? &MODULE : NULL;
+}
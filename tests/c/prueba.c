@@ -0,0 +1,136 @@
+/*
+ * Prueba de la interfaz de C, desde la raiz del repositorio:
+ *   cargo build --release
+ *   cc tests/c/prueba.c -Iinclude -Ltarget/release -lagalang_core -o target/prueba
+ *   LD_LIBRARY_PATH=target/release ./target/prueba
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "agal.h"
+
+static int failures = 0;
+
+static void check(bool condition, const char *message) {
+  if (!condition) {
+    fprintf(stderr, "FALLO: %s\n", message);
+    failures++;
+  }
+}
+
+static bool string_is(const AgalValue *value, const char *expected) {
+  char *text = agal_value_as_string(value);
+  bool equal = text != NULL && strcmp(text, expected) == 0;
+  agal_string_free(text);
+  return equal;
+}
+
+/* El texto puede tener exponente, como "15E-1" */
+static bool number_is(const AgalValue *value, double expected) {
+  char *text = agal_value_as_number(value);
+  bool equal = text != NULL && strtod(text, NULL) == expected;
+  agal_string_free(text);
+  return equal;
+}
+
+/* Suma los numeros de la lista que recibe, usando `data` como valor inicial */
+static AgalValue *sum(void *data, AgalValue *const *args, size_t len) {
+  long total = *(long *)data;
+  if (len != 1 || agal_value_type(args[0]) != AGAL_TYPE_LIST) {
+    agal_throw("suma: se esperaba una lista");
+    return NULL;
+  }
+  for (size_t i = 0; i < agal_list_len(args[0]); i++) {
+    AgalValue *item = agal_list_get(args[0], i);
+    char *text = agal_value_as_number(item);
+    if (text != NULL) {
+      total += strtol(text, NULL, 10);
+    }
+    agal_string_free(text);
+    agal_value_free(item);
+  }
+  char buffer[32];
+  snprintf(buffer, sizeof buffer, "%ld", total);
+  return agal_value_number(buffer);
+}
+
+/* Retorna el mismo argumento */
+static AgalValue *identity(void *data, AgalValue *const *args, size_t len) {
+  (void)data;
+  return len > 0 ? args[0] : NULL;
+}
+
+static const char *PROGRAM =
+    "importa ':anfitrion' como A;\n"
+    "exporta def total = A::suma([1, 2, 3]);\n"
+    "exporta def mismo = A::mismo('hola');\n"
+    "exporta fn saludo(quien, activo) {\n"
+    "  si activo { ret 'hola ' + quien + ' ' + nivel; }\n"
+    "  ret [quien, 1.5, falso];\n"
+    "}\n"
+    "exporta fn falla() { ret A::suma(1); }\n";
+
+int main(void) {
+  long initial = 10;
+  AgalVM *vm = agal_vm_new();
+  check(agal_vm_register(vm, "anfitrion", "suma", sum, &initial), "registrar suma");
+  check(agal_vm_register(vm, "anfitrion", "mismo", identity, NULL), "registrar mismo");
+
+  AgalValue *level = agal_value_number("7");
+  check(agal_vm_set_global(vm, "nivel", level), "asignar global");
+  agal_value_free(level);
+
+  if (!agal_vm_eval(vm, PROGRAM, "prueba.aga")) {
+    fprintf(stderr, "FALLO: %s\n", agal_vm_error(vm));
+    return 1;
+  }
+
+  AgalValue *total = agal_vm_get_export(vm, "total");
+  check(number_is(total, 16), "suma con los datos registrados");
+  agal_value_free(total);
+
+  AgalValue *same = agal_vm_get_export(vm, "mismo");
+  check(string_is(same, "hola"), "retornar un argumento");
+  agal_value_free(same);
+
+  AgalValue *args[2] = {agal_value_string("Agal"), agal_value_bool(true)};
+  AgalValue *greeting = agal_vm_call(vm, "saludo", (const AgalValue *const *)args, 2);
+  check(string_is(greeting, "hola Agal 7"), "llamar a una funcion exportada");
+  agal_value_free(greeting);
+
+  agal_value_free(args[1]);
+  args[1] = agal_value_bool(false);
+  AgalValue *list = agal_vm_call(vm, "saludo", (const AgalValue *const *)args, 2);
+  check(agal_value_type(list) == AGAL_TYPE_LIST && agal_list_len(list) == 3, "retornar una lista");
+  AgalValue *item = agal_list_get(list, 1);
+  check(number_is(item, 1.5), "numero decimal como texto");
+  agal_value_free(item);
+  item = agal_list_get(list, 2);
+  bool flag = true;
+  check(agal_value_as_bool(item, &flag) && !flag, "buleano");
+  agal_value_free(item);
+  agal_value_free(list);
+  agal_value_free(args[0]);
+  agal_value_free(args[1]);
+
+  check(agal_vm_call(vm, "falla", NULL, 0) == NULL, "error de una funcion registrada");
+  const char *error = agal_vm_error(vm);
+  check(error != NULL && strstr(error, "se esperaba una lista") != NULL, "mensaje del error");
+
+  check(agal_vm_call(vm, "no_existe", NULL, 0) == NULL, "funcion que no existe");
+  check(agal_value_number("no es numero") == NULL, "numero invalido");
+
+  check(!agal_vm_eval(vm, "def x = ;", "sintaxis.aga"), "error de sintaxis");
+  check(agal_vm_error(vm) != NULL, "mensaje del error de sintaxis");
+
+  /* Un fallo interno del interprete se retorna como error en lugar de terminar el programa */
+  check(!agal_vm_eval(vm, "1 / 0;", "panico.aga"), "fallo interno");
+  check(agal_vm_error(vm) != NULL, "mensaje del fallo interno");
+
+  agal_vm_free(vm);
+  if (failures == 0) {
+    printf("Todas las pruebas pasaron\n");
+  }
+  return failures == 0 ? 0 : 1;
+}
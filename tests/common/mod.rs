@@ -0,0 +1,21 @@
+use std::process::Command;
+
+/// Ejecuta el programa con el interprete y retorna lo que escribio en la salida y en los errores
+pub fn run(flags: &[&str], name: &str, code: &str) -> (String, String) {
+  let dir = std::env::temp_dir().join(format!("agal_pruebas_{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join(name);
+  std::fs::write(&path, code).unwrap();
+  let output = Command::new(env!("CARGO_BIN_EXE_agalang-core"))
+    .arg("ejecutar")
+    .args(flags)
+    .arg(&path)
+    .output()
+    .unwrap();
+  std::fs::remove_file(&path).ok();
+  assert!(output.status.success(), "{name}");
+  (
+    String::from_utf8_lossy(&output.stdout).to_string(),
+    String::from_utf8_lossy(&output.stderr).to_string(),
+  )
+}
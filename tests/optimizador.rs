@@ -0,0 +1,12 @@
+mod common;
+use common::run;
+
+#[test]
+fn test_folded_expressions_print_the_same() {
+  let code = "importa ':consola' como C;
+C::pinta(1 + 2, 2 - 3, 3 * 4, 6 / 3, 7 / 2, 1 / 3, 10 % 3, 0.1 + 0.2);
+C::pinta(2 ^ 4, 2 ^ 10, 2 ^ 64, 1.5 ^ 2, 0.5 ^ 64, 2 ^ 3 * 2, (1 + 2) * 4);";
+  let (plain, _) = run(&[], "plegado.aga", code);
+  let (optimized, _) = run(&["-optimizar"], "plegado.aga", code);
+  assert_eq!(optimized, plain);
+}
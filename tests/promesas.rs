@@ -0,0 +1,29 @@
+mod common;
+use common::run;
+
+#[test]
+fn test_caught_reaction_error_is_not_printed() {
+  let (stdout, stderr) = run(
+    &[],
+    "captura.aga",
+    "importa ':consola' como C;
+importa ':tmp' como T;
+T::espera(1)
+  ::entonces(fn(v) { def x = nada; x(); })
+  ::captura(fn(e) { C::pinta('capturado'); });",
+  );
+  assert!(stdout.contains("capturado"));
+  assert_eq!(stderr, "");
+}
+
+#[test]
+fn test_unhandled_reaction_error_is_reported() {
+  let (_, stderr) = run(
+    &[],
+    "sin_captura.aga",
+    "importa ':tmp' como T;
+T::espera(1)::entonces(fn(v) { def x = nada; x(); });",
+  );
+  assert!(stderr.contains("promesa rechazada sin manejar"));
+  assert!(stderr.contains("Se esperaba llamar una funcion"));
+}